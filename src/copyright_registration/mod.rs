@@ -1,3 +1,4 @@
+use crate::diagnostics::Diagnostics;
 use crate::gallery::{Item, Photo};
 use crate::output::write_image;
 use crate::util::progress_bar;
@@ -30,8 +31,11 @@ impl Gallery {
         resolution: u32,
         start_instant: Instant,
     ) {
-        // Copyright office doesn't support WebP.
-        if self.config.photo_format == OutputFormat::WebP {
+        // Copyright office doesn't support WebP or AVIF.
+        if matches!(
+            self.config.photo_format,
+            OutputFormat::WebP | OutputFormat::Avif
+        ) {
             self.config.photo_format = OutputFormat::Png;
         }
 
@@ -96,21 +100,33 @@ impl Gallery {
             manifest.drain(0..index);
         }
         if manifest.len() > limit {
-            println!("WARNING: truncated to {limit} photos; next is \"{}\"", manifest[limit].title);
+            println!(
+                "WARNING: truncated to {limit} photos; next is \"{}\"",
+                manifest[limit].title
+            );
             manifest.truncate(limit);
         }
 
         // Encode the images in parallel.
         let progress = progress_bar("Encoding images...", manifest.len(), start_instant);
+        let diagnostics = Diagnostics::default();
         manifest.par_iter_mut().for_each(|submission| {
-            submission.image_bytes = Some(write_image(
-                &submission.photo.custom_preview(&self.config, resolution),
-                &submission.filename,
-                Some((&self.config, submission.photo)),
-            ));
+            if let Some(image) = self.photo_image_or_report(submission.photo, &diagnostics) {
+                submission.image_bytes = Some(write_image(
+                    &submission.photo.preview_of(&image, resolution),
+                    &submission.filename,
+                    &self.config.photo_encoding,
+                    Some((&self.config, submission.photo)),
+                ));
+            }
             progress.inc(1);
         });
         progress.finish_and_clear();
+        // The registration must include every photo.
+        if !diagnostics.is_empty() {
+            diagnostics.report();
+            std::process::exit(1);
+        }
 
         let progress = progress_bar("Writing archive...", manifest.len(), start_instant);
         let mut written = 0usize;
@@ -0,0 +1,262 @@
+use serde::de::DeserializeOwned;
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl Display for Severity {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Error => "error",
+            Self::Warning => "warning",
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// Source file (or directory) the problem was found in.
+    pub path: PathBuf,
+    /// 1-based line and column, if known.
+    pub location: Option<(usize, usize)>,
+    pub message: String,
+}
+
+/// Collects problems found while loading the gallery, so that one
+/// bad file doesn't prevent the rest from being checked or built.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Mutex<Vec<Diagnostic>>,
+}
+
+impl Diagnostics {
+    pub fn push(&self, diagnostic: Diagnostic) {
+        self.diagnostics.lock().unwrap().push(diagnostic);
+    }
+
+    pub fn error(&self, path: impl AsRef<Path>, message: impl Display) {
+        self.push(Diagnostic {
+            severity: Severity::Error,
+            path: path.as_ref().to_owned(),
+            location: None,
+            message: message.to_string(),
+        });
+    }
+
+    /// Like `error`, unless the same error was already reported, as when
+    /// several outputs fail for the same reason.
+    pub fn error_once(&self, path: impl AsRef<Path>, message: impl Display) {
+        let path = path.as_ref();
+        let message = message.to_string();
+        let mut diagnostics = self.diagnostics.lock().unwrap();
+        if !diagnostics.iter().any(|diagnostic| {
+            diagnostic.severity == Severity::Error
+                && diagnostic.path == path
+                && diagnostic.message == message
+        }) {
+            diagnostics.push(Diagnostic {
+                severity: Severity::Error,
+                path: path.to_owned(),
+                location: None,
+                message,
+            });
+        }
+    }
+
+    pub fn warning(&self, path: impl AsRef<Path>, message: impl Display) {
+        self.push(Diagnostic {
+            severity: Severity::Warning,
+            path: path.as_ref().to_owned(),
+            location: None,
+            message: message.to_string(),
+        });
+    }
+
+    /// Report an error in a text file, pointing at `span` if it's known.
+    pub fn error_at(
+        &self,
+        path: impl AsRef<Path>,
+        text: &str,
+        span: Option<Range<usize>>,
+        message: impl Display,
+    ) {
+        self.push(Diagnostic {
+            severity: Severity::Error,
+            path: path.as_ref().to_owned(),
+            location: span.map(|span| line_column(text, span)),
+            message: message.to_string(),
+        });
+    }
+
+    /// Report an error about the value of `key` in a TOML file, pointing
+    /// at the value if it can be found.
+    pub fn toml_key_error(
+        &self,
+        path: impl AsRef<Path>,
+        text: &str,
+        key: &str,
+        message: impl Display,
+    ) {
+        self.toml_key(Severity::Error, path.as_ref(), text, key, message);
+    }
+
+    pub fn toml_key_warning(
+        &self,
+        path: impl AsRef<Path>,
+        text: &str,
+        key: &str,
+        message: impl Display,
+    ) {
+        self.toml_key(Severity::Warning, path.as_ref(), text, key, message);
+    }
+
+    fn toml_key(
+        &self,
+        severity: Severity,
+        path: &Path,
+        text: &str,
+        key: &str,
+        message: impl Display,
+    ) {
+        let span = toml_edit::ImDocument::parse(text)
+            .ok()
+            .and_then(|doc| doc.get(key).and_then(|item| item.span()));
+        self.push(Diagnostic {
+            severity,
+            path: path.to_owned(),
+            location: span.map(|span| line_column(text, span)),
+            message: message.to_string(),
+        });
+    }
+
+    /// Parse a TOML file, reporting an error and returning `None` on failure.
+    ///
+    /// Unknown keys, which are usually typos, are reported as warnings.
+    pub fn parse_toml<T: DeserializeOwned>(&self, path: impl AsRef<Path>, text: &str) -> Option<T> {
+        let path = path.as_ref();
+        let mut unknown = Vec::<String>::new();
+        match serde_ignored::deserialize(toml::Deserializer::new(text), |key| {
+            unknown.push(key.to_string())
+        }) {
+            Ok(value) => {
+                for key in unknown {
+                    self.toml_key_warning(path, text, &key, format!("unknown key `{key}`"));
+                }
+                Some(value)
+            }
+            Err(e) => {
+                let e: toml::de::Error = e;
+                self.push(Diagnostic {
+                    severity: Severity::Error,
+                    path: path.to_owned(),
+                    location: e.span().map(|span| line_column(text, span)),
+                    message: e.message().to_owned(),
+                });
+                None
+            }
+        }
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(Severity::Error)
+    }
+
+    fn count(&self, severity: Severity) -> usize {
+        self.diagnostics
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|d| d.severity == severity)
+            .count()
+    }
+
+    /// Leaves this empty.
+    pub fn take(&self) -> Self {
+        Self {
+            diagnostics: Mutex::new(std::mem::take(&mut self.diagnostics.lock().unwrap())),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.lock().unwrap().is_empty()
+    }
+
+    /// Print all diagnostics, grouped by file.
+    pub fn report(&self) {
+        let diagnostics = self.diagnostics.lock().unwrap();
+        let mut by_path = BTreeMap::<&Path, Vec<&Diagnostic>>::new();
+        for diagnostic in diagnostics.iter() {
+            by_path
+                .entry(&diagnostic.path)
+                .or_default()
+                .push(diagnostic);
+        }
+
+        for (path, diagnostics) in by_path.iter_mut() {
+            diagnostics.sort_by_key(|d| (d.location, d.severity));
+            println!("{}", path.display());
+            for diagnostic in diagnostics.iter() {
+                if let Some((line, column)) = diagnostic.location {
+                    println!(
+                        "  {}[{line}:{column}]: {}",
+                        diagnostic.severity, diagnostic.message
+                    );
+                } else {
+                    println!("  {}: {}", diagnostic.severity, diagnostic.message);
+                }
+            }
+        }
+
+        let errors = diagnostics
+            .iter()
+            .filter(|d| d.severity == Severity::Error)
+            .count();
+        let warnings = diagnostics.len() - errors;
+        println!(
+            "{errors} error{}, {warnings} warning{} in {} file{}",
+            plural(errors),
+            plural(warnings),
+            by_path.len(),
+            plural(by_path.len())
+        );
+    }
+}
+
+fn plural(n: usize) -> &'static str {
+    if n == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+/// Whether `text` parses as `T` without any unknown keys.
+pub fn fits_toml<T: DeserializeOwned>(text: &str) -> bool {
+    let mut unknown = false;
+    serde_ignored::deserialize::<_, _, T>(toml::Deserializer::new(text), |_| unknown = true).is_ok()
+        && !unknown
+}
+
+/// 1-based line and column of the start of `span`.
+fn line_column(text: &str, span: Range<usize>) -> (usize, usize) {
+    let before = &text[..span.start.min(text.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rsplit_once('\n')
+        .map(|(_, line)| line)
+        .unwrap_or(before)
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
@@ -0,0 +1,146 @@
+use crate::gallery::{parse_date, tag_slug, AlbumFilter, Category, CategoryPath, Gallery, Photo};
+use chrono::NaiveDate;
+
+impl AlbumFilter {
+    /// Parses `from` and `to`.
+    pub fn date_range(
+        &self,
+        date_format: &str,
+    ) -> Result<(Option<NaiveDate>, Option<NaiveDate>), String> {
+        let parse = |date: &Option<String>| {
+            date.as_ref()
+                .map(|date| parse_date(date, date_format))
+                .transpose()
+        };
+        Ok((parse(&self.from)?, parse(&self.to)?))
+    }
+
+    pub fn matches(
+        &self,
+        photo: &Photo,
+        (from, to): (Option<NaiveDate>, Option<NaiveDate>),
+    ) -> bool {
+        fn contains(haystack: &[Option<&String>], needle: &Option<String>) -> bool {
+            needle.as_ref().is_none_or(|needle| {
+                let haystack = haystack
+                    .iter()
+                    .flatten()
+                    .map(|s| s.trim_matches('"'))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                haystack.to_lowercase().contains(&needle.to_lowercase())
+            })
+        }
+
+        let date = photo.date_time().map(|date_time| date_time.date());
+        let exif = photo.exif();
+        from.is_none_or(|from| date.is_some_and(|date| date >= from))
+            && to.is_none_or(|to| date.is_some_and(|date| date <= to))
+            && contains(
+                &[exif.camera_make.as_ref(), exif.camera_model.as_ref()],
+                &self.camera,
+            )
+            && contains(
+                &[exif.lens_make.as_ref(), exif.lens_model.as_ref()],
+                &self.lens,
+            )
+            && contains(&[photo.config.location.as_ref()], &self.location)
+            && self.tag.as_ref().is_none_or(|tag| {
+                let tag = tag_slug(tag);
+                photo.tags.iter().any(|t| tag_slug(t) == tag)
+            })
+            && self
+                .min_rating
+                .is_none_or(|min_rating| photo.config.stars() >= min_rating)
+    }
+}
+
+impl Gallery {
+    /// The photos of a smart album, skipping any that no longer exist.
+    pub fn album_photos(&self, album: &[CategoryPath]) -> Vec<(CategoryPath, &Photo)> {
+        album
+            .iter()
+            .filter_map(|path| Some((path.pop()?, self.photo(path)?)))
+            .collect()
+    }
+
+    /// Like [`Category::thumbnail`], but also works for smart albums.
+    pub fn category_thumbnail<'a>(
+        &'a self,
+        path: &CategoryPath,
+        category: &'a Category,
+    ) -> Option<(CategoryPath, &'a Photo)> {
+        let Some(album) = &category.album else {
+            return category.thumbnail(path);
+        };
+        let photos = self.album_photos(album);
+        let thumbnail = category.config.thumbnail.as_ref();
+        photos
+            .iter()
+            .find(|(_, photo)| Some(&photo.name) == thumbnail)
+            .or(photos.first())
+            .cloned()
+    }
+
+    /// Like [`Category::first_and_last_dates`], but also works for smart albums.
+    pub fn category_dates(&self, category: &Category) -> Option<(NaiveDate, NaiveDate)> {
+        let Some(album) = &category.album else {
+            return category.first_and_last_dates();
+        };
+        let dates = self
+            .album_photos(album)
+            .iter()
+            .filter_map(|(_, photo)| photo.date_time())
+            .map(|date_time| date_time.date())
+            .collect::<Vec<_>>();
+        dates.iter().min().copied().zip(dates.iter().max().copied())
+    }
+
+    /// Fill in the contents of smart albums, newest first.
+    pub fn resolve_albums(&mut self) {
+        let date_format = self.config.date_format.clone();
+        let mut photos = Vec::<(CategoryPath, &Photo)>::new();
+        let mut albums = Vec::<(CategoryPath, &AlbumFilter)>::new();
+        self.visit_items(|path, item| {
+            if let Some(photo) = item.photo() {
+                photos.push((path.push(photo.slug()), photo));
+            } else if let Some(category) = item.category() {
+                if let (Some(_), Some(filter)) = (&category.album, &category.config.filter) {
+                    albums.push((path.push(category.slug()), filter));
+                }
+            }
+        });
+        photos.sort_by(|(_, a), (_, b)| {
+            b.date_time()
+                .cmp(&a.date_time())
+                .then_with(|| a.name.cmp(&b.name))
+        });
+
+        let resolved = albums
+            .into_iter()
+            .map(|(album_path, filter)| {
+                // Invalid dates were already reported.
+                let contents = filter
+                    .date_range(&date_format)
+                    .map(|range| {
+                        photos
+                            .iter()
+                            .filter(|(_, photo)| filter.matches(photo, range))
+                            .map(|(path, _)| path.clone())
+                            .collect::<Vec<_>>()
+                    })
+                    .unwrap_or_default();
+                (album_path, contents)
+            })
+            .collect::<Vec<_>>();
+
+        self.visit_items_mut(|path, item| {
+            if let Some(category) = item.category_mut() {
+                let category_path = path.push(category.slug());
+                if let Some((_, contents)) = resolved.iter().find(|(p, _)| *p == category_path) {
+                    category.album = Some(contents.clone());
+                }
+            }
+        });
+    }
+}
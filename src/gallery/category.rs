@@ -10,6 +10,9 @@ pub struct Category {
     pub config: CategoryConfig,
     /// foo/bar baz/Quxx
     pub src_key: String,
+    /// For a smart album, which has no children, the paths of the
+    /// photos matching `config.filter`.
+    pub album: Option<Vec<CategoryPath>>,
 }
 
 impl Category {
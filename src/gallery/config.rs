@@ -1,11 +1,13 @@
 use crate::{
-    gallery::{CategoryPath, Gallery},
-    output::OutputFormat,
-    util::add_trailing_slash_if_nonempty,
+    diagnostics::Diagnostics,
+    gallery::{CategoryPath, DecodeCache, Gallery},
+    output::{DeployTarget, EncodeOptions, OutputFormat},
+    util::{add_trailing_slash_if_nonempty, available_memory},
 };
-use chrono::NaiveDate;
+use chrono::{DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::BTreeMap,
     fs,
     io::{Read, Seek, SeekFrom, Write},
     path::{Path, PathBuf},
@@ -18,8 +20,20 @@ pub struct GalleryConfig {
     pub input: String,
     #[serde(default = "default_output")]
     pub output: String,
+    /// Encoded images, reused by `build` and `serve`.
+    #[serde(default = "default_cache")]
+    pub cache: String,
+    /// 0 disables the cache.
+    #[serde(default = "default_cache_size_mb")]
+    pub cache_size_mb: u64,
     #[serde(default = "default_title")]
     pub title: String,
+    /// BCP 47 language tag of the gallery's text, like `en-US`.
+    #[serde(default = "default_language")]
+    pub language: String,
+    /// Of photo times from UTC, like `+02:00`, where photos don't say.
+    /// Defaults to the local time zone.
+    pub utc_offset: Option<String>,
     pub author: Option<String>,
     pub root_url: Option<String>,
     pub author_url: Option<String>,
@@ -34,14 +48,35 @@ pub struct GalleryConfig {
     pub photo_format: OutputFormat,
     #[serde(default = "default_photo_resolution")]
     pub photo_resolution: u32,
+    #[serde(default, skip_serializing_if = "EncodeOptions::is_default")]
+    pub photo_encoding: EncodeOptions,
     #[serde(default = "default_preview_format")]
     pub preview_format: OutputFormat,
     #[serde(default = "default_preview_resolution")]
     pub preview_resolution: u32,
+    #[serde(default, skip_serializing_if = "EncodeOptions::is_default")]
+    pub preview_encoding: EncodeOptions,
+    /// Additional preview resolutions, for `srcset`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preview_resolutions: Vec<u32>,
+    /// Additional preview formats, for `<picture>`, in order of preference.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub preview_alternate_formats: Vec<OutputFormat>,
     #[serde(default = "default_thumbnail_format")]
     pub thumbnail_format: OutputFormat,
     #[serde(default = "default_thumbnail_resolution")]
     pub thumbnail_resolution: u32,
+    #[serde(default, skip_serializing_if = "EncodeOptions::is_default")]
+    pub thumbnail_encoding: EncodeOptions,
+    /// Additional thumbnail resolutions, for `srcset`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thumbnail_resolutions: Vec<u32>,
+    /// Additional thumbnail formats, for `<picture>`, in order of preference.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub thumbnail_alternate_formats: Vec<OutputFormat>,
+    /// How to decode camera RAW files.
+    #[serde(default)]
+    pub raw: RawMode,
     #[serde(default = "default_image_ai_api_base_url")]
     pub image_ai_api_base_url: String,
     pub image_ai_api_key: Option<String>,
@@ -59,13 +94,138 @@ pub struct GalleryConfig {
     #[serde(default = "default_date_format")]
     pub date_format: String,
     pub text_editor: Option<String>,
+    /// Address `serve` listens on, which is only reachable from this
+    /// device by default.
+    #[serde(default = "default_serve_host")]
+    pub serve_host: String,
+    #[serde(default = "default_serve_port")]
+    pub serve_port: u16,
+    /// Whether `serve` uses HTTPS, e.g. for PWA features on other devices.
+    #[serde(default)]
+    pub serve_https: bool,
+    /// PEM certificate chain for `serve_https`, like one from `mkcert`.
+    /// Defaults to a generated, self-signed certificate.
+    pub serve_https_cert: Option<String>,
+    /// PEM private key of `serve_https_cert`.
+    pub serve_https_key: Option<String>,
+    /// Overridden by the gallery's `theme` directory, if any.
+    #[serde(default)]
+    pub theme: BuiltInTheme,
+    /// Photos rated lower than this (unrated counts as 0) are left out
+    /// of the built gallery, like drafts.
+    #[serde(default)]
+    pub min_rating: u8,
+    /// Limits memory used to decode photos, in megabytes. Defaults to
+    /// half of the available memory.
+    pub memory_budget_mb: Option<usize>,
+    /// Whether `build` writes gzip and brotli siblings of text files, like
+    /// `index.html.gz`, and `serve` compresses them too.
+    #[serde(default)]
+    pub precompress: bool,
+    /// Text files smaller than this, in bytes, aren't compressed.
+    #[serde(default = "default_precompress_min_size")]
+    pub precompress_min_size: u64,
+    /// Where `deploy` uploads the output, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub deploy: BTreeMap<String, DeployTarget>,
+    /// Smart albums at the top level of the gallery, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub albums: BTreeMap<String, CategoryConfig>,
+}
+
+fn variants(
+    resolution: u32,
+    resolutions: &[u32],
+    format: OutputFormat,
+    formats: &[OutputFormat],
+) -> Vec<(u32, OutputFormat)> {
+    let mut ret = Vec::new();
+    for &format in std::iter::once(&format).chain(formats) {
+        for &resolution in std::iter::once(&resolution).chain(resolutions) {
+            if !ret.contains(&(resolution, format)) {
+                ret.push((resolution, format));
+            }
+        }
+    }
+    ret
 }
 
 fn default_date_format() -> String {
     "%b %-d, %Y".to_owned()
 }
 
+/// Parses a date from a config, in the `date_format` of the gallery configuration.
+pub fn parse_date(date: &str, date_format: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(date, date_format)
+        .map_err(|e| format!("date {date:?} doesn't match date_format {date_format:?}: {e}"))
+}
+
 impl GalleryConfig {
+    pub const PATH: &'static str = "./chillphoto.toml";
+
+    /// Reports problems to `diagnostics`.
+    pub fn load(diagnostics: &Diagnostics) -> Option<Self> {
+        match fs::read_to_string(Self::PATH) {
+            Ok(config_text) => {
+                let config = diagnostics.parse_toml::<Self>(Self::PATH, &config_text)?;
+                if let Some(utc_offset) = &config.utc_offset {
+                    if utc_offset.parse::<FixedOffset>().is_err() {
+                        diagnostics.toml_key_error(
+                            Self::PATH,
+                            &config_text,
+                            "utc_offset",
+                            format!("invalid UTC offset {utc_offset:?} (expected e.g. \"+02:00\")"),
+                        );
+                        return None;
+                    }
+                }
+                Some(config)
+            }
+            Err(e) => {
+                diagnostics.error(Self::PATH, format!("couldn't read: {e}"));
+                None
+            }
+        }
+    }
+
+    /// The time zone of `date_time`, where a photo doesn't say.
+    pub fn date_time_with_offset(
+        &self,
+        date_time: NaiveDateTime,
+        offset: Option<FixedOffset>,
+    ) -> DateTime<FixedOffset> {
+        let offset = offset
+            .or_else(|| self.utc_offset.as_ref()?.parse().ok())
+            .or_else(|| {
+                Some(
+                    Local
+                        .offset_from_local_datetime(&date_time)
+                        .earliest()?
+                        .fix(),
+                )
+            })
+            .unwrap_or_else(|| FixedOffset::east_opt(0).unwrap());
+        // Offsets never make local times ambiguous.
+        date_time.and_local_timezone(offset).unwrap()
+    }
+
+    /// `input`, with `~/` expanded.
+    pub fn input_glob(&self) -> String {
+        if let Some(remainder) = self.input.strip_prefix("~/") {
+            #[allow(deprecated)]
+            let home_dir = std::env::home_dir();
+            format!(
+                "{}/{remainder}",
+                home_dir
+                    .unwrap()
+                    .to_str()
+                    .expect("invalid utf-8 in os-string")
+            )
+        } else {
+            self.input.clone()
+        }
+    }
+
     pub fn format_date(&self, date: NaiveDate) -> String {
         date.format(&self.date_format).to_string()
     }
@@ -79,12 +239,43 @@ pub enum PaginationFlavor {
     Query,
 }
 
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BuiltInTheme {
+    #[default]
+    ZenPage,
+    Dark,
+}
+
+#[derive(Default, Debug, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RawMode {
+    /// Use the largest JPEG preview embedded by the camera.
+    #[default]
+    Preview,
+    /// Demosaic the sensor data, if it's uncompressed (e.g. in most DNG
+    /// files), and otherwise use the preview.
+    Develop,
+}
+
 fn default_items_per_page() -> usize {
     30
 }
 
 fn default_input() -> String {
-    String::from("**/*.{png,PNG,jpg,JPG,jpeg,JPEG,txt,md,html,toml}")
+    // Only photos this build can decode, as others would be errors.
+    let heif = if cfg!(feature = "heif") {
+        "heic,HEIC,heif,HEIF,hif,HIF,avif,AVIF,"
+    } else {
+        ""
+    };
+    format!(
+        "**/*.{{png,PNG,jpg,JPG,jpeg,JPEG,dng,DNG,cr2,CR2,cr3,CR3,nef,NEF,arw,ARW,{heif}txt,md,html,toml}}"
+    )
+}
+
+fn default_language() -> String {
+    "en".to_owned()
 }
 
 fn default_title() -> String {
@@ -123,6 +314,26 @@ fn default_output() -> String {
     String::from("./output")
 }
 
+fn default_serve_host() -> String {
+    String::from("127.0.0.1")
+}
+
+fn default_serve_port() -> u16 {
+    8080
+}
+
+fn default_cache() -> String {
+    String::from("./.chillphoto-cache")
+}
+
+fn default_precompress_min_size() -> u64 {
+    1024
+}
+
+fn default_cache_size_mb() -> u64 {
+    1_000
+}
+
 fn default_image_ai_api_base_url() -> String {
     async_openai::config::OPENAI_API_BASE.to_owned()
 }
@@ -136,6 +347,39 @@ fn default_ai_description_system_prompt() -> String {
 }
 
 impl GalleryConfig {
+    /// Everything (besides the photo) that encoded images depend on, so
+    /// that changing anything else, like where to deploy, keeps them cached.
+    pub fn image_hash_input(&self) -> String {
+        format!(
+            "{:?}",
+            (
+                (self.photo_format, self.photo_resolution, &self.photo_encoding),
+                (
+                    self.preview_format,
+                    self.preview_resolution,
+                    &self.preview_encoding,
+                    &self.preview_resolutions,
+                    &self.preview_alternate_formats,
+                ),
+                (
+                    self.thumbnail_format,
+                    self.thumbnail_resolution,
+                    &self.thumbnail_encoding,
+                    &self.thumbnail_resolutions,
+                    &self.thumbnail_alternate_formats,
+                ),
+                self.raw,
+                // Embedded as XMP.
+                (
+                    &self.author,
+                    &self.root_url,
+                    &self.license_url,
+                    self.disallow_ai_training,
+                ),
+            )
+        )
+    }
+
     pub fn subdirectory(&self, subdirectory: &str) -> String {
         Path::new(&self.output)
             .join(Path::new(subdirectory))
@@ -180,19 +424,82 @@ impl GalleryConfig {
         }
     }
 
+    /// In bytes.
+    pub fn memory_budget(&self) -> usize {
+        self.memory_budget_mb
+            .map(|megabytes| megabytes << 20)
+            .or_else(|| available_memory().map(|available| available / 2))
+            .unwrap_or(DecodeCache::DEFAULT_MEMORY_BUDGET)
+    }
+
     pub fn preview<const PUBLIC: bool>(&self, category: &CategoryPath, name: &str) -> String {
+        self.preview_variant::<PUBLIC>(category, name, self.preview_resolution, self.preview_format)
+    }
+
+    pub fn preview_variant<const PUBLIC: bool>(
+        &self,
+        category: &CategoryPath,
+        name: &str,
+        resolution: u32,
+        format: OutputFormat,
+    ) -> String {
+        let variation = if resolution == self.preview_resolution {
+            "-preview".to_owned()
+        } else {
+            format!("-preview-{resolution}")
+        };
         format!(
-            "{}.{}",
-            self.variation::<PUBLIC>(category, name, "-preview"),
-            self.preview_format
+            "{}.{format}",
+            self.variation::<PUBLIC>(category, name, &variation)
+        )
+    }
+
+    /// Every resolution and format of previews, starting with
+    /// `preview_resolution` and `preview_format`.
+    pub fn preview_variants(&self) -> Vec<(u32, OutputFormat)> {
+        variants(
+            self.preview_resolution,
+            &self.preview_resolutions,
+            self.preview_format,
+            &self.preview_alternate_formats,
         )
     }
 
     pub fn thumbnail<const PUBLIC: bool>(&self, category: &CategoryPath, name: &str) -> String {
+        self.thumbnail_variant::<PUBLIC>(
+            category,
+            name,
+            self.thumbnail_resolution,
+            self.thumbnail_format,
+        )
+    }
+
+    pub fn thumbnail_variant<const PUBLIC: bool>(
+        &self,
+        category: &CategoryPath,
+        name: &str,
+        resolution: u32,
+        format: OutputFormat,
+    ) -> String {
+        let variation = if resolution == self.thumbnail_resolution {
+            "-thumbnail".to_owned()
+        } else {
+            format!("-thumbnail-{resolution}")
+        };
         format!(
-            "{}.{}",
-            self.variation::<PUBLIC>(category, name, "-thumbnail"),
-            self.thumbnail_format
+            "{}.{format}",
+            self.variation::<PUBLIC>(category, name, &variation)
+        )
+    }
+
+    /// Every resolution and format of thumbnails, starting with
+    /// `thumbnail_resolution` and `thumbnail_format`.
+    pub fn thumbnail_variants(&self) -> Vec<(u32, OutputFormat)> {
+        variants(
+            self.thumbnail_resolution,
+            &self.thumbnail_resolutions,
+            self.thumbnail_format,
+            &self.thumbnail_alternate_formats,
         )
     }
 
@@ -227,6 +534,14 @@ impl GalleryConfig {
         )
     }
 
+    pub fn feed_xml<const PUBLIC: bool>(&self, category: &CategoryPath) -> String {
+        self.variation::<PUBLIC>(category, "feed.xml", "")
+    }
+
+    pub fn feed_json<const PUBLIC: bool>(&self, category: &CategoryPath) -> String {
+        self.variation::<PUBLIC>(category, "feed.json", "")
+    }
+
     pub fn page_html<const PUBLIC: bool>(&self, category: &CategoryPath, name: &str) -> String {
         let base = format!("{}/", self.variation::<PUBLIC>(category, name, ""));
         if PUBLIC {
@@ -240,6 +555,36 @@ impl GalleryConfig {
         self.page_html::<PUBLIC>(&CategoryPath::ROOT, "search")
     }
 
+    pub fn not_found_html<const PUBLIC: bool>(&self) -> String {
+        self.variation::<PUBLIC>(&CategoryPath::ROOT, "404.html", "")
+    }
+
+    /// The whole archive, a year, or a month of a year.
+    pub fn archive_html<const PUBLIC: bool>(
+        &self,
+        year: Option<i32>,
+        month: Option<u32>,
+        page: usize,
+    ) -> String {
+        let mut base = "/archive".to_owned();
+        if let Some(year) = year {
+            base.push_str(&format!("/{year}"));
+            if let Some(month) = month {
+                base.push_str(&format!("/{month:02}"));
+            }
+        }
+        format!("{base}{}", self.index_html::<PUBLIC>(page))
+    }
+
+    /// All tags, or the photos with a tag.
+    pub fn tags_html<const PUBLIC: bool>(&self, tag: Option<&str>, page: usize) -> String {
+        let mut base = "/tags".to_owned();
+        if let Some(tag) = tag {
+            base.push_str(&format!("/{tag}"));
+        }
+        format!("{base}{}", self.index_html::<PUBLIC>(page))
+    }
+
     pub fn index_html<const PUBLIC: bool>(&self, page: usize) -> String {
         let base = if page == 0 || matches!(self.pagination_flavor, PaginationFlavor::Query) {
             "/".to_owned()
@@ -268,6 +613,17 @@ pub struct PhotoConfig {
     pub description: Option<String>,
     #[serde(default)]
     pub location: Option<String>,
+    /// Keywords, in addition to those of its categories.
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Stars, from 0 to 5, or -1 to reject the photo, as in XMP.
+    pub rating: Option<i8>,
+    /// Left out of the built gallery, but shown by `serve`.
+    #[serde(default)]
+    pub draft: bool,
+    /// Like `draft`, but usually set by culling in another tool.
+    #[serde(default)]
+    pub rejected: bool,
     #[serde(default)]
     pub ai_description_hint: Option<String>,
     #[serde(default)]
@@ -287,6 +643,8 @@ pub struct PhotoConfig {
     /// Specify/override the date the photo was taken,
     /// in the `date_format` of the gallery configuration.
     pub date: Option<String>,
+    /// Left out until this date, in the `date_format` of the gallery configuration.
+    pub publish_at: Option<String>,
 }
 
 fn default_thumbnail_crop_factor() -> f64 {
@@ -304,6 +662,13 @@ impl Default for PhotoConfig {
 }
 
 impl PhotoConfig {
+    /// `rating`, where unrated or rejected counts as 0.
+    pub fn stars(&self) -> u8 {
+        self.rating
+            .and_then(|rating| u8::try_from(rating).ok())
+            .unwrap_or(0)
+    }
+
     pub fn path(gallery: &Gallery, path: &CategoryPath) -> Option<PathBuf> {
         let photo = gallery.photo(path)?;
         let mut config_path = gallery.root.clone();
@@ -342,17 +707,26 @@ pub struct Point2 {
     pub y: f64,
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct CategoryConfig {
     pub slug: Option<String>,
     #[serde(default)]
     pub order: i64,
     pub thumbnail: Option<String>,
     pub description: Option<String>,
+    /// Tags of every photo in the category, including subcategories.
+    #[serde(default)]
+    pub tags: Vec<String>,
     #[serde(default)]
     pub ai_description_hint: Option<String>,
     #[serde(default = "default_items_per_page")]
     pub items_per_page: usize,
+    /// Makes the category a smart album, containing the matching
+    /// photos from anywhere in the gallery instead of its own.
+    pub filter: Option<AlbumFilter>,
+    /// Left out, along with its contents, until this date, in the
+    /// `date_format` of the gallery configuration.
+    pub publish_at: Option<String>,
 }
 
 impl Default for CategoryConfig {
@@ -361,6 +735,23 @@ impl Default for CategoryConfig {
     }
 }
 
+/// Photos must match every specified field.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AlbumFilter {
+    /// Earliest date taken, in the `date_format` of the gallery configuration.
+    pub from: Option<String>,
+    /// Latest date taken, in the `date_format` of the gallery configuration.
+    pub to: Option<String>,
+    /// Part of the camera make or model, ignoring case.
+    pub camera: Option<String>,
+    /// Part of the lens make or model, ignoring case.
+    pub lens: Option<String>,
+    /// Part of the location, ignoring case.
+    pub location: Option<String>,
+    pub tag: Option<String>,
+    pub min_rating: Option<u8>,
+}
+
 #[derive(Deserialize, Debug)]
 pub struct PageConfig {
     pub slug: Option<String>,
@@ -369,6 +760,8 @@ pub struct PageConfig {
     pub description: Option<String>,
     #[serde(default)]
     pub unlisted: bool,
+    /// Left out until this date, in the `date_format` of the gallery configuration.
+    pub publish_at: Option<String>,
 }
 
 impl Default for PageConfig {
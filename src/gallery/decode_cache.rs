@@ -0,0 +1,298 @@
+use image::RgbImage;
+use std::{
+    cell::Cell,
+    fmt::Debug,
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex},
+};
+
+thread_local! {
+    /// How many decodes are in progress further up this thread's stack,
+    /// like when decoding in parallel lets it run another photo's job.
+    static DECODING: Cell<usize> = const { Cell::new(0) };
+}
+
+#[derive(Default)]
+struct Slot {
+    state: Mutex<SlotState>,
+    decoded: Condvar,
+}
+
+#[derive(Default)]
+enum SlotState {
+    #[default]
+    Empty,
+    Decoding,
+    Decoded(Arc<RgbImage>),
+}
+
+impl Slot {
+    fn image(&self) -> Option<Arc<RgbImage>> {
+        match &*self.state.lock().unwrap() {
+            SlotState::Decoded(image) => Some(Arc::clone(image)),
+            _ => None,
+        }
+    }
+}
+
+/// Recently decoded photos, so that the photo, previews, and thumbnails
+/// derived from each needn't decode it again. The least recently used are
+/// evicted to stay within a quarter of the memory budget, and the rest
+/// limits how many photos are decoded at the same time.
+pub struct DecodeCache {
+    /// In bytes.
+    budget: usize,
+    /// Least recently used first.
+    entries: Mutex<Vec<(PathBuf, Arc<Slot>)>>,
+    decoding: MemoryBudget,
+}
+
+impl DecodeCache {
+    /// If the memory budget isn't configured or can't be derived.
+    pub const DEFAULT_MEMORY_BUDGET: usize = 4 << 30;
+
+    /// `memory_budget` is in bytes.
+    pub fn new(memory_budget: usize) -> Self {
+        Self {
+            budget: memory_budget / 4,
+            entries: Default::default(),
+            decoding: MemoryBudget::new(memory_budget - memory_budget / 4),
+        }
+    }
+
+    /// If multiple threads want the same photo, only one decodes it. Waits
+    /// until `memory` bytes, the most that decoding will use, are available.
+    ///
+    /// Never waits while this thread is already decoding, as what it waits
+    /// for could be stuck behind that. Failures aren't kept, so others
+    /// waiting for the photo try to decode it themselves.
+    pub fn get_or_decode<E>(
+        &self,
+        path: &Path,
+        memory: usize,
+        decode: impl FnOnce() -> Result<RgbImage, E>,
+    ) -> Result<Arc<RgbImage>, E> {
+        let slot = {
+            let mut entries = self.entries.lock().unwrap();
+            let slot = match entries.iter().position(|(p, _)| p == path) {
+                Some(index) => entries.remove(index).1,
+                None => Default::default(),
+            };
+            entries.push((path.to_owned(), Arc::clone(&slot)));
+            slot
+        };
+        let nested = DECODING.get() > 0;
+        let owner = {
+            let mut state = slot.state.lock().unwrap();
+            loop {
+                match &*state {
+                    SlotState::Decoded(image) => {
+                        let image = Arc::clone(image);
+                        drop(state);
+                        self.evict();
+                        return Ok(image);
+                    }
+                    SlotState::Decoding if !nested => {
+                        state = slot.decoded.wait(state).unwrap();
+                    }
+                    // Decode it again, without keeping it.
+                    SlotState::Decoding => break None,
+                    SlotState::Empty => {
+                        *state = SlotState::Decoding;
+                        break Some(&*slot);
+                    }
+                }
+            }
+        };
+
+        let guard = DecodingGuard::new(owner);
+        let image = {
+            let _reservation = self.decoding.reserve(memory, !nested);
+            Arc::new(decode()?)
+        };
+        guard.finish(&image);
+        self.evict();
+        Ok(image)
+    }
+
+    /// Forgets photos for which `keep` returns false, like those whose
+    /// source files changed.
+    pub fn retain(&self, mut keep: impl FnMut(&Path) -> bool) {
+        self.entries.lock().unwrap().retain(|(path, _)| keep(path));
+    }
+
+    fn evict(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        let size = |slot: &Slot| slot.image().map_or(0, |image| image.len());
+        let mut total = entries.iter().map(|(_, slot)| size(slot)).sum::<usize>();
+        // Keep the most recently used, even if it alone exceeds the budget,
+        // and those being decoded, which others may be waiting for.
+        while total > self.budget {
+            let Some(index) = entries[..entries.len().saturating_sub(1)]
+                .iter()
+                .position(|(_, slot)| slot.image().is_some())
+            else {
+                break;
+            };
+            let (_, slot) = entries.remove(index);
+            total -= size(&slot);
+        }
+    }
+}
+
+/// Marks this thread as decoding, and lets others waiting for the slot it
+/// owns, if any, decode it themselves if decoding fails or panics.
+struct DecodingGuard<'a> {
+    owner: Option<&'a Slot>,
+}
+
+impl<'a> DecodingGuard<'a> {
+    fn new(owner: Option<&'a Slot>) -> Self {
+        DECODING.set(DECODING.get() + 1);
+        Self { owner }
+    }
+
+    fn finish(mut self, image: &Arc<RgbImage>) {
+        if let Some(slot) = self.owner.take() {
+            *slot.state.lock().unwrap() = SlotState::Decoded(Arc::clone(image));
+            slot.decoded.notify_all();
+        }
+    }
+}
+
+impl Drop for DecodingGuard<'_> {
+    fn drop(&mut self) {
+        DECODING.set(DECODING.get() - 1);
+        if let Some(slot) = self.owner {
+            *slot.state.lock().unwrap() = SlotState::Empty;
+            slot.decoded.notify_all();
+        }
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_MEMORY_BUDGET)
+    }
+}
+
+/// A number of bytes that can be reserved, waiting for other
+/// reservations to end if necessary.
+struct MemoryBudget {
+    total: usize,
+    reserved: Mutex<usize>,
+    released: Condvar,
+}
+
+impl MemoryBudget {
+    fn new(total: usize) -> Self {
+        Self {
+            total,
+            reserved: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Unless `wait`, may exceed the total.
+    fn reserve(&self, bytes: usize, wait: bool) -> Reservation<'_> {
+        // Allow oversized reservations, one at a time.
+        let bytes = bytes.min(self.total);
+        let mut reserved = self.reserved.lock().unwrap();
+        while wait && *reserved + bytes > self.total {
+            reserved = self.released.wait(reserved).unwrap();
+        }
+        *reserved += bytes;
+        Reservation {
+            budget: self,
+            bytes,
+        }
+    }
+}
+
+struct Reservation<'a> {
+    budget: &'a MemoryBudget,
+    bytes: usize,
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        *self.budget.reserved.lock().unwrap() -= self.bytes;
+        self.budget.released.notify_all();
+    }
+}
+
+impl Debug for DecodeCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DecodeCache")
+            .field("budget", &self.budget)
+            .field("decoding", &self.decoding.total)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(size: u32) -> RgbImage {
+        RgbImage::new(size, size)
+    }
+
+    #[test]
+    fn decodes_once() {
+        let cache = DecodeCache::new(1 << 20);
+        let path = Path::new("a.jpg");
+        let first = cache.get_or_decode(path, 0, || Ok::<_, ()>(image(1)));
+        let second = cache.get_or_decode(path, 0, || -> Result<_, ()> { unreachable!() });
+        assert!(Arc::ptr_eq(&first.unwrap(), &second.unwrap()));
+    }
+
+    #[test]
+    fn nested_decodes_dont_wait() {
+        // Less than either decode needs.
+        let cache = DecodeCache::new(4);
+        let path = Path::new("a.jpg");
+        let image = cache.get_or_decode(path, 100, || {
+            // The same photo, and another, on the same thread.
+            cache.get_or_decode(path, 100, || Ok(image(2)))?;
+            cache.get_or_decode(Path::new("b.jpg"), 100, || Ok(image(2)))?;
+            Ok::<_, ()>(image(1))
+        });
+        assert_eq!(image.unwrap().width(), 1);
+    }
+
+    #[test]
+    fn evicts_only_decoded() {
+        // Room for one 10x10 image.
+        let cache = DecodeCache::new(4 * 300);
+        let paths = |cache: &DecodeCache| {
+            cache
+                .entries
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(path, _)| path.to_str().unwrap().to_owned())
+                .collect::<Vec<_>>()
+        };
+        cache
+            .get_or_decode(Path::new("a.jpg"), 0, || {
+                cache.get_or_decode(Path::new("b.jpg"), 0, || Ok(image(10)))?;
+                cache.get_or_decode(Path::new("c.jpg"), 0, || Ok(image(10)))?;
+                // Not a, which is still being decoded.
+                assert_eq!(paths(&cache), ["a.jpg", "c.jpg"]);
+                Ok::<_, ()>(image(10))
+            })
+            .unwrap();
+        assert_eq!(paths(&cache), ["c.jpg"]);
+    }
+
+    #[test]
+    fn failures_arent_kept() {
+        let cache = DecodeCache::new(1 << 20);
+        let path = Path::new("a.jpg");
+        assert!(cache.get_or_decode(path, 0, || Err(())).is_err());
+        assert!(cache
+            .get_or_decode(path, 0, || Ok::<_, ()>(image(1)))
+            .is_ok());
+    }
+}
@@ -1,4 +1,4 @@
-use chrono::NaiveDateTime;
+use chrono::{FixedOffset, NaiveDateTime};
 use exif::{Exif, In, Tag};
 
 /// https://www.cipa.jp/std/documents/e/DC-008-2012_E.pdf
@@ -13,6 +13,8 @@ pub struct ExifData {
     pub lens_model: Option<String>,
     /// YYYY-MM-DD HH:MM:SS
     pub original_time_taken: Option<String>,
+    /// Of `original_time_taken` from UTC, like +02:00.
+    pub original_time_offset: Option<String>,
     /// Seconds.
     pub exposure_time: Option<String>,
     pub aperture: Option<String>,
@@ -42,6 +44,12 @@ impl ExifData {
             .and_then(|s| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").ok())
     }
 
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        self.original_time_offset
+            .as_ref()
+            .and_then(|s| s.trim_matches('"').parse().ok())
+    }
+
     pub fn oriented(&self) -> bool {
         self.orientation
             .as_ref()
@@ -82,6 +90,7 @@ impl ExifData {
             lens_make: lookup(&meta, Tag::LensMake),
             lens_model: lookup(&meta, Tag::LensModel),
             original_time_taken: lookup(&meta, Tag::DateTimeOriginal),
+            original_time_offset: lookup(&meta, Tag::OffsetTimeOriginal),
             exposure_time: lookup(&meta, Tag::ExposureTime),
             aperture: lookup(&meta, Tag::FNumber),
             iso_sensitivity: lookup(&meta, Tag::PhotographicSensitivity),
@@ -0,0 +1,136 @@
+#[cfg(feature = "heif")]
+use image::{
+    error::{DecodingError, ImageFormatHint},
+    metadata::Orientation,
+    ColorType, ImageDecoder, ImageError, ImageResult,
+};
+#[cfg(feature = "heif")]
+use libheif_rs::{ColorSpace, HeifContext, ImageHandle, ItemId, LibHeif, RgbChroma};
+use std::path::Path;
+
+/// Extensions of HEIF (including AVIF) files, in lowercase.
+pub const HEIF_EXTENSIONS: [&str; 4] = ["avif", "heic", "heif", "hif"];
+
+/// Extensions of JPEG XL files, in lowercase. They aren't decoded yet, only
+/// recognized so that they're reported instead of silently skipped.
+pub const JXL_EXTENSIONS: [&str; 1] = ["jxl"];
+
+pub fn is_heif(path: &Path) -> bool {
+    has_extension(path, &HEIF_EXTENSIONS)
+}
+
+pub fn is_jxl(path: &Path) -> bool {
+    has_extension(path, &JXL_EXTENSIONS)
+}
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extensions.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Why this build of chillphoto can't decode the photo, if it can't.
+pub fn missing_codec(path: &Path) -> Option<&'static str> {
+    if is_heif(path) && !cfg!(feature = "heif") {
+        Some("no HEIF/AVIF codec in this build; reinstall chillphoto with `--features heif` (requires libheif)")
+    } else if is_jxl(path) {
+        Some("JPEG XL photos aren't supported yet; convert it to another format")
+    } else {
+        None
+    }
+}
+
+/// Decodes the primary image of HEIF and AVIF files using libheif.
+#[cfg(feature = "heif")]
+pub struct HeifDecoder {
+    // Fields are dropped in order, and each depends on the next.
+    handle: ImageHandle,
+    _context: HeifContext<'static>,
+    lib_heif: LibHeif,
+}
+
+#[cfg(feature = "heif")]
+impl HeifDecoder {
+    pub fn new(path: &Path) -> ImageResult<Self> {
+        let lib_heif = LibHeif::new();
+        let context = HeifContext::read_from_file(
+            path.to_str()
+                .ok_or_else(|| error("path isn't valid UTF-8"))?,
+        )
+        .map_err(error)?;
+        let handle = context.primary_image_handle().map_err(error)?;
+        Ok(Self {
+            handle,
+            _context: context,
+            lib_heif,
+        })
+    }
+}
+
+#[cfg(feature = "heif")]
+impl ImageDecoder for HeifDecoder {
+    fn dimensions(&self) -> (u32, u32) {
+        (self.handle.width(), self.handle.height())
+    }
+
+    fn color_type(&self) -> ColorType {
+        if self.handle.has_alpha_channel() {
+            ColorType::Rgba8
+        } else {
+            ColorType::Rgb8
+        }
+    }
+
+    fn exif_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        let mut ids: [ItemId; 1] = [0];
+        if self.handle.metadata_block_ids(&mut ids, b"Exif") == 0 {
+            return Ok(None);
+        }
+        let exif = self.handle.metadata(ids[0]).map_err(error)?;
+        // The TIFF header follows a big-endian offset to it.
+        Ok(exif
+            .get(..4)
+            .map(|offset| u32::from_be_bytes(offset.try_into().unwrap()) as usize + 4)
+            .and_then(|start| exif.get(start..))
+            .map(|tiff| tiff.to_vec()))
+    }
+
+    fn orientation(&mut self) -> ImageResult<Orientation> {
+        // libheif already applied the rotation and mirroring.
+        Ok(Orientation::NoTransforms)
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        let chroma = if self.handle.has_alpha_channel() {
+            RgbChroma::Rgba
+        } else {
+            RgbChroma::Rgb
+        };
+        let image = self
+            .lib_heif
+            .decode(&self.handle, ColorSpace::Rgb(chroma), None)
+            .map_err(error)?;
+        let planes = image.planes();
+        let plane = planes
+            .interleaved
+            .ok_or_else(|| error("missing interleaved plane"))?;
+        let row = buf.len() / plane.height.max(1) as usize;
+        for (y, output) in buf.chunks_exact_mut(row).enumerate() {
+            let input = &plane.data[y * plane.stride..][..row];
+            output.copy_from_slice(input);
+        }
+        Ok(())
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+#[cfg(feature = "heif")]
+fn error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("HEIF".to_owned()),
+        e,
+    ))
+}
@@ -0,0 +1,796 @@
+use crate::{
+    diagnostics::{fits_toml, Diagnostics},
+    gallery::{
+        parse_date, sidecar_paths, tag_slug, Category, CategoryConfig, CategoryPath, DecodeCache,
+        Gallery, GalleryConfig, Item, Order, Page, PageConfig, Photo, PhotoConfig, RichText,
+        RichTextFormat, StaticFile, XmpData,
+    },
+    output::{Theme, THEME_DIR},
+    util::is_camera_file_name,
+};
+use chrono::NaiveDate;
+use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use std::{
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Instant,
+};
+use wax::Glob;
+
+/// What [`Gallery::reload`] can reuse.
+struct Previous<'a> {
+    /// Whose source files are unchanged, by path.
+    photos: HashMap<PathBuf, &'a Photo>,
+    decode_cache: Arc<DecodeCache>,
+}
+
+impl Gallery {
+    /// Ingest the gallery's source files, reporting problems to `diagnostics`.
+    ///
+    /// Also returns source files that don't belong to anything.
+    ///
+    /// Unpublished photos are only kept in `preview`, which is also
+    /// editable if there is a `text_editor`. Items scheduled to be
+    /// published after `as_of`, if any, are left out.
+    pub fn load(
+        config: GalleryConfig,
+        preview: bool,
+        as_of: Option<NaiveDate>,
+        diagnostics: &Diagnostics,
+        start: Instant,
+    ) -> Option<(Self, Vec<PathBuf>)> {
+        Self::load_reusing(config, preview, as_of, None, diagnostics, start)
+    }
+
+    /// Like [`Self::load`] in preview, but reuses what was read and decoded
+    /// of photos whose source files aren't in `changed` (absolute paths).
+    pub fn reload(
+        &self,
+        config: GalleryConfig,
+        as_of: Option<NaiveDate>,
+        changed: &HashSet<PathBuf>,
+        diagnostics: &Diagnostics,
+        start: Instant,
+    ) -> Option<Self> {
+        let is_changed =
+            |path: &Path| std::path::absolute(path).is_ok_and(|path| changed.contains(&path));
+        if is_changed(Path::new(GalleryConfig::PATH)) {
+            // Any photo may be read or decoded differently.
+            return Self::load(config, true, as_of, diagnostics, start).map(|(gallery, _)| gallery);
+        }
+        let mut photos = HashMap::new();
+        self.visit_items(|_, item| {
+            let Some(photo) = item.photo() else {
+                return;
+            };
+            let config_path = self.root.join(format!("{}.toml", photo.src_key));
+            let unchanged = !is_changed(&photo.path)
+                && !is_changed(&config_path)
+                && !sidecar_paths(&photo.path)
+                    .iter()
+                    .any(|path| is_changed(path));
+            if unchanged {
+                photos.insert(photo.path.clone(), photo);
+            }
+        });
+        self.decode_cache.retain(|path| photos.contains_key(path));
+        let previous = Previous {
+            photos,
+            decode_cache: Arc::clone(&self.decode_cache),
+        };
+        Self::load_reusing(config, true, as_of, Some(previous), diagnostics, start)
+            .map(|(gallery, _)| gallery)
+    }
+
+    fn load_reusing(
+        config: GalleryConfig,
+        preview: bool,
+        as_of: Option<NaiveDate>,
+        previous: Option<Previous<'_>>,
+        diagnostics: &Diagnostics,
+        start: Instant,
+    ) -> Option<(Self, Vec<PathBuf>)> {
+        let input_path_string = config.input_glob();
+        let (root, glob) = match Glob::new(&input_path_string) {
+            Ok(glob) => glob.partition(),
+            Err(e) => {
+                diagnostics.error(GalleryConfig::PATH, format!("invalid input glob: {e}"));
+                return None;
+            }
+        };
+
+        struct GalleryExtras {
+            gallery: Gallery,
+            /// Config path and text.
+            item_configs: HashMap<String, (PathBuf, String)>,
+        }
+
+        let entries = match glob
+            .walk(&root)
+            .not([config.output.as_str(), config.cache.as_str()])
+        {
+            Ok(entries) => entries.collect::<Vec<_>>(),
+            Err(e) => {
+                diagnostics.error(GalleryConfig::PATH, format!("invalid output path: {e}"));
+                return None;
+            }
+        };
+
+        let theme = Theme::load(&root.join(THEME_DIR), config.theme, diagnostics);
+        let raw_mode = config.raw;
+
+        let decode_cache = match &previous {
+            Some(previous) => Arc::clone(&previous.decode_cache),
+            None => Arc::new(DecodeCache::new(config.memory_budget())),
+        };
+        let gallery = Mutex::new(GalleryExtras {
+            gallery: Gallery {
+                children: Vec::new(),
+                favicon: None,
+                editable: preview && config.text_editor.is_some(),
+                config,
+                head_html: None,
+                home_text: None,
+                not_found_text: None,
+                static_files: Vec::new(),
+                theme,
+                root: root.clone(),
+                decode_cache,
+            },
+            item_configs: HashMap::new(),
+        });
+
+        entries.into_par_iter().for_each(|entry| {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    diagnostics.error(
+                        e.path().unwrap_or(&root),
+                        format!("couldn't read directory entry: {e}"),
+                    );
+                    return;
+                }
+            };
+            if !entry.file_type().is_file() {
+                return;
+            }
+            let entire_path = entry.matched().complete().to_owned();
+            let (category_names, categories, except_name, name) =
+                if let Some((categories, name)) = entire_path.rsplit_once('/') {
+                    let category_names = categories
+                        .split('/')
+                        .map(|s| s.to_owned())
+                        .collect::<Vec<String>>();
+                    let path = CategoryPath::new(
+                        &category_names
+                            .iter()
+                            .map(|n| n.replace(' ', "-"))
+                            .collect::<Vec<_>>()
+                            .join("/"),
+                    );
+                    (category_names, path, categories, name)
+                } else {
+                    (Vec::new(), CategoryPath::ROOT, "", entire_path.as_str())
+                };
+
+            if !category_names.is_empty() && category_names[0] == THEME_DIR {
+                // Loaded separately, which reports anything that isn't part of it.
+                return;
+            }
+
+            if !category_names.is_empty() && category_names[0] == "static" {
+                let contents = match fs::read(entry.path()) {
+                    Ok(contents) => contents,
+                    Err(e) => {
+                        diagnostics.error(entry.path(), format!("couldn't read static file: {e}"));
+                        return;
+                    }
+                };
+                let mut gallery = gallery.lock().unwrap();
+                gallery.gallery.static_files.push(StaticFile {
+                    path: format!("/{entire_path}"),
+                    contents,
+                });
+                return;
+            }
+
+            let Some((name_no_extension, _)) = name.rsplit_once('.') else {
+                diagnostics.warning(entry.path(), "ignoring file without extension");
+                return;
+            };
+            let name_no_extension = name_no_extension.to_owned();
+            let path_no_extension = if except_name.is_empty() {
+                name_no_extension.clone()
+            } else {
+                format!("{except_name}/{name_no_extension}")
+            };
+
+            if name.ends_with(".xmp") {
+                // Read along with the photo.
+                return;
+            }
+
+            if name.ends_with(".toml") {
+                let config_text = match fs::read_to_string(entry.path()) {
+                    Ok(config_text) => config_text,
+                    Err(e) => {
+                        diagnostics.error(entry.path(), format!("couldn't read config: {e}"));
+                        return;
+                    }
+                };
+
+                let mut gallery = gallery.lock().unwrap();
+                gallery
+                    .item_configs
+                    .insert(path_no_extension, (entry.path().to_owned(), config_text));
+                return;
+            }
+
+            let page_format = if name.ends_with(".html") {
+                Some(RichTextFormat::Html)
+            } else if name.ends_with(".md") {
+                Some(RichTextFormat::Markdown)
+            } else if name.ends_with(".txt") {
+                Some(RichTextFormat::PlainText)
+            } else {
+                None
+            };
+
+            if let Some(format) = page_format {
+                let file = match std::fs::read_to_string(entry.path()) {
+                    Ok(file) => file,
+                    Err(e) => {
+                        diagnostics.error(entry.path(), format!("couldn't read text: {e}"));
+                        return;
+                    }
+                };
+                let mut gallery = gallery.lock().unwrap();
+                if name_no_extension == "head" && matches!(format, RichTextFormat::Html) {
+                    gallery.gallery.head_html = Some(file);
+                    return;
+                }
+                let to_insert = gallery
+                    .gallery
+                    .get_or_create_category(&category_names, &categories);
+                to_insert.push(Item::Page(Page {
+                    name: name_no_extension,
+                    text: RichText {
+                        content: file,
+                        format,
+                    },
+                    config: PageConfig::default(),
+                    src_key: path_no_extension,
+                    path: entry.path().to_owned(),
+                }));
+                return;
+            }
+
+            let metadata = match fs::metadata(entry.path()) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    diagnostics.error(entry.path(), format!("couldn't read metadata: {e}"));
+                    return;
+                }
+            };
+
+            if categories.is_root() && name_no_extension == "favicon" {
+                let favicon = match fs::read(entry.path()) {
+                    Ok(favicon) => favicon,
+                    Err(e) => {
+                        diagnostics.error(entry.path(), format!("couldn't read favicon: {e}"));
+                        return;
+                    }
+                };
+                if let Err(e) = image::load_from_memory(&favicon) {
+                    diagnostics.error(entry.path(), format!("couldn't decode favicon: {e}"));
+                    return;
+                }
+                let mut gallery = gallery.lock().unwrap();
+                gallery.gallery.favicon = Some((favicon, OnceLock::new()));
+                return;
+            }
+
+            let reusable = previous
+                .as_ref()
+                .and_then(|previous| previous.photos.get(entry.path()));
+            let photo = Photo {
+                name: name_no_extension,
+                text: None,
+                exif: reusable.map_or_else(Default::default, |photo| photo.exif.clone()),
+                decoded_dimensions: reusable
+                    .map_or_else(Default::default, |photo| photo.decoded_dimensions.clone()),
+                xmp: reusable.map_or_else(
+                    || XmpData::read(entry.path(), diagnostics),
+                    |photo| photo.xmp.clone(),
+                ),
+                raw_mode,
+                config: Default::default(),
+                tags: Vec::new(),
+                file_date: metadata.modified().or(metadata.created()).ok(),
+                path: entry.path().to_owned(),
+                src_key: path_no_extension,
+                parsed_config_date: None,
+                distinct_name: None,
+            };
+
+            if reusable.is_none() {
+                if let Err(e) = photo.try_decoded_dimensions() {
+                    diagnostics.error(entry.path(), format!("couldn't open photo: {e}"));
+                    return;
+                }
+            }
+
+            let mut gallery = gallery.lock().unwrap();
+            let to_insert = gallery
+                .gallery
+                .get_or_create_category(&category_names, &categories);
+            to_insert.push(Item::Photo(photo));
+        });
+
+        let GalleryExtras {
+            mut gallery,
+            mut item_configs,
+        } = gallery.into_inner().unwrap();
+
+        // Smart albums have a config, and maybe a caption, but no other files.
+        let mut src_keys = HashSet::<String>::new();
+        gallery.visit_items(|_, item| match item {
+            Item::Photo(photo) => {
+                src_keys.insert(photo.src_key.clone());
+            }
+            Item::Category(category) => {
+                src_keys.insert(category.src_key.clone());
+            }
+            Item::Page(_) => {}
+        });
+        let mut albums = Vec::<(String, CategoryConfig)>::new();
+        for (name, config) in &gallery.config.albums {
+            match &config.filter {
+                Some(filter) => {
+                    if let Err(e) = filter.date_range(&gallery.config.date_format) {
+                        diagnostics.error(GalleryConfig::PATH, format!("album {name:?}: {e}"));
+                    }
+                    if let Some(Err(e)) = config
+                        .publish_at
+                        .as_ref()
+                        .map(|date| parse_date(date, &gallery.config.date_format))
+                    {
+                        diagnostics.error(GalleryConfig::PATH, format!("album {name:?}: {e}"));
+                    }
+                    albums.push((name.clone(), config.clone()));
+                }
+                None => {
+                    diagnostics.error(GalleryConfig::PATH, format!("album {name:?} has no filter"))
+                }
+            }
+        }
+        for (src_key, (_, config_text)) in &item_configs {
+            if !src_keys.contains(src_key)
+                && config_text
+                    .parse::<toml::Table>()
+                    .is_ok_and(|table| table.contains_key("filter"))
+            {
+                // Parsed along with the other configs.
+                albums.push((src_key.clone(), CategoryConfig::default()));
+            }
+        }
+        for (src_key, config) in albums {
+            let mut names = src_key.split('/').map(str::to_owned).collect::<Vec<_>>();
+            let name = names.pop().unwrap();
+            let path = CategoryPath::new(&names.join("/").replace(' ', "-"));
+            gallery
+                .get_or_create_category(&names, &path)
+                .push(Item::Category(Category {
+                    name,
+                    text: None,
+                    children: Vec::new(),
+                    config,
+                    src_key,
+                    album: Some(Vec::new()),
+                }));
+        }
+        let mut categories = 0usize;
+        let mut category_configs = 0usize;
+        let mut category_texts = 0usize;
+        let mut photos = 0usize;
+        let mut photo_configs = 0usize;
+        let mut photo_texts = 0usize;
+        let mut pages = 0usize;
+        let mut page_configs = 0usize;
+        // `special` pages, like "home", are texts of the gallery itself.
+        let mut match_pages = |special: &mut [(&str, &mut Option<RichText>)],
+                               children: &mut Vec<Item>| {
+            let mut matches = Vec::<(String, RichText)>::new();
+            for name in children
+                .iter()
+                .filter_map(|i| {
+                    i.photo()
+                        .map(|p| p.name.as_str())
+                        .or(i.category().map(|c| c.name.as_str()))
+                })
+                .chain(special.iter().map(|(name, _)| *name))
+            {
+                for page in children.iter().filter_map(|i| i.page()) {
+                    if *name == page.name {
+                        matches.push((name.to_owned(), page.text.clone()));
+                    }
+                }
+            }
+            for (name, text) in matches {
+                for (_, special) in special.iter_mut().filter(|(special, _)| *special == name) {
+                    **special = Some(text.clone());
+                }
+                children.retain_mut(|child| {
+                    match child {
+                        Item::Photo(photo) => {
+                            if photo.name == name {
+                                photo.text = Some(text.clone());
+                                photo_texts += 1;
+                            }
+                        }
+                        Item::Category(category) => {
+                            if category.name == name {
+                                category.text = Some(text.clone());
+                                category_texts += 1;
+                            }
+                        }
+                        Item::Page(page) => return page.name != name,
+                    }
+                    true
+                });
+            }
+        };
+        // Source files that don't belong to anything.
+        let mut orphans = Vec::<PathBuf>::new();
+        /// Pages that look like they were meant to be captions
+        /// of a photo or category that doesn't exist.
+        fn find_dangling_captions(
+            diagnostics: &Diagnostics,
+            children: &[Item],
+            orphans: &mut Vec<PathBuf>,
+        ) {
+            for page in children.iter().filter_map(|i| i.page()) {
+                let near_miss = children
+                    .iter()
+                    .filter_map(|i| {
+                        i.photo()
+                            .map(|p| p.name.as_str())
+                            .or(i.category().map(|c| c.name.as_str()))
+                    })
+                    .find(|name| name.eq_ignore_ascii_case(&page.name));
+                if let Some(name) = near_miss {
+                    diagnostics.warning(
+                        &page.path,
+                        format!(
+                            "caption doesn't match any photo or category (did you mean {name:?}?)"
+                        ),
+                    );
+                } else if is_camera_file_name(&page.name) {
+                    diagnostics.warning(&page.path, "caption doesn't match any photo");
+                } else {
+                    continue;
+                }
+                orphans.push(page.path.clone());
+            }
+        }
+        match_pages(
+            &mut [
+                ("home", &mut gallery.home_text),
+                ("404", &mut gallery.not_found_text),
+            ],
+            &mut gallery.children,
+        );
+        find_dangling_captions(diagnostics, &gallery.children, &mut orphans);
+        let date_format = gallery.config.date_format.clone();
+        let check_publish_at =
+            |publish_at: &Option<String>, config_path: &Path, config_text: &str| {
+                if let Some(Err(e)) = publish_at
+                    .as_ref()
+                    .map(|date| parse_date(date, &date_format))
+                {
+                    diagnostics.toml_key_error(config_path, config_text, "publish_at", e);
+                }
+            };
+        gallery.visit_items_mut(|_, item| match item {
+            Item::Category(category) => {
+                if let Some((config_path, config_text)) = item_configs.remove(&category.src_key) {
+                    if let Some(config) =
+                        diagnostics.parse_toml::<CategoryConfig>(&config_path, &config_text)
+                    {
+                        category.config = config;
+                    }
+                    check_publish_at(&category.config.publish_at, &config_path, &config_text);
+                    match (&category.album, &category.config.filter) {
+                        (Some(_), Some(filter)) => {
+                            if let Err(e) = filter.date_range(&date_format) {
+                                diagnostics.toml_key_error(&config_path, &config_text, "filter", e);
+                            }
+                        }
+                        (None, Some(_)) => diagnostics.toml_key_warning(
+                            &config_path,
+                            &config_text,
+                            "filter",
+                            "ignoring filter of a category with its own files",
+                        ),
+                        _ => {}
+                    }
+                    if let Some(thumbnail) = category
+                        .config
+                        .thumbnail
+                        .as_ref()
+                        .filter(|_| category.album.is_none())
+                    {
+                        let mut found = false;
+                        category.visit_items(&CategoryPath::ROOT, |_, item| {
+                            found |= item.photo().is_some_and(|p| p.name == *thumbnail)
+                                || item.category().is_some_and(|c| c.name == *thumbnail);
+                        });
+                        if !found {
+                            diagnostics.toml_key_warning(
+                                &config_path,
+                                &config_text,
+                                "thumbnail",
+                                format!(
+                                    "thumbnail {thumbnail:?} doesn't match any photo or category"
+                                ),
+                            );
+                        }
+                    }
+                    category_configs += 1;
+                }
+
+                match_pages(&mut [], &mut category.children);
+                find_dangling_captions(diagnostics, &category.children, &mut orphans);
+
+                categories += 1;
+            }
+            Item::Photo(photo) => {
+                if let Some((config_path, config_text)) = item_configs.remove(&photo.src_key) {
+                    if let Some(config) =
+                        diagnostics.parse_toml::<PhotoConfig>(&config_path, &config_text)
+                    {
+                        photo.config = config;
+                    }
+                    if let Some(date) = &photo.config.date {
+                        match parse_date(date, &date_format) {
+                            Ok(date) => photo.parsed_config_date = Some(date),
+                            Err(e) => {
+                                diagnostics.toml_key_error(&config_path, &config_text, "date", e)
+                            }
+                        }
+                    }
+                    match photo.config.rating {
+                        Some(-1) => photo.config.rejected = true,
+                        Some(rating) if !(0..=5).contains(&rating) => {
+                            diagnostics.toml_key_error(
+                                &config_path,
+                                &config_text,
+                                "rating",
+                                format!("rating {rating} isn't from 0 to 5, or -1 to reject"),
+                            );
+                            photo.config.rating = None;
+                        }
+                        _ => {}
+                    }
+                    check_publish_at(&photo.config.publish_at, &config_path, &config_text);
+                    photo_configs += 1;
+                }
+                photo.config.apply_xmp(&photo.xmp);
+                photos += 1;
+            }
+            Item::Page(page) => {
+                if let Some((config_path, config)) = item_configs.remove(&page.src_key) {
+                    if !fits_toml::<PageConfig>(&config)
+                        && (fits_toml::<PhotoConfig>(&config)
+                            || fits_toml::<CategoryConfig>(&config))
+                    {
+                        // The photo or category was probably renamed or deleted.
+                        diagnostics.warning(
+                            &page.path,
+                            "caption and config don't match any photo or category",
+                        );
+                        orphans.push(page.path.clone());
+                        orphans.push(config_path);
+                    } else if let Some(parsed) =
+                        diagnostics.parse_toml::<PageConfig>(&config_path, &config)
+                    {
+                        page.config = parsed;
+                        check_publish_at(&page.config.publish_at, &config_path, &config);
+                    }
+                    page_configs += 1;
+                }
+                pages += 1;
+            }
+        });
+
+        for (config_path, _) in item_configs.into_values() {
+            diagnostics.warning(
+                &config_path,
+                "config doesn't match any photo, category, or page",
+            );
+            orphans.push(config_path);
+        }
+        orphans.sort();
+        orphans.dedup();
+
+        fn inherit_tags(items: &mut [Item], inherited: &[String]) {
+            for item in items {
+                match item {
+                    Item::Photo(photo) => {
+                        for tag in photo.config.tags.iter().chain(inherited) {
+                            let tag = tag.trim();
+                            if !tag.is_empty()
+                                && photo.tags.iter().all(|t| tag_slug(t) != tag_slug(tag))
+                            {
+                                photo.tags.push(tag.to_owned());
+                            }
+                        }
+                    }
+                    Item::Category(category) => {
+                        let inherited = inherited
+                            .iter()
+                            .chain(&category.config.tags)
+                            .cloned()
+                            .collect::<Vec<_>>();
+                        inherit_tags(&mut category.children, &inherited);
+                    }
+                    Item::Page(_) => {}
+                }
+            }
+        }
+        inherit_tags(&mut gallery.children, &[]);
+
+        /// Also removes categories that only had unpublished photos.
+        fn remove_unpublished(
+            config: &GalleryConfig,
+            preview: bool,
+            as_of: Option<NaiveDate>,
+            items: &mut Vec<Item>,
+        ) {
+            // Invalid dates were already reported.
+            let scheduled = |publish_at: &Option<String>| {
+                as_of.is_none_or(|as_of| {
+                    publish_at
+                        .as_ref()
+                        .and_then(|date| parse_date(date, &config.date_format).ok())
+                        .is_none_or(|date| date <= as_of)
+                })
+            };
+            items.retain_mut(|item| match item {
+                Item::Photo(photo) => {
+                    (preview || photo.published(config)) && scheduled(&photo.config.publish_at)
+                }
+                Item::Category(category) => {
+                    if !scheduled(&category.config.publish_at) {
+                        return false;
+                    }
+                    let had_photos = category.thumbnail(&CategoryPath::ROOT).is_some();
+                    remove_unpublished(config, preview, as_of, &mut category.children);
+                    !had_photos || category.thumbnail(&CategoryPath::ROOT).is_some()
+                }
+                Item::Page(page) => scheduled(&page.config.publish_at),
+            });
+        }
+        remove_unpublished(&gallery.config, preview, as_of, &mut gallery.children);
+
+        fn sort_and_make_photo_names_distinct(items: &mut [Item]) {
+            // Don't let user-defined order change distinct names.
+            items.sort_by_key(|item| {
+                if let Item::Photo(photo) = item {
+                    Some((std::cmp::Reverse(photo.date_time()), photo.name.clone()))
+                } else {
+                    None
+                }
+            });
+
+            let mut indices = HashMap::<String, usize>::new();
+            for item in items.iter_mut().rev() {
+                let photo = if let Item::Photo(photo) = item {
+                    photo
+                } else {
+                    continue;
+                };
+
+                let name = photo.output_name();
+                let index = indices.entry(name.to_owned()).or_default();
+                *index += 1;
+                if *index > 1 {
+                    photo.distinct_name = Some(format!("{name} {index}"));
+                }
+            }
+
+            items.sort_by_key(Order::new);
+        }
+
+        /// Generated pages at the root of the gallery.
+        const RESERVED_SLUGS: [&str; 3] = ["archive", "search", "tags"];
+
+        /// Items with the same slug would overwrite each other's output.
+        fn remove_duplicate_slugs(
+            diagnostics: &Diagnostics,
+            root: &Path,
+            items: &mut Vec<Item>,
+            reserved: &[&str],
+        ) {
+            let mut slugs = HashSet::<String>::new();
+            items.retain(|item| {
+                let slug = item.slug();
+                let path = || match item {
+                    Item::Photo(photo) => photo.path.clone(),
+                    Item::Category(category) => root.join(&category.src_key),
+                    Item::Page(page) => page.path.clone(),
+                };
+                if reserved.contains(&slug.as_str()) {
+                    diagnostics.error(
+                        path(),
+                        format!("slug {slug:?} is reserved for a generated page"),
+                    );
+                    return false;
+                }
+                if slugs.insert(slug.clone()) {
+                    return true;
+                }
+                diagnostics.error(
+                    path(),
+                    format!("slug {slug:?} is already used by another item in the same category"),
+                );
+                false
+            });
+
+            for item in items {
+                if let Item::Category(category) = item {
+                    if category.album.is_none() && category.thumbnail(&CategoryPath::ROOT).is_none()
+                    {
+                        diagnostics
+                            .warning(root.join(&category.src_key), "category contains no photos");
+                    }
+                    remove_duplicate_slugs(diagnostics, root, &mut category.children, &[]);
+                }
+            }
+        }
+
+        sort_and_make_photo_names_distinct(&mut gallery.children);
+        gallery.visit_items_mut(|_, item| {
+            if let Item::Category(category) = item {
+                sort_and_make_photo_names_distinct(&mut category.children);
+            }
+        });
+        remove_duplicate_slugs(
+            diagnostics,
+            &gallery.root,
+            &mut gallery.children,
+            &RESERVED_SLUGS,
+        );
+
+        gallery.resolve_albums();
+        gallery.visit_items(|_, item| {
+            if let Some(category) = item.category() {
+                if category
+                    .album
+                    .as_ref()
+                    .is_some_and(|album| album.is_empty())
+                {
+                    let path = if gallery.config.albums.contains_key(&category.src_key) {
+                        PathBuf::from(GalleryConfig::PATH)
+                    } else {
+                        gallery.root.join(format!("{}.toml", category.src_key))
+                    };
+                    diagnostics.warning(
+                        path,
+                        format!("album {:?} doesn't match any photos", category.name),
+                    );
+                }
+            }
+        });
+
+        //println!("{gallery:?}");
+        println!(
+            "({:.1}s) Found {photos} photos ({photo_configs} with config, {photo_texts} with caption) in {categories} categories ({category_configs} with config, {category_texts} with caption), and {pages} pages ({page_configs} with config)",
+            start.elapsed().as_secs_f32()
+        );
+
+        Some((gallery, orphans))
+    }
+}
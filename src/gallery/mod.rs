@@ -1,27 +1,41 @@
-use image::RgbImage;
-use std::{path::PathBuf, sync::OnceLock};
-
+use crate::{diagnostics::Diagnostics, output::Theme};
+use image::{ImageResult, RgbImage};
+use std::{
+    path::PathBuf,
+    sync::{Arc, OnceLock},
+};
+
+mod album;
 mod category;
 mod category_path;
 mod config;
+mod decode_cache;
 mod exif;
+mod heif;
 mod item;
+mod load;
 mod order;
 mod page;
 mod photo;
+mod raw;
 mod rich_text;
 mod static_file;
+mod xmp;
 
 pub use category::*;
 pub use category_path::*;
 pub use config::*;
+pub use decode_cache::*;
 pub use exif::*;
+pub use heif::*;
 pub use item::*;
 pub use order::*;
 pub use page::*;
 pub use photo::*;
+pub use raw::*;
 pub use rich_text::*;
 pub use static_file::*;
+pub use xmp::*;
 
 #[derive(Debug)]
 pub struct Gallery {
@@ -30,10 +44,15 @@ pub struct Gallery {
     pub config: GalleryConfig,
     pub head_html: Option<String>,
     pub home_text: Option<RichText>,
+    /// Caption of the 404 page.
+    pub not_found_text: Option<RichText>,
     pub static_files: Vec<StaticFile>,
+    pub theme: Theme,
     /// Path to top level of gallery source files in file system.
     pub root: PathBuf,
     pub editable: bool,
+    /// Shared with the gallery it's reloaded as, if any.
+    pub decode_cache: Arc<DecodeCache>,
 }
 
 impl Gallery {
@@ -47,6 +66,26 @@ impl Gallery {
         })
     }
 
+    /// Like `Photo::image`, but shared by everything derived from the photo.
+    pub fn photo_image(&self, photo: &Photo) -> ImageResult<Arc<RgbImage>> {
+        self.decode_cache
+            .get_or_decode(&photo.path, photo.image_memory(), || {
+                photo.image(&self.config)
+            })
+    }
+
+    /// Like `photo_image`, but reports failures to `diagnostics`, once per
+    /// photo.
+    pub fn photo_image_or_report(
+        &self,
+        photo: &Photo,
+        diagnostics: &Diagnostics,
+    ) -> Option<Arc<RgbImage>> {
+        self.photo_image(photo)
+            .map_err(|e| diagnostics.error_once(&photo.path, format!("couldn't decode photo: {e}")))
+            .ok()
+    }
+
     pub fn thumbnail(&self) -> Option<(CategoryPath, &Photo)> {
         let mut ret = Option::<(CategoryPath, &Photo)>::None;
         self.visit_items(|path, item| {
@@ -147,7 +186,9 @@ impl Gallery {
     ) -> &mut Vec<Item> {
         let mut current_items = &mut self.children;
 
-        for (category_name, _category_slug) in names.iter().zip(path.iter_segments()) {
+        for (i, (category_name, _category_slug)) in
+            names.iter().zip(path.iter_segments()).enumerate()
+        {
             let position = current_items.iter().position(|item| {
                 if let Item::Category(cat) = item {
                     cat.name == *category_name
@@ -164,7 +205,8 @@ impl Gallery {
                     text: None,
                     children: Vec::new(),
                     config: CategoryConfig::default(),
-                    src_key: names.join("/"),
+                    src_key: names[..=i].join("/"),
+                    album: None,
                 }));
 
                 current_items.last_mut().unwrap().category_mut().unwrap()
@@ -1,4 +1,5 @@
 use crate::gallery::{PageConfig, RichText};
+use std::path::PathBuf;
 
 #[derive(Debug)]
 pub struct Page {
@@ -6,6 +7,8 @@ pub struct Page {
     pub text: RichText,
     pub config: PageConfig,
     pub src_key: String,
+    /// Source file.
+    pub path: PathBuf,
 }
 
 impl Page {
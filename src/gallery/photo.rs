@@ -1,18 +1,21 @@
 use crate::{
-    gallery::{ExifData, GalleryConfig, PhotoConfig, RichText},
+    gallery::{
+        is_raw, missing_codec, raw_dimensions, ExifData, GalleryConfig, PhotoConfig, RawDecoder,
+        RawMode, RichText, XmpData,
+    },
     util::is_camera_file_name,
 };
 use base64::Engine;
-use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, NaiveTime};
 use image::{
     imageops::{self, FilterType},
     metadata::Orientation,
-    DynamicImage, ImageDecoder, ImageReader, RgbImage,
+    DynamicImage, ImageDecoder, ImageReader, ImageResult, RgbImage,
 };
 use std::{
     fmt::Debug,
-    fs::{File, OpenOptions},
-    io::{BufReader, Read},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, Read},
     path::PathBuf,
     sync::OnceLock,
     time::SystemTime,
@@ -23,9 +26,16 @@ pub struct Photo {
     pub text: Option<RichText>,
     pub path: PathBuf,
     pub exif: OnceLock<ExifData>,
+    /// See [`Self::try_decoded_dimensions`].
+    pub decoded_dimensions: OnceLock<(u32, u32)>,
+    pub xmp: XmpData,
+    /// For RAW files, from the gallery config.
+    pub raw_mode: RawMode,
     pub file_date: Option<SystemTime>,
     pub parsed_config_date: Option<NaiveDate>,
     pub config: PhotoConfig,
+    /// Tags from its config and those of its categories.
+    pub tags: Vec<String>,
     pub distinct_name: Option<String>,
     pub src_key: String,
 }
@@ -33,9 +43,10 @@ pub struct Photo {
 impl Photo {
     pub fn exif(&self) -> &ExifData {
         self.exif.get_or_init(|| {
-            self.image_decoder()
-                .exif_metadata()
-                .unwrap()
+            self.try_image_decoder()
+                .ok()
+                .and_then(|mut decoder| decoder.exif_metadata().ok())
+                .flatten()
                 .map(|exif| ExifData::new(exif))
                 .unwrap_or_default()
         })
@@ -62,6 +73,11 @@ impl Photo {
         })
     }
 
+    /// Whether `build` includes the photo.
+    pub fn published(&self, config: &GalleryConfig) -> bool {
+        !self.config.draft && !self.config.rejected && self.config.stars() >= config.min_rating
+    }
+
     pub fn date_time(&self) -> Option<NaiveDateTime> {
         self.parsed_config_date
             .map(|d| NaiveDateTime::new(d, NaiveTime::from_hms_opt(0, 0, 0).unwrap()))
@@ -78,12 +94,20 @@ impl Photo {
             })
     }
 
-    pub fn input_image_data_file(&self) -> File {
-        OpenOptions::new().read(true).open(&self.path).unwrap()
+    /// Of [`Self::date_time`] from UTC, if known.
+    pub fn utc_offset(&self) -> Option<FixedOffset> {
+        if self.parsed_config_date.is_some() {
+            None
+        } else if self.exif().date_time().is_some() {
+            self.exif().utc_offset()
+        } else {
+            // File dates are in UTC.
+            self.file_date.map(|_| FixedOffset::east_opt(0).unwrap())
+        }
     }
 
-    pub fn buffered_input_image_data(&self) -> BufReader<File> {
-        BufReader::new(self.input_image_data_file())
+    pub fn input_image_data_file(&self) -> File {
+        OpenOptions::new().read(true).open(&self.path).unwrap()
     }
 
     pub fn input_image_data(&self) -> Vec<u8> {
@@ -98,16 +122,58 @@ impl Photo {
         base64::engine::general_purpose::STANDARD_NO_PAD.encode(&ctx.compute().0)
     }
 
-    pub fn image_decoder(&self) -> impl ImageDecoder {
-        ImageReader::new(self.buffered_input_image_data())
-            .with_guessed_format()
-            .unwrap()
-            .into_decoder()
-            .unwrap()
+    /// Doesn't decode pixels, so this is cheap enough to validate photos.
+    pub fn try_image_decoder(&self) -> ImageResult<Box<dyn ImageDecoder>> {
+        if let Some(message) = missing_codec(&self.path) {
+            return Err(io::Error::new(io::ErrorKind::Unsupported, message).into());
+        }
+        #[cfg(feature = "heif")]
+        if crate::gallery::is_heif(&self.path) {
+            return Ok(Box::new(crate::gallery::HeifDecoder::new(&self.path)?));
+        }
+        if is_raw(&self.path) {
+            return Ok(Box::new(RawDecoder::new(
+                fs::read(&self.path)?,
+                self.raw_mode,
+            )?));
+        }
+        Ok(Box::new(
+            ImageReader::new(BufReader::new(File::open(&self.path)?))
+                .with_guessed_format()?
+                .into_decoder()?,
+        ))
+    }
+
+    /// Of the decoded image, after orientation. Only reads the headers of
+    /// RAW files, so this is cheap enough to validate photos.
+    pub fn try_decoded_dimensions(&self) -> ImageResult<(u32, u32)> {
+        if let Some(dimensions) = self.decoded_dimensions.get() {
+            return Ok(*dimensions);
+        }
+        let dimensions = if is_raw(&self.path) && missing_codec(&self.path).is_none() {
+            raw_dimensions(&self.path, self.raw_mode)?
+        } else {
+            let mut decoder = self.try_image_decoder()?;
+            let (width, height) = decoder.dimensions();
+            match decoder.orientation() {
+                Ok(
+                    Orientation::Rotate90
+                    | Orientation::Rotate90FlipH
+                    | Orientation::Rotate270
+                    | Orientation::Rotate270FlipH,
+                ) => (height, width),
+                _ => (width, height),
+            }
+        };
+        Ok(*self.decoded_dimensions.get_or_init(|| dimensions))
     }
 
     pub fn image_dimensions(&self, config: &GalleryConfig) -> (u32, u32) {
-        if let Some((width, height)) = self.exif().dimensions().filter(|_| !self.exif().oriented())
+        // RAW files describe the dimensions of a thumbnail or the sensor.
+        if let Some((width, height)) = self
+            .exif()
+            .dimensions()
+            .filter(|_| !self.exif().oriented() && !is_raw(&self.path))
         {
             // Avoid decoding the image if we don't have to.
             resize_dimensions(
@@ -117,25 +183,28 @@ impl Photo {
                 config.photo_resolution,
             )
         } else {
-            let mut decoder = self.image_decoder();
-            let mut dim = decoder.dimensions();
-            if let Ok(
-                Orientation::Rotate90
-                | Orientation::Rotate90FlipH
-                | Orientation::Rotate270
-                | Orientation::Rotate270FlipH,
-            ) = decoder.orientation()
-            {
-                std::mem::swap(&mut dim.0, &mut dim.1);
-            }
-            dim
+            self.try_decoded_dimensions().unwrap()
         }
     }
 
-    pub fn image(&self, config: &GalleryConfig) -> RgbImage {
-        let mut decoder = self.image_decoder();
+    /// Roughly the most memory `image` uses, in bytes.
+    pub fn image_memory(&self) -> usize {
+        let (width, height) = self
+            .exif()
+            .dimensions()
+            .filter(|_| !is_raw(&self.path))
+            .unwrap_or_else(|| self.try_decoded_dimensions().unwrap());
+        // The decoded image, which may have 16-bit channels,
+        // and a few RGB copies of it.
+        width as usize * height as usize * 12
+    }
+
+    /// Fails if the photo is corrupt, like a truncated file, which
+    /// loading the gallery doesn't notice.
+    pub fn image(&self, config: &GalleryConfig) -> ImageResult<RgbImage> {
+        let mut decoder = self.try_image_decoder()?;
         let orientation = decoder.orientation();
-        let mut image = DynamicImage::from_decoder(decoder).unwrap();
+        let mut image = DynamicImage::from_decoder(decoder)?;
 
         if let Ok(orientation) = orientation {
             image.apply_orientation(orientation);
@@ -155,35 +224,38 @@ impl Photo {
             }
         }
 
-        resize_image(&rgb, config.photo_resolution)
+        Ok(resize_image(&rgb, config.photo_resolution))
     }
 
     pub fn preview_dimensions(&self, config: &GalleryConfig) -> (u32, u32) {
-        let (width, height) = self.image_dimensions(config);
-        resize_dimensions(
-            width,
-            height,
-            config.preview_resolution,
-            config.preview_resolution,
-        )
+        self.custom_preview_dimensions(config, config.preview_resolution)
     }
 
-    pub fn preview(&self, config: &GalleryConfig) -> RgbImage {
-        self.custom_preview(config, config.preview_resolution)
+    pub fn custom_preview_dimensions(&self, config: &GalleryConfig, resolution: u32) -> (u32, u32) {
+        resized_dimensions(self.image_dimensions(config), resolution)
     }
 
-    pub fn thumbnail(&self, config: &GalleryConfig) -> RgbImage {
+    pub fn thumbnail(&self, config: &GalleryConfig) -> ImageResult<RgbImage> {
         self.custom_thumbnail(config, config.thumbnail_resolution)
     }
 
     /// Not cached.
-    pub fn custom_thumbnail(&self, config: &GalleryConfig, resolution: u32) -> RgbImage {
-        generate_thumbnail(&self.image(config), resolution, &self.config)
+    pub fn custom_thumbnail(
+        &self,
+        config: &GalleryConfig,
+        resolution: u32,
+    ) -> ImageResult<RgbImage> {
+        Ok(self.thumbnail_of(&self.image(config)?, resolution))
     }
 
-    /// Not cached.
-    pub fn custom_preview(&self, config: &GalleryConfig, resolution: u32) -> RgbImage {
-        resize_image(&self.image(config), resolution)
+    /// From the result of `image`, which can be reused.
+    pub fn thumbnail_of(&self, image: &RgbImage, resolution: u32) -> RgbImage {
+        generate_thumbnail(image, resolution, &self.config)
+    }
+
+    /// From the result of `image`, which can be reused.
+    pub fn preview_of(&self, image: &RgbImage, resolution: u32) -> RgbImage {
+        resize_image(image, resolution)
     }
 }
 
@@ -250,6 +322,14 @@ fn generate_thumbnail(img: &RgbImage, resolution: u32, photo_config: &PhotoConfi
     imageops::resize(&*cropped, resolution, resolution, FilterType::Lanczos3)
 }
 
+/// The dimensions of an image after `resize_image`.
+pub fn resized_dimensions((width, height): (u32, u32), resolution: u32) -> (u32, u32) {
+    if width <= resolution && height <= resolution {
+        return (width, height);
+    }
+    resize_dimensions(width, height, resolution, resolution)
+}
+
 fn resize_image(img: &RgbImage, resolution: u32) -> RgbImage {
     if img.width() <= resolution && img.height() <= resolution {
         return img.clone();
@@ -257,3 +337,8 @@ fn resize_image(img: &RgbImage, resolution: u32) -> RgbImage {
     let (width, height) = resize_dimensions(img.width(), img.height(), resolution, resolution);
     imageops::resize(img, width, height, FilterType::Lanczos3)
 }
+
+/// Tags are case-insensitive.
+pub fn tag_slug(tag: &str) -> String {
+    tag.trim().to_lowercase().replace(' ', "-")
+}
@@ -0,0 +1,1006 @@
+use crate::gallery::RawMode;
+use image::{
+    codecs::jpeg::JpegDecoder,
+    error::{DecodingError, ImageFormatHint},
+    metadata::Orientation,
+    ColorType, ImageDecoder, ImageError, ImageResult,
+};
+use rayon::{
+    iter::{IndexedParallelIterator, ParallelIterator},
+    slice::ParallelSliceMut,
+};
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::{Cursor, Read, Seek, SeekFrom},
+    path::Path,
+};
+
+/// Extensions of supported camera RAW files, in lowercase.
+pub const RAW_EXTENSIONS: [&str; 5] = ["arw", "cr2", "cr3", "dng", "nef"];
+
+pub fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| RAW_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// Decodes camera RAW files, by developing the sensor data (only if it's
+/// uncompressed) or using the largest JPEG preview embedded by the camera.
+pub struct RawDecoder {
+    image: RawImage,
+    /// TIFF-formatted, like the EXIF of a JPEG. Except for CR3, this is
+    /// the whole file, which the sensor data is read from.
+    exif: Option<Vec<u8>>,
+}
+
+enum RawImage {
+    Preview(JpegDecoder<Cursor<Vec<u8>>>),
+    Developed(Sensor),
+}
+
+impl RawDecoder {
+    pub fn new(data: Vec<u8>, mode: RawMode) -> ImageResult<Self> {
+        if data.get(4..12) == Some(b"ftypcrx ".as_slice()) {
+            let (preview, _) =
+                cr3_preview(&data, find_box).ok_or_else(|| error("no JPEG preview"))?;
+            return Ok(Self {
+                image: RawImage::Preview(JpegDecoder::new(Cursor::new(preview.to_vec()))?),
+                exif: cr3_exif(&data),
+            });
+        }
+
+        let tiff = Tiff::new(&data).ok_or_else(|| error("unsupported RAW format"))?;
+        let ifds = tiff.ifds();
+        let sensor = match mode {
+            RawMode::Develop => Sensor::new(&tiff, &ifds),
+            RawMode::Preview => None,
+        };
+        let image = match sensor {
+            Some(sensor) => RawImage::Developed(sensor),
+            None => {
+                let preview = tiff_preview(&tiff, &ifds).ok_or_else(|| error("no JPEG preview"))?;
+                RawImage::Preview(JpegDecoder::new(Cursor::new(preview.to_vec()))?)
+            }
+        };
+        Ok(Self {
+            image,
+            exif: Some(data),
+        })
+    }
+}
+
+/// Bytes read from the start of a RAW file to find its dimensions, which
+/// is enough for the IFDs (or CR3 boxes) of cameras seen so far.
+const HEADER_SIZE: u64 = 1 << 20;
+/// Bytes read from the start of an embedded JPEG to find its dimensions.
+const JPEG_HEADER_SIZE: u64 = 1 << 16;
+
+/// Of the image [`RawDecoder`] decodes, after orientation. Only reads the
+/// headers, unless they're unusually large.
+pub fn raw_dimensions(path: &Path, mode: RawMode) -> ImageResult<(u32, u32)> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len() as usize;
+    let mut header = Vec::new();
+    (&mut file).take(HEADER_SIZE).read_to_end(&mut header)?;
+    let jpeg_dimensions_at = |offset: usize| {
+        header.get(offset..).and_then(jpeg_dimensions).or_else(|| {
+            (&file).seek(SeekFrom::Start(offset as u64)).ok()?;
+            let mut jpeg = Vec::new();
+            (&file).take(JPEG_HEADER_SIZE).read_to_end(&mut jpeg).ok()?;
+            jpeg_dimensions(&jpeg)
+        })
+    };
+    if let Some(dimensions) = header_dimensions(&header, len, mode, jpeg_dimensions_at) {
+        return Ok(dimensions);
+    }
+    // Also reports why it can't be decoded, if it can't.
+    let mut decoder = RawDecoder::new(fs::read(path)?, mode)?;
+    let (width, height) = decoder.dimensions();
+    Ok(match decoder.orientation()? {
+        Orientation::Rotate90
+        | Orientation::Rotate90FlipH
+        | Orientation::Rotate270
+        | Orientation::Rotate270FlipH => (height, width),
+        _ => (width, height),
+    })
+}
+
+/// Like [`raw_dimensions`], given the start of a file `len` bytes long, and
+/// a way to find the dimensions of the JPEG at an offset in it.
+fn header_dimensions(
+    header: &[u8],
+    len: usize,
+    mode: RawMode,
+    mut jpeg_dimensions_at: impl FnMut(usize) -> Option<(u32, u32)>,
+) -> Option<(u32, u32)> {
+    let ((width, height), orientation) = if header.get(4..12) == Some(b"ftypcrx ".as_slice()) {
+        let (_, dimensions) = cr3_preview(header, find_box_start)?;
+        let ifd0 = Tiff::new(find_box(header, b"CMT1")?)?;
+        let (ifd0_entries, _) = ifd0.ifd(ifd0.u32(4)? as usize)?;
+        (dimensions, ifd0.int(&ifd0_entries, ORIENTATION))
+    } else {
+        let mut tiff = Tiff::new(header)?;
+        tiff.len = len;
+        let ifds = tiff.ifds();
+        let sensor = match mode {
+            RawMode::Develop => Sensor::new(&tiff, &ifds),
+            RawMode::Preview => None,
+        };
+        let dimensions = match sensor {
+            Some(sensor) => (sensor.width as u32, sensor.height as u32),
+            None => tiff_previews(&tiff, &ifds)
+                .into_iter()
+                .filter_map(|(offset, _)| jpeg_dimensions_at(offset))
+                .max_by_key(|(width, height)| *width as u64 * *height as u64)?,
+        };
+        (dimensions, tiff.int(ifds.first()?, ORIENTATION))
+    };
+    Some(if matches!(orientation, Some(5..=8)) {
+        (height, width)
+    } else {
+        (width, height)
+    })
+}
+
+impl ImageDecoder for RawDecoder {
+    fn dimensions(&self) -> (u32, u32) {
+        match &self.image {
+            RawImage::Preview(decoder) => decoder.dimensions(),
+            RawImage::Developed(sensor) => (sensor.width as u32, sensor.height as u32),
+        }
+    }
+
+    fn color_type(&self) -> ColorType {
+        match &self.image {
+            RawImage::Preview(decoder) => decoder.color_type(),
+            RawImage::Developed(_) => ColorType::Rgb8,
+        }
+    }
+
+    fn exif_metadata(&mut self) -> ImageResult<Option<Vec<u8>>> {
+        Ok(self.exif.clone())
+    }
+
+    fn read_image(self, buf: &mut [u8]) -> ImageResult<()> {
+        match self.image {
+            RawImage::Preview(decoder) => decoder.read_image(buf),
+            RawImage::Developed(sensor) => {
+                sensor.develop(self.exif.as_deref().unwrap_or_default(), buf);
+                Ok(())
+            }
+        }
+    }
+
+    fn read_image_boxed(self: Box<Self>, buf: &mut [u8]) -> ImageResult<()> {
+        (*self).read_image(buf)
+    }
+}
+
+fn error(message: &'static str) -> ImageError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("RAW".to_owned()),
+        message,
+    ))
+}
+
+const NEW_SUBFILE_TYPE: u16 = 0x00FE;
+const IMAGE_WIDTH: u16 = 0x0100;
+const IMAGE_LENGTH: u16 = 0x0101;
+const BITS_PER_SAMPLE: u16 = 0x0102;
+const COMPRESSION: u16 = 0x0103;
+const PHOTOMETRIC_INTERPRETATION: u16 = 0x0106;
+const STRIP_OFFSETS: u16 = 0x0111;
+const ORIENTATION: u16 = 0x0112;
+const SAMPLES_PER_PIXEL: u16 = 0x0115;
+const STRIP_BYTE_COUNTS: u16 = 0x0117;
+const SUB_IFDS: u16 = 0x014A;
+const JPEG_OFFSET: u16 = 0x0201;
+const JPEG_LENGTH: u16 = 0x0202;
+const CFA_REPEAT_PATTERN_DIM: u16 = 0x828D;
+const CFA_PATTERN: u16 = 0x828E;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const INTEROPERABILITY_IFD: u16 = 0xA005;
+const BLACK_LEVEL: u16 = 0xC61A;
+const WHITE_LEVEL: u16 = 0xC61D;
+const COLOR_MATRIX_1: u16 = 0xC621;
+const AS_SHOT_NEUTRAL: u16 = 0xC628;
+const CR2_SLICE: u16 = 0xC640;
+const ACTIVE_AREA: u16 = 0xC68D;
+
+const PHOTOMETRIC_CFA: usize = 32803;
+const PHOTOMETRIC_LINEAR_RAW: usize = 34892;
+
+/// Just enough of TIFF, which most RAW formats are based on, to find
+/// the images and metadata.
+struct Tiff<'a> {
+    data: &'a [u8],
+    /// Of the whole file, which `data` may only be the start of.
+    len: usize,
+    little_endian: bool,
+}
+
+struct Entry<'a> {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    value: &'a [u8],
+}
+
+/// Entries of an image file directory.
+type Ifd<'a> = Vec<Entry<'a>>;
+
+fn find<'a, 'b>(ifd: &'b Ifd<'a>, tag: u16) -> Option<&'b Entry<'a>> {
+    ifd.iter().find(|entry| entry.tag == tag)
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(..4)? {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(Self {
+            data,
+            len: data.len(),
+            little_endian,
+        })
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(2)?)?
+            .try_into()
+            .ok()?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(bytes)
+        } else {
+            u16::from_be_bytes(bytes)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(4)?)?
+            .try_into()
+            .ok()?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        })
+    }
+
+    /// Also returns the offset of the next IFD, or 0 if there isn't one.
+    fn ifd(&self, offset: usize) -> Option<(Ifd<'a>, usize)> {
+        let count = self.u16(offset)? as usize;
+        let mut entries = Vec::with_capacity(count);
+        for i in 0..count {
+            let start = offset + 2 + i * 12;
+            let kind = self.u16(start + 2)?;
+            let count = self.u32(start + 4)? as usize;
+            let Some(size) = type_size(kind).checked_mul(count) else {
+                continue;
+            };
+            let value_offset = if size <= 4 {
+                start + 8
+            } else {
+                self.u32(start + 8)? as usize
+            };
+            // Skip entries that are out of bounds, rather than the whole IFD.
+            let Some(value) = value_offset
+                .checked_add(size)
+                .and_then(|end| self.data.get(value_offset..end))
+            else {
+                continue;
+            };
+            entries.push(Entry {
+                tag: self.u16(start)?,
+                kind,
+                count,
+                value,
+            });
+        }
+        let next = self.u32(offset + 2 + count * 12)? as usize;
+        Some((entries, next))
+    }
+
+    /// Every IFD, including sub-IFDs.
+    fn ifds(&self) -> Vec<Ifd<'a>> {
+        let mut ret = Vec::new();
+        let mut visited = HashSet::new();
+        let mut queue = vec![self.u32(4).unwrap_or(0) as usize];
+        while let Some(offset) = queue.pop() {
+            // Guard against cycles and garbage.
+            if offset == 0 || ret.len() >= 64 || !visited.insert(offset) {
+                continue;
+            }
+            let Some((ifd, next)) = self.ifd(offset) else {
+                continue;
+            };
+            queue.push(next);
+            if let Some(sub_ifds) = find(&ifd, SUB_IFDS) {
+                queue.extend(self.values(sub_ifds).into_iter().map(|o| o as usize));
+            }
+            ret.push(ifd);
+        }
+        ret
+    }
+
+    /// Numeric values, of any type.
+    fn values(&self, entry: &Entry) -> Vec<f64> {
+        let value = Tiff {
+            data: entry.value,
+            len: entry.value.len(),
+            little_endian: self.little_endian,
+        };
+        (0..entry.count)
+            .filter_map(|i| {
+                Some(match entry.kind {
+                    1 | 7 => *entry.value.get(i)? as f64,
+                    6 => *entry.value.get(i)? as i8 as f64,
+                    3 => value.u16(i * 2)? as f64,
+                    8 => value.u16(i * 2)? as i16 as f64,
+                    4 | 13 => value.u32(i * 4)? as f64,
+                    9 => value.u32(i * 4)? as i32 as f64,
+                    5 => value.u32(i * 8)? as f64 / value.u32(i * 8 + 4)? as f64,
+                    10 => value.u32(i * 8)? as i32 as f64 / value.u32(i * 8 + 4)? as i32 as f64,
+                    11 => f32::from_bits(value.u32(i * 4)?) as f64,
+                    _ => return None,
+                })
+            })
+            .filter(|value| value.is_finite())
+            .collect()
+    }
+
+    fn int(&self, ifd: &Ifd, tag: u16) -> Option<usize> {
+        self.values(find(ifd, tag)?)
+            .first()
+            .map(|value| *value as usize)
+    }
+}
+
+fn type_size(kind: u16) -> usize {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 | 13 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+/// The largest baseline JPEG referenced by any IFD.
+fn tiff_preview<'a>(tiff: &Tiff<'a>, ifds: &[Ifd<'a>]) -> Option<&'a [u8]> {
+    tiff_previews(tiff, ifds)
+        .into_iter()
+        .filter_map(|(offset, length)| tiff.data.get(offset..offset.checked_add(length)?))
+        .filter_map(|jpeg| Some((jpeg, jpeg_dimensions(jpeg)?)))
+        .max_by_key(|(_, (width, height))| *width as u64 * *height as u64)
+        .map(|(jpeg, _)| jpeg)
+}
+
+/// Offsets and lengths of the JPEGs referenced by any IFD.
+fn tiff_previews(tiff: &Tiff, ifds: &[Ifd]) -> Vec<(usize, usize)> {
+    ifds.iter()
+        .flat_map(|ifd| {
+            let thumbnail = tiff.int(ifd, JPEG_OFFSET).zip(tiff.int(ifd, JPEG_LENGTH));
+            let strip = (matches!(tiff.int(ifd, COMPRESSION), Some(6 | 7))
+                && find(ifd, CR2_SLICE).is_none()
+                && !matches!(
+                    tiff.int(ifd, PHOTOMETRIC_INTERPRETATION),
+                    Some(PHOTOMETRIC_CFA | PHOTOMETRIC_LINEAR_RAW)
+                ))
+            .then(|| {
+                let offsets = tiff.values(find(ifd, STRIP_OFFSETS)?);
+                let counts = tiff.values(find(ifd, STRIP_BYTE_COUNTS)?);
+                match (offsets.as_slice(), counts.as_slice()) {
+                    ([offset], [count]) => Some((*offset as usize, *count as usize)),
+                    _ => None,
+                }
+            })
+            .flatten();
+            [thumbnail, strip]
+        })
+        .flatten()
+        .collect()
+}
+
+/// Dimensions of a baseline or progressive JPEG, which excludes the
+/// lossless JPEG some cameras use for sensor data.
+fn jpeg_dimensions(data: &[u8]) -> Option<(u32, u32)> {
+    if data.get(..2)? != [0xFF, 0xD8] {
+        return None;
+    }
+    let u16_at = |offset: usize| {
+        Some(u16::from_be_bytes([
+            *data.get(offset)?,
+            *data.get(offset + 1)?,
+        ]))
+    };
+    let mut offset = 2;
+    loop {
+        if *data.get(offset)? != 0xFF {
+            return None;
+        }
+        match *data.get(offset + 1)? {
+            // Padding.
+            0xFF => offset += 1,
+            0xC0..=0xC2 => {
+                let height = u16_at(offset + 5)?;
+                let width = u16_at(offset + 7)?;
+                return (width > 0 && height > 0).then_some((width.into(), height.into()));
+            }
+            // Other frame types, or the image data without a frame.
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF | 0xDA => return None,
+            _ => offset += 2 + u16_at(offset + 2)? as usize,
+        }
+    }
+}
+
+/// Contents of the first ISO base media box of type `kind`, wherever
+/// it is nested.
+fn find_box<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let start = data
+        .windows(4)
+        .position(|window| window == kind)?
+        .checked_sub(4)?;
+    let size = u32::from_be_bytes(data[start..start + 4].try_into().unwrap()) as usize;
+    data.get(start + 8..start.checked_add(size)?)
+}
+
+/// Like [`find_box`], but the contents may be cut off by the end of `data`.
+fn find_box_start<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    let start = data
+        .windows(4)
+        .position(|window| window == kind)?
+        .checked_sub(4)?;
+    let size = u32::from_be_bytes(data[start..start + 4].try_into().unwrap()) as usize;
+    data.get(start + 8..start.checked_add(size)?.min(data.len()))
+}
+
+/// The larger of Canon's preview and thumbnail, and its dimensions.
+fn cr3_preview<'a>(
+    data: &'a [u8],
+    find_box: fn(&'a [u8], &[u8; 4]) -> Option<&'a [u8]>,
+) -> Option<(&'a [u8], (u32, u32))> {
+    [b"PRVW", b"THMB"]
+        .into_iter()
+        .filter_map(|kind| {
+            let contents = find_box(data, kind)?;
+            let start = contents
+                .windows(3)
+                .position(|window| window == [0xFF, 0xD8, 0xFF])?;
+            let jpeg = &contents[start..];
+            Some((jpeg, jpeg_dimensions(jpeg)?))
+        })
+        .max_by_key(|(_, (width, height))| *width as u64 * *height as u64)
+}
+
+/// Canon stores IFD0 and the EXIF IFD as separate TIFFs, so merge them.
+fn cr3_exif(data: &[u8]) -> Option<Vec<u8>> {
+    let ifd0 = Tiff::new(find_box(data, b"CMT1")?)?;
+    let (ifd0_entries, _) = ifd0.ifd(ifd0.u32(4)? as usize)?;
+    let exif_entries = find_box(data, b"CMT2")
+        .and_then(Tiff::new)
+        .filter(|exif| exif.little_endian == ifd0.little_endian)
+        .and_then(|exif| Some(exif.ifd(exif.u32(4)? as usize)?.0))
+        .unwrap_or_default();
+
+    let mut ret = if ifd0.little_endian {
+        b"II*\0\x08\0\0\0".to_vec()
+    } else {
+        b"MM\0*\0\0\0\x08".to_vec()
+    };
+    let pointer = write_ifd(
+        &mut ret,
+        &ifd0_entries,
+        ifd0.little_endian,
+        !exif_entries.is_empty(),
+    );
+    if let Some(pointer) = pointer {
+        let offset = ret.len() as u32;
+        ret[pointer..pointer + 4].copy_from_slice(&if ifd0.little_endian {
+            offset.to_le_bytes()
+        } else {
+            offset.to_be_bytes()
+        });
+        write_ifd(&mut ret, &exif_entries, ifd0.little_endian, false);
+    }
+    Some(ret)
+}
+
+/// Appends an IFD, with its values in the same byte order, leaving out
+/// pointers to other IFDs.
+///
+/// If `exif_pointer`, adds a pointer to the EXIF IFD and returns its
+/// location, to be filled in.
+fn write_ifd(
+    out: &mut Vec<u8>,
+    entries: &[Entry],
+    little_endian: bool,
+    exif_pointer: bool,
+) -> Option<usize> {
+    let u16_bytes = |value: u16| {
+        if little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    };
+    let u32_bytes = |value: u32| {
+        if little_endian {
+            value.to_le_bytes()
+        } else {
+            value.to_be_bytes()
+        }
+    };
+
+    let mut entries = entries
+        .iter()
+        .filter(|entry| ![SUB_IFDS, EXIF_IFD, GPS_IFD, INTEROPERABILITY_IFD].contains(&entry.tag))
+        .map(|entry| (entry.tag, entry.kind, entry.count as u32, entry.value))
+        .collect::<Vec<_>>();
+    if exif_pointer {
+        entries.push((EXIF_IFD, 4, 1, &[0; 4]));
+    }
+    entries.sort_by_key(|(tag, ..)| *tag);
+
+    let data_offset = out.len() + 2 + entries.len() * 12 + 4;
+    let mut data = Vec::<u8>::new();
+    let mut pointer = None;
+    out.extend(u16_bytes(entries.len() as u16));
+    for (tag, kind, count, value) in entries {
+        out.extend(u16_bytes(tag));
+        out.extend(u16_bytes(kind));
+        out.extend(u32_bytes(count));
+        if tag == EXIF_IFD {
+            pointer = Some(out.len());
+        }
+        if value.len() <= 4 {
+            let mut inline = [0; 4];
+            inline[..value.len()].copy_from_slice(value);
+            out.extend(inline);
+        } else {
+            out.extend(u32_bytes((data_offset + data.len()) as u32));
+            data.extend(value);
+            // Values start on word boundaries.
+            if data.len() % 2 == 1 {
+                data.push(0);
+            }
+        }
+    }
+    out.extend(u32_bytes(0));
+    out.extend(data);
+    pointer
+}
+
+/// Uncompressed sensor data behind a 2x2 color filter array, as in
+/// many DNG files.
+struct Sensor {
+    /// Of the active area.
+    width: usize,
+    height: usize,
+    full_width: usize,
+    /// Top left of the active area.
+    top: usize,
+    left: usize,
+    /// Offsets and lengths of the data.
+    strips: Vec<(usize, usize)>,
+    bits: usize,
+    little_endian: bool,
+    /// 0 (red), 1 (green), or 2 (blue) for the top left 2x2 pixels of
+    /// the active area, in row-major order.
+    pattern: [u8; 4],
+    black: f32,
+    white: f32,
+    /// Multiplier for each color.
+    white_balance: [f32; 3],
+    /// Camera RGB to linear sRGB.
+    color_matrix: [[f32; 3]; 3],
+}
+
+impl Sensor {
+    fn new(tiff: &Tiff, ifds: &[Ifd]) -> Option<Self> {
+        let ifd = ifds.iter().find(|ifd| {
+            tiff.int(ifd, PHOTOMETRIC_INTERPRETATION) == Some(PHOTOMETRIC_CFA)
+                && tiff.int(ifd, COMPRESSION) == Some(1)
+                && tiff.int(ifd, SAMPLES_PER_PIXEL).unwrap_or(1) == 1
+                && tiff.int(ifd, NEW_SUBFILE_TYPE).unwrap_or(0) == 0
+        })?;
+        let full_width = tiff.int(ifd, IMAGE_WIDTH)?;
+        let full_height = tiff.int(ifd, IMAGE_LENGTH)?;
+        let bits = tiff.int(ifd, BITS_PER_SAMPLE)?;
+        if !matches!(bits, 8 | 16) || tiff.values(find(ifd, CFA_REPEAT_PATTERN_DIM)?) != [2.0, 2.0]
+        {
+            return None;
+        }
+        let pattern = <[u8; 4]>::try_from(find(ifd, CFA_PATTERN)?.value).ok()?;
+        if pattern.iter().any(|color| *color > 2) {
+            return None;
+        }
+
+        let offsets = tiff.values(find(ifd, STRIP_OFFSETS)?);
+        let counts = tiff.values(find(ifd, STRIP_BYTE_COUNTS)?);
+        let strips = offsets
+            .iter()
+            .zip(&counts)
+            .map(|(offset, count)| (*offset as usize, *count as usize))
+            .collect::<Vec<_>>();
+        let length = strips.iter().map(|(_, count)| count).sum::<usize>();
+        if length < full_width * full_height * bits / 8
+            || strips
+                .iter()
+                .any(|(offset, count)| offset.checked_add(*count).is_none_or(|end| end > tiff.len))
+        {
+            return None;
+        }
+
+        let (top, left, bottom, right) = match find(ifd, ACTIVE_AREA)
+            .map(|entry| tiff.values(entry))
+            .as_deref()
+        {
+            Some(&[top, left, bottom, right]) => (
+                top as usize,
+                left as usize,
+                (bottom as usize).min(full_height),
+                (right as usize).min(full_width),
+            ),
+            _ => (0, 0, full_height, full_width),
+        };
+        if top >= bottom || left >= right {
+            return None;
+        }
+        let pattern = [0, 1, 2, 3].map(|i| pattern[(i / 2 + top) % 2 * 2 + (i % 2 + left) % 2]);
+
+        let black_levels = find(ifd, BLACK_LEVEL)
+            .map(|entry| tiff.values(entry))
+            .unwrap_or_default();
+        let black = if black_levels.is_empty() {
+            0.0
+        } else {
+            black_levels.iter().sum::<f64>() / black_levels.len() as f64
+        };
+        let white = tiff
+            .int(ifd, WHITE_LEVEL)
+            .map(|white| white as f64)
+            .unwrap_or(((1u32 << bits) - 1) as f64);
+
+        // Usually in IFD0, rather than that of the sensor data.
+        let find_any = |tag| {
+            ifds.iter()
+                .find_map(|ifd| find(ifd, tag))
+                .map(|entry| tiff.values(entry))
+        };
+        let white_balance = match find_any(AS_SHOT_NEUTRAL).as_deref() {
+            Some(&[r, g, b]) if r > 0.0 && g > 0.0 && b > 0.0 => {
+                let multipliers = [1.0 / r, 1.0 / g, 1.0 / b];
+                let min = multipliers.into_iter().fold(f64::INFINITY, f64::min);
+                multipliers.map(|m| (m / min) as f32)
+            }
+            _ => [1.0; 3],
+        };
+        let color_matrix = find_any(COLOR_MATRIX_1)
+            .and_then(|matrix| camera_to_srgb(&matrix))
+            .unwrap_or([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]]);
+
+        Some(Self {
+            width: right - left,
+            height: bottom - top,
+            full_width,
+            top,
+            left,
+            strips,
+            bits,
+            little_endian: tiff.little_endian,
+            pattern,
+            black: black as f32,
+            white: white as f32,
+            white_balance,
+            color_matrix,
+        })
+    }
+
+    /// Writes 8-bit sRGB to `buf`, reading the data from `file`.
+    fn develop(&self, file: &[u8], buf: &mut [u8]) {
+        let mut bytes = Vec::new();
+        for &(offset, count) in &self.strips {
+            bytes.extend_from_slice(&file[offset..offset + count]);
+        }
+        let sample = |x: usize, y: usize| {
+            let i = (y + self.top) * self.full_width + x + self.left;
+            match self.bits {
+                8 => bytes[i] as f32,
+                _ => {
+                    let pair = [bytes[i * 2], bytes[i * 2 + 1]];
+                    (if self.little_endian {
+                        u16::from_le_bytes(pair)
+                    } else {
+                        u16::from_be_bytes(pair)
+                    }) as f32
+                }
+            }
+        };
+        let range = (self.white - self.black).max(1.0);
+        let color = |x: usize, y: usize| self.pattern[y % 2 * 2 + x % 2] as usize;
+        let value = |x: usize, y: usize| {
+            ((sample(x, y) - self.black).max(0.0) / range * self.white_balance[color(x, y)])
+                .min(1.0)
+        };
+
+        buf.par_chunks_exact_mut(self.width * 3)
+            .enumerate()
+            .for_each(|(y, row)| {
+                for x in 0..self.width {
+                    // Bilinear demosaic, averaging the neighbors of each color.
+                    let mut sums = [0.0f32; 3];
+                    let mut counts = [0u32; 3];
+                    for ny in y.saturating_sub(1)..(y + 2).min(self.height) {
+                        for nx in x.saturating_sub(1)..(x + 2).min(self.width) {
+                            sums[color(nx, ny)] += value(nx, ny);
+                            counts[color(nx, ny)] += 1;
+                        }
+                    }
+                    let own = color(x, y);
+                    let camera = [0, 1, 2].map(|c| {
+                        if c == own {
+                            value(x, y)
+                        } else if counts[c] > 0 {
+                            sums[c] / counts[c] as f32
+                        } else {
+                            0.0
+                        }
+                    });
+                    for (c, output) in row[x * 3..x * 3 + 3].iter_mut().enumerate() {
+                        let linear = (0..3)
+                            .map(|k| self.color_matrix[c][k] * camera[k])
+                            .sum::<f32>();
+                        *output = (srgb_gamma(linear.clamp(0.0, 1.0)) * 255.0).round() as u8;
+                    }
+                }
+            });
+    }
+}
+
+/// From DNG's `ColorMatrix1`, which maps XYZ to camera RGB.
+fn camera_to_srgb(xyz_to_camera: &[f64]) -> Option<[[f32; 3]; 3]> {
+    if xyz_to_camera.len() != 9 {
+        return None;
+    }
+    const SRGB_TO_XYZ: [[f64; 3]; 3] = [
+        [0.4124564, 0.3575761, 0.1804375],
+        [0.2126729, 0.7151522, 0.0721750],
+        [0.0193339, 0.1191920, 0.9503041],
+    ];
+    let mut srgb_to_camera = [[0.0; 3]; 3];
+    for (i, row) in srgb_to_camera.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3)
+                .map(|k| xyz_to_camera[i * 3 + k] * SRGB_TO_XYZ[k][j])
+                .sum();
+        }
+        // So that white balanced white stays white.
+        let sum = row.iter().sum::<f64>();
+        if sum.abs() < 1e-6 {
+            return None;
+        }
+        row.iter_mut().for_each(|value| *value /= sum);
+    }
+    invert(srgb_to_camera).map(|matrix| matrix.map(|row| row.map(|value| value as f32)))
+}
+
+fn invert(m: [[f64; 3]; 3]) -> Option<[[f64; 3]; 3]> {
+    let cofactor = |i: usize, j: usize| {
+        let (r0, r1) = ((i + 1) % 3, (i + 2) % 3);
+        let (c0, c1) = ((j + 1) % 3, (j + 2) % 3);
+        m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+    };
+    let determinant = (0..3).map(|j| m[0][j] * cofactor(0, j)).sum::<f64>();
+    if determinant.abs() < 1e-9 {
+        return None;
+    }
+    // Transpose of the cofactors.
+    Some([0, 1, 2].map(|i| [0, 1, 2].map(|j| cofactor(j, i) / determinant)))
+}
+
+fn srgb_gamma(linear: f32) -> f32 {
+    if linear <= 0.0031308 {
+        12.92 * linear
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A TIFF with one IFD of `entries`, which are tag, type, count, and
+    /// value (in the given byte order).
+    fn tiff(little_endian: bool, entries: &[(u16, u16, usize, &[u8])]) -> Vec<u8> {
+        let entries = entries
+            .iter()
+            .map(|&(tag, kind, count, value)| Entry {
+                tag,
+                kind,
+                count,
+                value,
+            })
+            .collect::<Vec<_>>();
+        let mut ret = if little_endian {
+            b"II*\0\x08\0\0\0".to_vec()
+        } else {
+            b"MM\0*\0\0\0\x08".to_vec()
+        };
+        write_ifd(&mut ret, &entries, little_endian, false);
+        ret
+    }
+
+    /// The start of a baseline JPEG, up to its dimensions.
+    fn jpeg(width: u16, height: u16) -> Vec<u8> {
+        let mut ret = vec![0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00];
+        ret.extend([0xFF, 0xC0, 0x00, 0x11, 0x08]);
+        ret.extend(height.to_be_bytes());
+        ret.extend(width.to_be_bytes());
+        ret
+    }
+
+    #[test]
+    fn rejects_other_formats() {
+        assert!(Tiff::new(b"").is_none());
+        assert!(Tiff::new(b"II*").is_none());
+        assert!(Tiff::new(b"\xFF\xD8\xFF\xE0").is_none());
+        assert!(RawDecoder::new(b"not a raw file".to_vec(), RawMode::Preview).is_err());
+    }
+
+    #[test]
+    fn parses_ifd() {
+        for little_endian in [true, false] {
+            let u16_bytes = |value: u16| {
+                if little_endian {
+                    value.to_le_bytes()
+                } else {
+                    value.to_be_bytes()
+                }
+            };
+            let width = u16_bytes(640);
+            let matrix = [1u16, 2, 3, 4].map(u16_bytes).concat();
+            let data = tiff(
+                little_endian,
+                &[(IMAGE_WIDTH, 3, 1, &width), (BLACK_LEVEL, 3, 4, &matrix)],
+            );
+            let tiff = Tiff::new(&data).unwrap();
+            assert_eq!(tiff.little_endian, little_endian);
+            let (ifd, next) = tiff.ifd(8).unwrap();
+            assert_eq!(next, 0);
+            assert_eq!(ifd.len(), 2);
+            assert_eq!(tiff.int(&ifd, IMAGE_WIDTH), Some(640));
+            assert_eq!(
+                tiff.values(find(&ifd, BLACK_LEVEL).unwrap()),
+                [1.0, 2.0, 3.0, 4.0]
+            );
+            assert_eq!(tiff.ifds().len(), 1);
+        }
+    }
+
+    #[test]
+    fn skips_out_of_bounds_values() {
+        let mut data = tiff(true, &[(IMAGE_WIDTH, 4, 1, &640u32.to_le_bytes())]);
+        // An entry of 1000 shorts, far past the end.
+        data.extend([0; 12]);
+        data[8] = 2;
+        data[22..34].copy_from_slice(
+            &[
+                BLACK_LEVEL.to_le_bytes().as_slice(),
+                &3u16.to_le_bytes(),
+                &1000u32.to_le_bytes(),
+                &u32::MAX.to_le_bytes(),
+            ]
+            .concat(),
+        );
+        data.extend([0; 4]);
+        let tiff = Tiff::new(&data).unwrap();
+        let (ifd, _) = tiff.ifd(8).unwrap();
+        assert_eq!(ifd.len(), 1);
+        assert_eq!(tiff.int(&ifd, IMAGE_WIDTH), Some(640));
+    }
+
+    #[test]
+    fn truncated_input_is_an_error() {
+        let mut data = tiff(
+            true,
+            &[
+                (IMAGE_WIDTH, 4, 1, &640u32.to_le_bytes()),
+                (IMAGE_LENGTH, 4, 1, &480u32.to_le_bytes()),
+                (JPEG_OFFSET, 4, 1, &0u32.to_le_bytes()),
+                (
+                    JPEG_LENGTH,
+                    4,
+                    1,
+                    &(jpeg(64, 48).len() as u32).to_le_bytes(),
+                ),
+            ],
+        );
+        let offset = (data.len() as u32).to_le_bytes();
+        data[8 + 2 + 2 * 12 + 8..][..4].copy_from_slice(&offset);
+        data.extend(jpeg(64, 48));
+        let jpeg_dimensions_at = |at: usize| jpeg_dimensions(data.get(at..)?);
+        assert_eq!(
+            header_dimensions(&data, data.len(), RawMode::Develop, jpeg_dimensions_at),
+            Some((64, 48))
+        );
+        for len in 0..data.len() {
+            let truncated = &data[..len];
+            if let Some(tiff) = Tiff::new(truncated) {
+                tiff.ifds();
+            }
+            header_dimensions(truncated, len, RawMode::Develop, |_| None);
+            jpeg_dimensions(truncated);
+            assert!(RawDecoder::new(truncated.to_vec(), RawMode::Preview).is_err());
+        }
+        assert!(Tiff::new(&data[..12]).unwrap().ifd(8).is_none());
+    }
+
+    #[test]
+    fn ignores_cyclic_ifds() {
+        let mut data = tiff(true, &[(IMAGE_WIDTH, 4, 1, &640u32.to_le_bytes())]);
+        // The next IFD is itself.
+        let next = data.len() - 4;
+        data[next..].copy_from_slice(&8u32.to_le_bytes());
+        assert_eq!(Tiff::new(&data).unwrap().ifds().len(), 1);
+    }
+
+    #[test]
+    fn finds_jpeg_dimensions() {
+        assert_eq!(jpeg_dimensions(&jpeg(6000, 4000)), Some((6000, 4000)));
+        assert_eq!(jpeg_dimensions(&jpeg(0, 4000)), None);
+        // Lossless, as used for sensor data.
+        let mut lossless = jpeg(6000, 4000);
+        lossless[9] = 0xC3;
+        assert_eq!(jpeg_dimensions(&lossless), None);
+    }
+
+    #[test]
+    fn finds_preview_dimensions_in_header() {
+        let preview = jpeg(1620, 1080);
+        let mut data = tiff(
+            true,
+            &[
+                (JPEG_OFFSET, 4, 1, &0u32.to_le_bytes()),
+                (JPEG_LENGTH, 4, 1, &(preview.len() as u32).to_le_bytes()),
+                (ORIENTATION, 3, 1, &[6, 0]),
+            ],
+        );
+        let offset = data.len();
+        // After orientation, as entries are sorted by tag.
+        data[8 + 2 + 12 + 8..][..4].copy_from_slice(&(offset as u32).to_le_bytes());
+        data.extend(&preview);
+
+        // Read from the header, or from the rest of the file.
+        let dimensions = header_dimensions(&data, data.len(), RawMode::Preview, |at| {
+            jpeg_dimensions(data.get(at..)?)
+        });
+        assert_eq!(dimensions, Some((1080, 1620)));
+        let dimensions = header_dimensions(&data[..offset], 1 << 30, RawMode::Develop, |at| {
+            assert_eq!(at, offset);
+            Some((1620, 1080))
+        });
+        assert_eq!(dimensions, Some((1080, 1620)));
+    }
+
+    #[test]
+    fn finds_boxes() {
+        let mut data = b"\0\0\0\x10ftypcrx \0\0\0\0".to_vec();
+        data.extend(b"\0\0\0\x0cCMT1abcd");
+        assert_eq!(find_box(&data, b"CMT1"), Some(b"abcd".as_slice()));
+        assert_eq!(find_box(&data[..26], b"CMT1"), None);
+        assert_eq!(find_box_start(&data[..26], b"CMT1"), Some(b"ab".as_slice()));
+        assert_eq!(find_box(&data, b"PRVW"), None);
+        // Found at the very start, without room for the size.
+        assert_eq!(find_box(b"PRVW", b"PRVW"), None);
+    }
+}
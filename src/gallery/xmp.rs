@@ -0,0 +1,148 @@
+use crate::{diagnostics::Diagnostics, gallery::PhotoConfig};
+use std::{fs, io::ErrorKind, path::Path, path::PathBuf};
+use xmp_toolkit::{xmp_ns, OpenFileOptions, XmpFile, XmpMeta};
+
+/// Metadata set by other tools (e.g. Lightroom or darktable), embedded
+/// in the photo as XMP or IPTC, or in a sidecar file.
+#[derive(Debug, Default, Clone)]
+pub struct XmpData {
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub location: Option<String>,
+    pub keywords: Vec<String>,
+    /// -1 (rejected) to 5.
+    pub rating: Option<i32>,
+}
+
+impl XmpData {
+    /// The sidecar, if any, takes precedence over embedded metadata.
+    ///
+    /// Reports unreadable sidecars to `diagnostics`.
+    pub fn read(path: &Path, diagnostics: &Diagnostics) -> Self {
+        let mut sidecar = None;
+        for sidecar_path in sidecar_paths(path) {
+            match fs::read_to_string(&sidecar_path) {
+                Ok(text) => match text.parse::<XmpMeta>() {
+                    Ok(meta) => {
+                        sidecar = Some(Self::new(&meta));
+                        break;
+                    }
+                    Err(e) => diagnostics.warning(&sidecar_path, format!("couldn't parse: {e}")),
+                },
+                Err(e) if e.kind() == ErrorKind::NotFound => {}
+                Err(e) => diagnostics.warning(&sidecar_path, format!("couldn't read: {e}")),
+            }
+        }
+
+        // The smart handler also reconciles legacy IPTC into XMP.
+        let embedded = XmpFile::new()
+            .ok()
+            .and_then(|mut file| {
+                file.open_file(
+                    path,
+                    OpenFileOptions::default()
+                        .for_read()
+                        .only_xmp()
+                        .use_smart_handler(),
+                )
+                .ok()?;
+                file.xmp()
+            })
+            .map(|meta| Self::new(&meta))
+            .unwrap_or_default();
+
+        match sidecar {
+            Some(sidecar) => sidecar.or(embedded),
+            None => embedded,
+        }
+    }
+
+    fn new(meta: &XmpMeta) -> Self {
+        let text = |namespace: &str, path: &str| {
+            meta.property(namespace, path)
+                .map(|value| value.value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+        };
+        let localized = |path: &str| {
+            meta.localized_text(xmp_ns::DC, path, None, "x-default")
+                .map(|(value, _)| value.value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+        };
+
+        let location = [
+            text(xmp_ns::IPTC_CORE, "Location"),
+            text(xmp_ns::PHOTOSHOP, "City"),
+            text(xmp_ns::PHOTOSHOP, "State"),
+            text(xmp_ns::PHOTOSHOP, "Country"),
+        ]
+        .into_iter()
+        .flatten()
+        .collect::<Vec<_>>();
+
+        Self {
+            title: localized("title"),
+            description: meta
+                .localized_text(xmp_ns::IPTC_CORE, "AltTextAccessibility", None, "x-default")
+                .map(|(value, _)| value.value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+                .or_else(|| localized("description")),
+            location: (!location.is_empty()).then(|| location.join(", ")),
+            keywords: meta
+                .property_array(xmp_ns::DC, "subject")
+                .map(|value| value.value.trim().to_owned())
+                .filter(|value| !value.is_empty())
+                .collect(),
+            rating: meta
+                .property_i32(xmp_ns::XMP, "Rating")
+                .map(|value| value.value),
+        }
+    }
+
+    /// Fill in missing fields from `other`.
+    fn or(self, other: Self) -> Self {
+        Self {
+            title: self.title.or(other.title),
+            description: self.description.or(other.description),
+            location: self.location.or(other.location),
+            keywords: if self.keywords.is_empty() {
+                other.keywords
+            } else {
+                self.keywords
+            },
+            rating: self.rating.or(other.rating),
+        }
+    }
+}
+
+impl PhotoConfig {
+    /// Use `xmp` for anything the TOML config doesn't specify.
+    pub fn apply_xmp(&mut self, xmp: &XmpData) {
+        if self.rename.is_none() {
+            self.rename = xmp.title.clone();
+        }
+        if self.description.is_none() {
+            self.description = xmp.description.clone();
+        }
+        if self.location.is_none() {
+            self.location = xmp.location.clone();
+        }
+        if self.tags.is_empty() {
+            self.tags = xmp.keywords.clone();
+        }
+        if self.rating.is_none() {
+            self.rating = xmp
+                .rating
+                .filter(|rating| *rating >= 0)
+                .map(|rating| rating.min(5) as i8);
+            // A rating in the TOML config un-rejects the photo.
+            self.rejected |= xmp.rating == Some(-1);
+        }
+    }
+}
+
+/// darktable's `photo.jpg.xmp` and Lightroom's `photo.xmp`.
+pub fn sidecar_paths(path: &Path) -> [PathBuf; 2] {
+    let mut darktable = path.as_os_str().to_owned();
+    darktable.push(".xmp");
+    [PathBuf::from(darktable), path.with_extension("xmp")]
+}
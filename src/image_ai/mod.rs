@@ -10,7 +10,7 @@ use async_openai::types::{
     ImageUrl,
 };
 use base64::Engine;
-use image::ImageFormat;
+use image::{ImageFormat, ImageResult};
 use ollama_rs::{
     generation::{
         completion::{request::GenerationRequest, GenerationResponse},
@@ -90,11 +90,17 @@ pub fn init_image_ai(gallery: &Gallery, path: &CategoryPath, photo: &Photo, doc:
         }
     }
 
-    let summary = image_ai(
+    let summary = match image_ai(
         prompt,
         &gallery.config.image_ai_api_base_url,
         gallery.config.image_ai_api_key.as_deref(),
-    );
+    ) {
+        Ok(summary) => summary,
+        Err(e) => {
+            println!("couldn't decode {}: {e}", photo.name);
+            return;
+        }
+    };
 
     doc["description"] = toml_edit::value(summary.clone());
     doc["ai_description_input_checksum"] = toml_edit::value(input_checksum);
@@ -122,8 +128,12 @@ impl<'a> ImageAiPrompt<'a> {
     }
 }
 
-pub fn image_ai(prompt: ImageAiPrompt, base_url: &str, api_key: Option<&str>) -> String {
-    let image = prompt.photo.thumbnail(&prompt.config);
+pub fn image_ai(
+    prompt: ImageAiPrompt,
+    base_url: &str,
+    api_key: Option<&str>,
+) -> ImageResult<String> {
+    let image = prompt.photo.thumbnail(&prompt.config)?;
     let jpeg = Vec::<u8>::new();
     let mut cursor = Cursor::new(jpeg);
     image.write_to(&mut cursor, ImageFormat::Jpeg).unwrap();
@@ -137,7 +147,7 @@ pub fn image_ai(prompt: ImageAiPrompt, base_url: &str, api_key: Option<&str>) ->
 
     const TEMPERATURE: f32 = 0.25;
 
-    rt.block_on(async {
+    Ok(rt.block_on(async {
         if let Some(api_key) = api_key {
             let client = async_openai::Client::with_config(
                 async_openai::config::OpenAIConfig::new()
@@ -219,7 +229,7 @@ pub fn image_ai(prompt: ImageAiPrompt, base_url: &str, api_key: Option<&str>) ->
 
             response.response.trim().to_owned()
         }
-    })
+    }))
 }
 
 async fn send_request(
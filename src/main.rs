@@ -1,23 +1,22 @@
-use crate::gallery::Order;
 use crate::image_ai::init_image_ai;
-use chrono::NaiveDate;
+use chrono::{Local, NaiveDate};
 use clap::{Parser, Subcommand};
+use diagnostics::Diagnostics;
 use gallery::CategoryPath;
 use gallery::Photo;
-use gallery::StaticFile;
-use gallery::{CategoryConfig, GalleryConfig, PageConfig, PhotoConfig};
-use gallery::{Gallery, Item, Page, RichText, RichTextFormat};
-use output::{build, serve};
+use gallery::{Gallery, GalleryConfig, PhotoConfig};
+use image::DynamicImage;
+use output::{build, deploy, serve, Cache, ServeOptions};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
-use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
 use std::io::{ErrorKind, Write};
-use std::sync::{Mutex, OnceLock};
+use std::path::PathBuf;
 use std::time::Instant;
-use util::remove_dir_contents;
-use wax::Glob;
+use util::{move_file, progress_bar, remove_dir_contents};
 
 mod copyright_registration;
+mod diagnostics;
 mod gallery;
 mod image_ai;
 mod output;
@@ -91,11 +90,66 @@ enum Command {
         /// using a web browser that supports Speculation Rules.
         #[arg(long)]
         background: bool,
+        /// Preview the gallery as it will be published on this
+        /// date (YYYY-MM-DD), instead of today.
+        #[arg(long)]
+        as_of: Option<NaiveDate>,
+        /// Address to listen on, instead of `serve_host`.
+        #[arg(long, conflicts_with = "lan")]
+        host: Option<String>,
+        /// Port to listen on, instead of `serve_port`.
+        #[arg(long)]
+        port: Option<u16>,
+        /// Make the preview reachable from other devices on the
+        /// local network, like phones. Anyone on the network can
+        /// see unpublished photos.
+        #[arg(long)]
+        lan: bool,
+        /// Use HTTPS (as if `serve_https` were set), which PWA
+        /// features need on other devices.
+        #[arg(long)]
+        https: bool,
     },
     /// Build static gallery website.
-    Build,
+    Build {
+        /// Build the gallery as it will be published on this
+        /// date (YYYY-MM-DD), instead of today.
+        #[arg(long)]
+        as_of: Option<NaiveDate>,
+    },
+    /// Report problems with the gallery's source files
+    /// without building anything.
+    Check {
+        /// Fully decode every photo. This is slow, but
+        /// catches truncated or otherwise corrupt files.
+        #[arg(long)]
+        decode: bool,
+        /// Move orphaned configs and dangling captions into
+        /// this directory, which should be outside the input.
+        #[arg(long)]
+        quarantine: Option<PathBuf>,
+    },
     /// Clear the output directory.
     Clean,
+    /// Upload what changed since the last deploy, and delete
+    /// what is no longer built.
+    Deploy {
+        /// Name of the target in the top-level config. Optional
+        /// if there is only one.
+        target: Option<String>,
+    },
+    /// Manage the cache of encoded images.
+    Cache {
+        #[clap(subcommand)]
+        command: CacheCommand,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheCommand {
+    /// Remove images the gallery no longer uses, then the least
+    /// recently used images until the cache fits `cache_size_mb`.
+    Prune,
 }
 
 fn main() {
@@ -122,9 +176,11 @@ fn main() {
         }
     }
 
-    let config_text =
-        fs::read_to_string("./chillphoto.toml").expect("couldn't read ./chillphoto.toml");
-    let config = toml::from_str::<GalleryConfig>(&config_text).unwrap();
+    let diagnostics = Diagnostics::default();
+    let Some(config) = GalleryConfig::load(&diagnostics) else {
+        diagnostics.report();
+        std::process::exit(1);
+    };
 
     if matches!(args.command, Command::Clean) {
         if fs::exists(&config.output).unwrap() {
@@ -133,286 +189,84 @@ fn main() {
         return;
     }
 
-    let mut input_path_string = config.input.clone();
-    if let Some(remainder) = input_path_string.strip_prefix("~/") {
-        #[allow(deprecated)]
-        let home_dir = std::env::home_dir();
-        input_path_string = format!(
-            "{}/{remainder}",
-            home_dir
-                .unwrap()
-                .to_str()
-                .expect("invalid utf-8 in os-string")
-        );
-    }
-    let (root, glob) = Glob::new(&input_path_string).unwrap().partition();
-
-    struct GalleryExtras {
-        gallery: Gallery,
-        item_configs: HashMap<String, String>,
-    }
-
-    let entries = glob
-        .walk(&root)
-        .not([config.output.as_str()])
-        .unwrap()
-        .collect::<Vec<_>>();
-
-    let gallery = Mutex::new(GalleryExtras {
-        gallery: Gallery {
-            children: Vec::new(),
-            favicon: None,
-            editable: matches!(args.command, Command::Serve { .. }) && config.text_editor.is_some(),
-            config,
-            head_html: None,
-            home_text: None,
-            static_files: Vec::new(),
-            root,
-        },
-        item_configs: HashMap::new(),
-    });
-
-    entries.into_par_iter().for_each(|entry| {
-        let entry = entry.unwrap();
-        if !entry.file_type().is_file() {
-            return;
+    if let Command::Deploy { target } = &args.command {
+        deploy(start, &config, target.as_deref(), &diagnostics);
+        if !diagnostics.is_empty() {
+            diagnostics.report();
+            std::process::exit(1);
         }
-        let entire_path = entry.matched().complete().to_owned();
-        let (category_names, categories, except_name, name) =
-            if let Some((categories, name)) = entire_path.rsplit_once('/') {
-                let category_names = categories
-                    .split('/')
-                    .map(|s| s.to_owned())
-                    .collect::<Vec<String>>();
-                let path = CategoryPath::new(
-                    &category_names
-                        .iter()
-                        .map(|n| n.replace(' ', "-"))
-                        .collect::<Vec<_>>()
-                        .join("/"),
-                );
-                (category_names, path, categories, name)
-            } else {
-                (Vec::new(), CategoryPath::ROOT, "", entire_path.as_str())
-            };
-
-        if !category_names.is_empty() && category_names[0] == "static" {
-            let mut gallery = gallery.lock().unwrap();
-            gallery.gallery.static_files.push(StaticFile {
-                path: format!("/{entire_path}"),
-                contents: fs::read(entry.path()).unwrap(),
-            });
-            return;
-        }
-
-        let name_no_extension = name.rsplit_once('.').unwrap().0.to_owned();
-        let path_no_extension = if except_name.is_empty() {
-            name_no_extension.clone()
-        } else {
-            format!("{except_name}/{name_no_extension}")
-        };
-
-        if name.ends_with(".toml") {
-            let config_text = fs::read_to_string(entry.path()).expect("couldn't read photo config");
-
-            let mut gallery = gallery.lock().unwrap();
-            gallery.item_configs.insert(path_no_extension, config_text);
-            return;
-        }
-
-        let page_format = if name.ends_with(".html") {
-            Some(RichTextFormat::Html)
-        } else if name.ends_with(".md") {
-            Some(RichTextFormat::Markdown)
-        } else if name.ends_with(".txt") {
-            Some(RichTextFormat::PlainText)
-        } else {
-            None
-        };
-
-        if let Some(format) = page_format {
-            let file = std::fs::read_to_string(entry.path()).unwrap();
-            let mut gallery = gallery.lock().unwrap();
-            if name_no_extension == "head" && matches!(format, RichTextFormat::Html) {
-                gallery.gallery.head_html = Some(file);
-                return;
-            }
-            let to_insert = gallery
-                .gallery
-                .get_or_create_category(&category_names, &categories);
-            to_insert.push(Item::Page(Page {
-                name: name_no_extension,
-                text: RichText {
-                    content: file,
-                    format,
-                },
-                config: PageConfig::default(),
-                src_key: path_no_extension,
-            }));
-            return;
-        }
-
-        let metadata = fs::metadata(entry.path()).unwrap();
+        return;
+    }
 
-        let mut gallery = gallery.lock().unwrap();
-        if categories.is_root() && name_no_extension == "favicon" {
-            gallery.gallery.favicon = Some((fs::read(entry.path()).unwrap(), OnceLock::new()));
-            return;
+    // Check, describe, and keep cached images of unpublished photos too.
+    let preview = matches!(
+        args.command,
+        Command::Serve { .. }
+            | Command::Check { .. }
+            | Command::Init { .. }
+            | Command::Cache { .. }
+    );
+    // Check and describe scheduled items too.
+    let as_of = match &args.command {
+        Command::Serve { as_of, .. } | Command::Build { as_of } => {
+            Some(as_of.unwrap_or_else(|| Local::now().date_naive()))
         }
+        Command::Copyright { .. } => Some(Local::now().date_naive()),
+        _ => None,
+    };
+    let Some((mut gallery, orphans)) = Gallery::load(config, preview, as_of, &diagnostics, start)
+    else {
+        diagnostics.report();
+        std::process::exit(1);
+    };
 
-        let photo = Photo {
-            name: name_no_extension,
-            text: None,
-            exif: Default::default(),
-            config: Default::default(),
-            file_date: metadata.modified().or(metadata.created()).ok(),
-            path: entry.path().to_owned(),
-            src_key: path_no_extension,
-            parsed_config_date: None,
-            distinct_name: None,
-        };
-
-        let to_insert = gallery
-            .gallery
-            .get_or_create_category(&category_names, &categories);
-        to_insert.push(Item::Photo(photo));
-    });
-
-    let GalleryExtras {
-        mut gallery,
-        mut item_configs,
-    } = gallery.into_inner().unwrap();
-    let mut categories = 0usize;
-    let mut category_configs = 0usize;
-    let mut category_texts = 0usize;
-    let mut photos = 0usize;
-    let mut photo_configs = 0usize;
-    let mut photo_texts = 0usize;
-    let mut pages = 0usize;
-    let mut page_configs = 0usize;
-    let mut match_pages = |mut home: Option<&mut Option<RichText>>, children: &mut Vec<Item>| {
-        let mut matches = Vec::<(String, RichText)>::new();
-        for name in children
-            .iter()
-            .filter_map(|i| {
-                i.photo()
-                    .map(|p| p.name.as_str())
-                    .or(i.category().map(|c| c.name.as_str()))
-            })
-            .chain(std::iter::once("home"))
-        {
-            for page in children.iter().filter_map(|i| i.page()) {
-                if *name == page.name {
-                    matches.push((name.to_owned(), page.text.clone()));
-                }
-            }
-        }
-        for (name, text) in matches {
-            if let Some(home) = &mut home {
-                if name == "home" {
-                    **home = Some(text.clone());
+    if let Command::Check { decode, quarantine } = &args.command {
+        if *decode {
+            let mut to_decode = Vec::new();
+            gallery.visit_items(|_, item| {
+                if let Some(photo) = item.photo() {
+                    to_decode.push(photo);
                 }
-            }
-            children.retain_mut(|child| {
-                match child {
-                    Item::Photo(photo) => {
-                        if photo.name == name {
-                            photo.text = Some(text.clone());
-                            photo_texts += 1;
-                        }
-                    }
-                    Item::Category(category) => {
-                        if category.name == name {
-                            category.text = Some(text.clone());
-                            category_texts += 1;
-                        }
-                    }
-                    Item::Page(page) => {
-                        return page.name != name && (home.is_none() || page.name != "home")
-                    }
+            });
+            let progress = progress_bar("Decoding photos...", to_decode.len(), start);
+            to_decode.into_par_iter().for_each(|photo| {
+                if let Err(e) = photo
+                    .try_image_decoder()
+                    .and_then(DynamicImage::from_decoder)
+                {
+                    diagnostics.error(&photo.path, format!("couldn't decode photo: {e}"));
                 }
-                true
+                progress.inc(1);
             });
+            progress.finish_and_clear();
         }
-    };
-    match_pages(Some(&mut gallery.home_text), &mut gallery.children);
-    let date_format = gallery.config.date_format.clone();
-    gallery.visit_items_mut(|path, item| match item {
-        Item::Category(category) => {
-            if let Some(config) = item_configs.remove(&category.src_key) {
-                let config = toml::from_str::<CategoryConfig>(&config).unwrap();
-                category.config = config;
-                category_configs += 1;
-            }
-
-            match_pages(None, &mut category.children);
 
-            categories += 1;
-        }
-        Item::Photo(photo) => {
-            if let Some(config) = item_configs.remove(&photo.src_key) {
-                let config = toml::from_str::<PhotoConfig>(&config).expect(&path.to_string());
-                photo.config = config;
-                if let Some(date) = &photo.config.date {
-                    photo.parsed_config_date =
-                        Some(NaiveDate::parse_from_str(date, &date_format).unwrap());
+        if let Some(quarantine) = quarantine {
+            for orphan in &orphans {
+                let destination =
+                    quarantine.join(orphan.strip_prefix(&gallery.root).unwrap_or(orphan));
+                if let Err(e) = destination
+                    .parent()
+                    .map_or(Ok(()), fs::create_dir_all)
+                    .and_then(|_| move_file(orphan, &destination))
+                {
+                    diagnostics.error(orphan, format!("couldn't quarantine: {e}"));
+                } else {
+                    println!("Quarantined {}", destination.display());
                 }
-                photo_configs += 1;
-            }
-            photos += 1;
-        }
-        Item::Page(page) => {
-            if let Some(config) = item_configs.remove(&page.src_key) {
-                let config = toml::from_str::<PageConfig>(&config).expect(&path.to_string());
-                page.config = config;
-                page_configs += 1;
             }
-            pages += 1;
         }
-    });
 
-    fn sort_and_make_photo_names_distinct(items: &mut [Item]) {
-        // Don't let user-defined order change distinct names.
-        items.sort_by_key(|item| {
-            if let Item::Photo(photo) = item {
-                Some((std::cmp::Reverse(photo.date_time()), photo.name.clone()))
-            } else {
-                None
-            }
-        });
-
-        let mut indices = HashMap::<String, usize>::new();
-        for item in items.iter_mut().rev() {
-            let photo = if let Item::Photo(photo) = item {
-                photo
-            } else {
-                continue;
-            };
-
-            let name = photo.output_name();
-            let index = indices.entry(name.to_owned()).or_default();
-            *index += 1;
-            if *index > 1 {
-                photo.distinct_name = Some(format!("{name} {index}"));
-            }
+        diagnostics.report();
+        if diagnostics.errors() > 0 {
+            std::process::exit(1);
         }
-
-        items.sort_by_key(Order::new);
+        return;
     }
 
-    sort_and_make_photo_names_distinct(&mut gallery.children);
-    gallery.visit_items_mut(|_, item| match item {
-        Item::Category(category) => {
-            sort_and_make_photo_names_distinct(&mut category.children);
-        }
-        _ => {}
-    });
-
-    //println!("{gallery:?}");
-    println!(
-        "({:.1}s) Found {photos} photos ({photo_configs} with config, {photo_texts} with caption) in {categories} categories ({category_configs} with config, {category_texts} with caption), and {pages} pages ({page_configs} with config)",
-        start.elapsed().as_secs_f32()
-    );
+    if !diagnostics.is_empty() {
+        diagnostics.report();
+    }
 
     if let Command::Init { photos, image_ai } = &args.command {
         let mut jobs = Vec::new();
@@ -463,16 +317,86 @@ fn main() {
         return;
     }
 
-    let output = gallery.output();
+    if let Command::Serve {
+        background,
+        as_of,
+        host,
+        port,
+        lan,
+        https,
+    } = &args.command
+    {
+        let options = ServeOptions {
+            background: *background,
+            host: if *lan {
+                "0.0.0.0".to_owned()
+            } else {
+                host.clone()
+                    .unwrap_or_else(|| gallery.config.serve_host.clone())
+            },
+            port: port.unwrap_or(gallery.config.serve_port),
+            https: *https || gallery.config.serve_https,
+        };
+        // Only returns if the server couldn't be started.
+        let serve_diagnostics = Diagnostics::default();
+        serve(
+            start,
+            options,
+            gallery,
+            &serve_diagnostics,
+            |gallery, changed| {
+                // Today may have changed since the server started.
+                let as_of = as_of.unwrap_or_else(|| Local::now().date_naive());
+                let diagnostics = Diagnostics::default();
+                let gallery = GalleryConfig::load(&diagnostics).and_then(|config| {
+                    gallery.reload(config, Some(as_of), changed, &diagnostics, Instant::now())
+                });
+                if !diagnostics.is_empty() {
+                    diagnostics.report();
+                }
+                gallery
+            },
+        );
+        serve_diagnostics.report();
+        std::process::exit(1);
+    }
+
+    let output_diagnostics = Diagnostics::default();
+    let output = gallery.output(&output_diagnostics);
 
     println!(
         "({:.1}s) Generated output manifest",
         start.elapsed().as_secs_f32(),
     );
 
-    if let Command::Serve { background } = &args.command {
-        serve(start, *background, &gallery, &output);
-    } else {
-        build(start, &gallery, output)
+    if let Command::Cache {
+        command: CacheCommand::Prune,
+    } = &args.command
+    {
+        let hashes = output
+            .into_values()
+            .filter_map(|(_, hasher)| hasher)
+            .collect::<Vec<_>>()
+            .into_par_iter()
+            .map(|hasher| String::clone(&hasher))
+            .collect::<HashSet<_>>();
+        let (files, bytes) = Cache::new(&gallery.config).prune(Some(&hashes));
+        println!(
+            "({:.1}s) Pruned {files} cached images ({:.1}MB)",
+            start.elapsed().as_secs_f32(),
+            bytes as f64 / 1e6
+        );
+        return;
+    }
+
+    build(start, &gallery, output);
+    let render_diagnostics = gallery.theme.take_render_diagnostics();
+    for diagnostics in [&render_diagnostics, &output_diagnostics] {
+        if !diagnostics.is_empty() {
+            diagnostics.report();
+        }
+    }
+    if diagnostics.errors() > 0 || output_diagnostics.errors() > 0 {
+        std::process::exit(1);
     }
 }
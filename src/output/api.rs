@@ -21,6 +21,7 @@ pub fn render_api(gallery: &Gallery) -> Vec<u8> {
             page_text_content: photo.text.as_ref().map(|t| t.content.clone()),
             page_text_format: photo.text.as_ref().map(|t| t.format),
             location: photo.config.location.clone(),
+            tags: photo.tags.clone(),
             description: photo.config.description.clone(),
             page_path: gallery.config.photo_html::<true>(&path, &photo.slug()),
             photo_path: gallery.config.photo::<true>(&path, &photo.slug()),
@@ -82,6 +83,8 @@ struct ApiPhoto {
     page_text_format: Option<RichTextFormat>,
     #[serde(skip_serializing_if = "Option::is_none")]
     location: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     page_path: String,
@@ -1,12 +1,14 @@
 use crate::{
     gallery::{CategoryPath, Gallery, Page, RichText, RichTextFormat},
     output::{
-        paginate, rich_text_html, write_speculation_rules, write_structured_data, BreadcrumbList,
+        archive_name, displayed_items, paginate, parse_archive_path, parse_tags_path,
+        rich_text_html, write_speculation_rules, write_structured_data, BreadcrumbList,
         BreadcrumbListElement, PersonStructuredData, RelativeNavigation,
-        SearchActionStructuredData, WebSiteStructuredData,
+        SearchActionStructuredData, ThemeContext, ThemeGallery, ThemeLink, WebSiteStructuredData,
     },
     util::join,
 };
+use minijinja::Value;
 use yew::{classes, function_component, html, AttrValue, Html, LocalServerRenderer, Properties};
 
 pub struct AppProps<'a> {
@@ -25,268 +27,42 @@ pub struct AppProps<'a> {
 }
 
 pub fn app(props: AppProps<'_>) -> Html {
-    let style = Html::from_html_unchecked(
-        r#"
-        :root {
-            --colored-text-light-background: #4d5a41;
-            --colored-text-dark-background: #e0ff28;
-        }
-
-        @view-transition {
-            navigation: auto;
-        }
-
-        html {
-            font-size: calc(8px + 0.8vw);
-        }
-
-        body {
-            background-color: #222222;
-            margin: 2rem;
-            user-select: none;
-            -webkit-user-drag: none;
-            font-family: "Helvetica Neue", "Lucida Grande", Arial, Helvetica, sans-serif;
-        }
-
-        h1, h2, h3, h4, h5, h6 {
-            font-family: Times, "Times New Roman", Georgia, serif;
-        }
-
-        img, summary {
-            user-select: none;
-        }
-
-        a {
-            text-decoration: none;
-            color: var(--colored-text-light-background);
-        }
-
-        p a, span a, footer a, li a:not(.sidebar_panel_list_link), details a {
-            text-decoration: underline;
-        }
-
-        #page {
-            background-color: white;
-            max-width: 60rem;
-            margin: 0rem auto;
-            display: flex;
-            flex-direction: column;
-            border-radius: 0.5rem;
-            overflow: hidden;
-        }
-
-        @media (max-width: 600px) {
-            body {
-                margin: 0;
-            }
-
-            #page {
-                border-radius: 0;
-                margin: 0;
-                max-width: initial;
-            }
-        }
-
-        #header, #footer {
-            background-color: #dadfbb;
-        }
-
-        #header {
-            display: flex;
-            flex-direction: row;
-            gap: 1rem;
-            align-items: center;
-            padding: 2.25rem 2rem;
-        }
-
-        #title {
-            font-size: 1.5rem;
-            font-weight: normal;
-            letter-spacing: 0.1rem;
-            flex-grow: 1;
-            margin: 0;
-        }
-
-        #relative_navigation {
-            display: flex;
-            flex-direction: row;
-            gap: 0.5rem;
-        }
-
-        .relative_navigation_unavailable {
-            opacity: 0.5;
-        }
-
-        #breadcrumbs {
-            background-color: #505050;
-            padding: 0.5rem 2rem;
-            color: white;
-            display: flex;
-            flex-direction: row;
-            gap: 0.25rem;
-            align-items: center;
-            white-space: nowrap;
-        }
-
-        .breadcrumb {
-            color: var(--colored-text-dark-background);
-        }
-
-        .breadcrumb_final {
-            color: white;
-            font-weight: bold;
-        }
-
-        #search_form {
-            margin-left: auto;
-        }
-
-        @media (max-width: 600px) {
-            #search_form {
-                display: none;
-            }
-        }
-
-        #main_and_sidebar {
-            display: flex;
-            flex-direction: row;
-            flex-grow: 1;
-            min-height: 24rem;
-        }
-
-        #page_main_body {
-            flex-grow: 1;
-            margin: 2rem;
-        }
-
-        #page_main_body > :first-child {
-            margin-top: 0;
-        }
-
-        #page_main_body > :last-child {
-            margin-bottom: 0;
-        }
-
-        #page_main_body_items, #page_main_body_search_results {
-            display: flex;
-            flex-wrap: wrap;
-            gap: 0.5rem;
-            align-content: flex-start;
-        }
-
-        #sidebar {
-            background-color: #fbfbfb;
-            min-width: 18rem;
-            width: 18rem;
-            box-shadow: -0.25rem 0px 0.5rem 0 rgba(0, 0, 0, 0.1);
-            height: 100%;
-        }
-
-        .sidebar_panel {
-            border-bottom: 1px dashed darkgray;
-            padding: 1rem;
-        }
-
-        .sidebar_panel_heading {
-            margin: 0;
-            font-size: 1.2rem;
-            font-weight: normal;
-            font-style: italic;
-        }
-
-        .sidebar_panel_list {
-            padding-inline-start: 0.5rem;
-        }
-
-        .sidebar_panel_list_item { 
-            list-style: none;
-            margin-top: 0.25rem;
-            font-size: 0.9rem;
-        }
-
-        .sidebar_details_panel_text {
-            font-size: 0.9rem;
-        }
-
-        details.sidebar_details_panel_text > summary {
-            margin-left: .2rem;
-        }
-
-        details > summary {
-            cursor: pointer;
-        }
-  
-        .sidebar_panel_list_item::before { 
-            content: "\00BB"; 
-        } 
-
-        #footer {
-            text-align: center;
-            padding: 0.5rem;
-        }
-
-        .thumbnail_container {
-            padding: 0.5rem;
-            display: inline-flex;
-            flex-direction: row;
-            gap: 0.5rem;
-            border: 1px solid #e6e6e6;
-            background-color: #FBFBF8;
-            height: min-content;
-        }
-
-        .category_item_info {
-            width: 10rem;
-        }
-
-        .category_item_name {
-            margin: 0;
-            overflow-wrap: anywhere;
-            font-size: 1rem;
-            font-weight: normal;
-            color: var(--colored-text-light-background);
-            text-overflow: ellipsis;
-            white-space: nowrap;
-            overflow: hidden;
-        }
-
-        .category_item_dates {
-            color: black;
-            font-size: 0.75rem;
-        }
-
-        .category_item_description {
-            color: #373737;
-            font-size: 0.75rem;
-            margin-top: 0.2rem;
-        }
-
-        .preview {
-            width: 100%;
-            height: auto;
-        }
-
-        .thumbnail {
-            width: 6rem;
-            height: 6rem;
-        }
-
-        .thumbnail, .preview {
-            background-color: #282828;
-            font-size: 0.5rem;
-        }
-    "#
-        .into(),
-    );
+    let style = Html::from_html_unchecked(props.gallery.theme.style().to_owned().into());
 
     let breadcrumbs = props
         .path
         .iter_paths()
         .enumerate()
         .map(|(i, path)| {
+            if let Some((year, month)) = parse_archive_path(&path) {
+                return BreadcrumbListElement {
+                    _type: "ListItem",
+                    name: archive_name(year, month),
+                    position: i + 1,
+                    item: (path != props.path)
+                        .then(|| props.gallery.config.archive_html::<true>(year, month, 0)),
+                };
+            }
+            if let Some(tag) = parse_tags_path(&path) {
+                return BreadcrumbListElement {
+                    _type: "ListItem",
+                    name: match tag {
+                        Some(tag) => props
+                            .gallery
+                            .tags()
+                            .get(tag)
+                            .map_or(tag, |tag| tag.name)
+                            .to_owned(),
+                        None => "Tags".to_owned(),
+                    },
+                    position: i + 1,
+                    item: (path != props.path)
+                        .then(|| props.gallery.config.tags_html::<true>(tag, 0)),
+                };
+            }
             if path != props.path {
                 let page = paginate(
-                    props.gallery.children(&path).unwrap(),
+                    displayed_items(props.gallery.children(&path).unwrap()),
                     props
                         .gallery
                         .category(&path)
@@ -325,6 +101,8 @@ pub fn app(props: AppProps<'_>) -> Html {
                         "Home".to_owned()
                     } else if path.len() == 1 && path.last_segment() == Some("search") {
                         "Search".to_owned()
+                    } else if path.len() == 1 && path.last_segment() == Some("404") {
+                        "Page not found".to_owned()
                     } else {
                         props.gallery.item_name(&path).to_owned()
                     },
@@ -335,6 +113,69 @@ pub fn app(props: AppProps<'_>) -> Html {
         })
         .collect::<Vec<_>>();
 
+    // Every photo has a date, if only from the file system.
+    let archive = props
+        .gallery
+        .thumbnail()
+        .map(|_| props.gallery.config.archive_html::<true>(None, None, 0));
+    let mut has_tags = false;
+    props.gallery.visit_items(|_, item| {
+        has_tags |= item.photo().is_some_and(|photo| !photo.tags.is_empty());
+    });
+    let tags = has_tags.then(|| props.gallery.config.tags_html::<true>(None, 0));
+
+    let theme = &props.gallery.theme;
+    let theme_context = ["header.html", "sidebar.html", "footer.html"]
+        .into_iter()
+        .any(|template| theme.overrides(template))
+        .then(|| ThemeContext {
+            gallery: ThemeGallery::new(&props.gallery.config),
+            title: &props.title,
+            description: props.description.as_deref(),
+            canonical: &props.canonical,
+            path: props.path.to_string(),
+            pages: props
+                .pages
+                .iter()
+                .map(|(href, page)| ThemeLink {
+                    name: page.name.clone(),
+                    href: Some(href.clone()),
+                })
+                .collect(),
+            breadcrumbs: breadcrumbs
+                .iter()
+                .map(|breadcrumb| ThemeLink {
+                    name: breadcrumb.name.clone(),
+                    href: breadcrumb.item.clone(),
+                })
+                .collect(),
+            archive: archive.clone(),
+            tags: tags.clone(),
+            relative: props.relative.as_ref(),
+            sidebar: Value::from_safe_string(render_fragment(props.sidebar.clone())),
+        });
+    let [header, sidebar, footer] =
+        ["header.html", "sidebar.html", "footer.html"].map(|template| {
+            theme_context
+                .as_ref()
+                .and_then(|context| theme.render(template, context))
+        });
+
+    // Feeds for the gallery and, if different, the closest category.
+    let feeds = if props.gallery.config.root_url.is_some() {
+        let category = props
+            .path
+            .iter_paths()
+            .filter(|path| props.gallery.category(path).is_some())
+            .last();
+        std::iter::once(CategoryPath::ROOT)
+            .chain(category)
+            .map(|path| (props.gallery.item_name(&path).to_owned(), path))
+            .collect::<Vec<_>>()
+    } else {
+        Vec::new()
+    };
+
     let web_site_structured_data =
         write_structured_data(WebSiteStructuredData {
             _type: "WebSite",
@@ -368,7 +209,7 @@ pub fn app(props: AppProps<'_>) -> Html {
     }
 
     html! {
-        <html lang="en">
+        <html lang={props.gallery.config.language.clone()}>
             <head>
                 <meta charset="UTF-8"/>
                 <title>{props.title.clone()}</title>
@@ -390,6 +231,20 @@ pub fn app(props: AppProps<'_>) -> Html {
                 }
                 <meta name="robots" content={robots_meta}/>
                 <link rel="manifest" href="/manifest.json"/>
+                {feeds.iter().map(|(name, path)| html!{<>
+                    <link
+                        rel="alternate"
+                        type="application/atom+xml"
+                        title={name.clone()}
+                        href={props.gallery.config.feed_xml::<true>(path)}
+                    />
+                    <link
+                        rel="alternate"
+                        type="application/feed+json"
+                        title={name.clone()}
+                        href={props.gallery.config.feed_json::<true>(path)}
+                    />
+                </>}).collect::<Html>()}
                 <meta name="viewport" content="width=device-width, initial-scale=1"/>
                 <meta property="og:type" content="website" />
                 if let Some(root) = &props.gallery.config.root_url {
@@ -440,111 +295,139 @@ pub fn app(props: AppProps<'_>) -> Html {
             <body>
                 <div id="page">
                     <section id="header_nav" data-nosnippet={"nosnippet"}>
-                        <header id="header">
-                            <h1 id="title">{props.gallery.config.title.clone()}</h1>
-                            if let Some(relative) = &props.relative {
-                                <div id="relative_navigation">
-                                    <a
-                                        href={relative.previous.clone()}
-                                        class={classes!(
-                                            "relative_navigation_previous",
-                                            relative.previous.is_none().then_some("relative_navigation_unavailable"),
-                                        )}
-                                        aria-disabled={relative.previous.is_none().then_some("true")}
-                                    >{"Previous"}</a>
-                                    <a
-                                        href={relative.next.clone()}
-                                        class={classes!(
-                                            "relative_navigation_previous",
-                                            relative.next.is_none().then_some("relative_navigation_unavailable"),
-                                        )}
-                                        aria-disabled={relative.next.is_none().then_some("true")}
-                                    >{"Next"}</a>
-                                </div>
-                            }
-                        </header>
-                        <nav id="breadcrumbs" aria-label="Breadcrumb" data-nosnippet={"nosnippet"}>
-                            {join(&breadcrumbs.iter().map(|breadcrumb| html!{
-                                if let Some(href) = &breadcrumb.item {
-                                    <a
-                                        class={"breadcrumb"}
-                                        href={href.clone()}
-                                    >{breadcrumb.name.clone()}</a>
-                                } else {
-                                    <span
-                                        class={"breadcrumb breadcrumb_final"}
-                                        aria-current="page"
-                                    >{breadcrumb.name.clone()}</span>
+                        if let Some(header) = header {
+                            {header}
+                        } else {
+                            <header id="header">
+                                <h1 id="title">{props.gallery.config.title.clone()}</h1>
+                                if let Some(relative) = &props.relative {
+                                    <div id="relative_navigation">
+                                        <a
+                                            href={relative.previous.clone()}
+                                            class={classes!(
+                                                "relative_navigation_previous",
+                                                relative.previous.is_none().then_some("relative_navigation_unavailable"),
+                                            )}
+                                            aria-disabled={relative.previous.is_none().then_some("true")}
+                                        >{"Previous"}</a>
+                                        <a
+                                            href={relative.next.clone()}
+                                            class={classes!(
+                                                "relative_navigation_previous",
+                                                relative.next.is_none().then_some("relative_navigation_unavailable"),
+                                            )}
+                                            aria-disabled={relative.next.is_none().then_some("true")}
+                                        >{"Next"}</a>
+                                    </div>
                                 }
-                            }).collect::<Vec<_>>(), &html!{{"»"}})}
-                            if let Some(relative) = &props.relative {
-                                {format!(" ({}/{})", relative.index + 1, relative.count)}
-                            }
-                            if breadcrumbs.len() > 1 {
-                                if let Some(root) = &props.gallery.config.root_url {
-                                    {write_structured_data(BreadcrumbList{
-                                        _type: "BreadcrumbList",
-                                        item_list_element: breadcrumbs.into_iter().skip(1).map(|mut b| {
-                                            // Don't do Home.
-                                            b.position -= 1;
-                                            b.item = b.item.map(|item| format!("{root}{item}"));
-                                            b
-                                        }).collect(),
-                                    })}
+                            </header>
+                            <nav id="breadcrumbs" aria-label="Breadcrumb" data-nosnippet={"nosnippet"}>
+                                {join(&breadcrumbs.iter().map(|breadcrumb| html!{
+                                    if let Some(href) = &breadcrumb.item {
+                                        <a
+                                            class={"breadcrumb"}
+                                            href={href.clone()}
+                                        >{breadcrumb.name.clone()}</a>
+                                    } else {
+                                        <span
+                                            class={"breadcrumb breadcrumb_final"}
+                                            aria-current="page"
+                                        >{breadcrumb.name.clone()}</span>
+                                    }
+                                }).collect::<Vec<_>>(), &html!{{"»"}})}
+                                if let Some(relative) = &props.relative {
+                                    {format!(" ({}/{})", relative.index + 1, relative.count)}
                                 }
+                                <form id="search_form" action="/search/" method="get">
+                                    <input id="search_query" type="search" name="query" minlength={1} aria-label="Search query"/>
+                                    <button id="search_button" type="submit">{"Search"}</button>
+                                </form>
+                            </nav>
+                        }
+                        if breadcrumbs.len() > 1 {
+                            if let Some(root) = &props.gallery.config.root_url {
+                                {write_structured_data(BreadcrumbList{
+                                    _type: "BreadcrumbList",
+                                    item_list_element: breadcrumbs.into_iter().skip(1).map(|mut b| {
+                                        // Don't do Home.
+                                        b.position -= 1;
+                                        b.item = b.item.map(|item| format!("{root}{item}"));
+                                        b
+                                    }).collect(),
+                                })}
                             }
-                            <form id="search_form" action="/search/" method="get">
-                                <input id="search_query" type="search" name="query" minlength={1} aria-label="Search query"/>
-                                <button id="search_button" type="submit">{"Search"}</button>
-                            </form>
-                        </nav>
+                        }
                     </section>
                     <section id="main_and_sidebar">
                         <main id="page_main_body">
                             {props.body.clone()}
                         </main>
                         <section id="sidebar_section" data-nosnippet={"nosnippet"}>
-                            <aside id="sidebar">
-                                if !props.pages.is_empty() {
-                                    <div class="sidebar_panel">
-                                        <h2 class="sidebar_panel_heading">{"Pages"}</h2>
-                                        <ul class="sidebar_panel_list">
-                                            {props.pages.iter().map(|(href, page)| html!{
-                                                <li class="sidebar_panel_list_item">
-                                                    <a
-                                                        class="sidebar_panel_list_link"
-                                                        href={href.clone()}
-                                                    >{page.name.clone()}</a>
-                                                </li>
-                                            }).collect::<Html>()}
-                                        </ul>
-                                    </div>
-                                }
-                                {props.sidebar.clone()}
-                            </aside>
+                            if let Some(sidebar) = sidebar {
+                                {sidebar}
+                            } else {
+                                <aside id="sidebar">
+                                    if !props.pages.is_empty() || archive.is_some() || tags.is_some() {
+                                        <div class="sidebar_panel">
+                                            <h2 class="sidebar_panel_heading">{"Pages"}</h2>
+                                            <ul class="sidebar_panel_list">
+                                                {props.pages.iter().map(|(href, page)| html!{
+                                                    <li class="sidebar_panel_list_item">
+                                                        <a
+                                                            class="sidebar_panel_list_link"
+                                                            href={href.clone()}
+                                                        >{page.name.clone()}</a>
+                                                    </li>
+                                                }).collect::<Html>()}
+                                                if let Some(href) = archive {
+                                                    <li class="sidebar_panel_list_item">
+                                                        <a
+                                                            class="sidebar_panel_list_link"
+                                                            {href}
+                                                        >{"Archive"}</a>
+                                                    </li>
+                                                }
+                                                if let Some(href) = tags {
+                                                    <li class="sidebar_panel_list_item">
+                                                        <a
+                                                            class="sidebar_panel_list_link"
+                                                            {href}
+                                                        >{"Tags"}</a>
+                                                    </li>
+                                                }
+                                            </ul>
+                                        </div>
+                                    }
+                                    {props.sidebar.clone()}
+                                </aside>
+                            }
                         </section>
                     </section>
                     <section id="footer_section" data-nosnippet={"nosnippet"}>
-                        <footer id="footer">
-                            {join(&props.gallery.config.author.as_ref().map(|author| {
-                                {html!{<>
-                                    {"Published by "}
-                                    if let Some(href) = props.gallery.config.author_url.clone() {
-                                        <a {href} target="_blank">{author}</a>
-                                    } else {
-                                        {author}
-                                    }
-                                </>}}
-                            }).into_iter()
-                                .chain(std::iter::once(html!{<>
-                                    {"Powered by "}
-                                    <a
-                                        href="https://github.com/finnbear/chillphoto"
-                                        target="_blank"
-                                    >{"chillphoto"}</a>
-                                </>}))
-                            .collect::<Vec<_>>(), &html!{{" | "}})}
-                        </footer>
+                        if let Some(footer) = footer {
+                            {footer}
+                        } else {
+                            <footer id="footer">
+                                {join(&props.gallery.config.author.as_ref().map(|author| {
+                                    {html!{<>
+                                        {"Published by "}
+                                        if let Some(href) = props.gallery.config.author_url.clone() {
+                                            <a {href} target="_blank">{author}</a>
+                                        } else {
+                                            {author}
+                                        }
+                                    </>}}
+                                }).into_iter()
+                                    .chain(std::iter::once(html!{<>
+                                        {"Powered by "}
+                                        <a
+                                            href="https://github.com/finnbear/chillphoto"
+                                            target="_blank"
+                                        >{"chillphoto"}</a>
+                                    </>}))
+                                .collect::<Vec<_>>(), &html!{{" | "}})}
+                            </footer>
+                        }
                     </section>
                 </div>
             </body>
@@ -554,21 +437,7 @@ pub fn app(props: AppProps<'_>) -> Html {
 
 // Takes around 10ms.
 pub fn render_html(props: AppProps<'_>) -> Vec<u8> {
-    let html = app(props);
-
-    #[derive(Properties, PartialEq)]
-    struct InnerAppProps {
-        html: Html,
-    }
-
-    #[function_component(InnerApp)]
-    fn inner_app(props: &InnerAppProps) -> Html {
-        props.html.clone()
-    }
-
-    let renderer =
-        LocalServerRenderer::<InnerApp>::with_props(InnerAppProps { html }).hydratable(false);
-    let html = futures::executor::block_on(renderer.render());
+    let html = render_fragment(app(props));
 
     let mut options = markup_fmt::config::FormatOptions::default();
     options.layout.use_tabs = true;
@@ -589,3 +458,20 @@ pub fn render_html(props: AppProps<'_>) -> Vec<u8> {
 
     html.into_bytes()
 }
+
+/// Render markup without formatting it.
+pub fn render_fragment(html: Html) -> String {
+    #[derive(Properties, PartialEq)]
+    struct InnerAppProps {
+        html: Html,
+    }
+
+    #[function_component(InnerApp)]
+    fn inner_app(props: &InnerAppProps) -> Html {
+        props.html.clone()
+    }
+
+    let renderer =
+        LocalServerRenderer::<InnerApp>::with_props(InnerAppProps { html }).hydratable(false);
+    futures::executor::block_on(renderer.render())
+}
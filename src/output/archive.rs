@@ -0,0 +1,277 @@
+use crate::{
+    gallery::{CategoryPath, Gallery, Page, Photo},
+    output::{
+        paginate, render_html, render_photo_items, render_picture, AppProps, DynLazy,
+        ItemThemeContext, RelativeNavigation, ThemeGallery, ThemeItem,
+    },
+};
+use chrono::{Datelike, NaiveDate};
+use minijinja::Value;
+use std::{collections::BTreeMap, fmt::Write, sync::LazyLock};
+use yew::{html, Html};
+
+/// Photos with known dates, by year and then month, in chronological order.
+pub type Archive<'a> = BTreeMap<i32, BTreeMap<u32, Vec<(CategoryPath, &'a Photo)>>>;
+
+impl Gallery {
+    pub fn archive(&self) -> Archive<'_> {
+        let mut dated = Vec::new();
+        self.visit_items(|path, item| {
+            if let Some(photo) = item.photo() {
+                if let Some(date_time) = photo.date_time() {
+                    dated.push((date_time, path.clone(), photo));
+                }
+            }
+        });
+        dated.sort_by(|(a, _, a_photo), (b, _, b_photo)| {
+            a.cmp(b).then_with(|| a_photo.name.cmp(&b_photo.name))
+        });
+
+        let mut ret = Archive::new();
+        for (date_time, path, photo) in dated {
+            ret.entry(date_time.year())
+                .or_default()
+                .entry(date_time.month())
+                .or_default()
+                .push((path, photo));
+        }
+        ret
+    }
+
+    /// Archive pages, by output path.
+    pub fn archive_output<'a>(
+        &'a self,
+        pages: &[(String, &'a Page)],
+        og_image: Option<(String, (u32, u32))>,
+    ) -> Vec<(String, DynLazy<'a, Vec<u8>>)> {
+        let config = &self.config;
+        let archive = self.archive();
+        let mut ret = Vec::<(String, DynLazy<'a, Vec<u8>>)>::new();
+        if archive.is_empty() {
+            return ret;
+        }
+
+        {
+            let archive = archive.clone();
+            ret.push(self.archive_page(
+                pages,
+                og_image.clone(),
+                None,
+                None,
+                0,
+                Box::new(move || {
+                    // Newest first.
+                    html! {
+                        <section id="page_main_body_items" data-nosnippet="nosnippet">
+                            {archive.iter().rev().map(|(year, months)| {
+                                render_period_item(self, Some(*year), None, months.values().flatten())
+                            }).collect::<Html>()}
+                        </section>
+                    }
+                }),
+                None,
+            ));
+        }
+
+        let years = archive.keys().copied().collect::<Vec<_>>();
+        for (index, (year, months)) in archive.iter().enumerate() {
+            let year = *year;
+            let months = months.clone();
+            ret.push(self.archive_page(
+                pages,
+                og_image.clone(),
+                Some(year),
+                None,
+                0,
+                Box::new(move || {
+                    html! {
+                        <section id="page_main_body_items" data-nosnippet="nosnippet">
+                            {months.iter().map(|(month, photos)| {
+                                render_period_item(self, Some(year), Some(*month), photos.iter())
+                            }).collect::<Html>()}
+                        </section>
+                    }
+                }),
+                Some(RelativeNavigation {
+                    index,
+                    count: years.len(),
+                    previous: index
+                        .checked_sub(1)
+                        .map(|i| config.archive_html::<true>(Some(years[i]), None, 0)),
+                    next: years
+                        .get(index + 1)
+                        .map(|year| config.archive_html::<true>(Some(*year), None, 0)),
+                }),
+            ));
+        }
+
+        // Every page of every month, so that previous and next
+        // continue into the adjacent months.
+        let month_pages = archive
+            .iter()
+            .flat_map(|(year, months)| {
+                months.iter().flat_map(|(month, photos)| {
+                    paginate(photos, config.items_per_page)
+                        .map(|chunk| (*year, *month, chunk.index, chunk.items))
+                })
+            })
+            .collect::<Vec<_>>();
+        let month_page_html = |i: usize| {
+            let (year, month, page, _) = month_pages[i];
+            config.archive_html::<true>(Some(year), Some(month), page)
+        };
+        for (index, (year, month, page, photos)) in month_pages.iter().enumerate() {
+            let photos = photos.to_vec();
+            ret.push(self.archive_page(
+                pages,
+                og_image.clone(),
+                Some(*year),
+                Some(*month),
+                *page,
+                Box::new(move || render_photo_items(self, &photos)),
+                Some(RelativeNavigation {
+                    index,
+                    count: month_pages.len(),
+                    previous: index.checked_sub(1).map(month_page_html),
+                    next: (index + 1 < month_pages.len()).then(|| month_page_html(index + 1)),
+                }),
+            ));
+        }
+
+        ret
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn archive_page<'a>(
+        &'a self,
+        pages: &[(String, &'a Page)],
+        og_image: Option<(String, (u32, u32))>,
+        year: Option<i32>,
+        month: Option<u32>,
+        page: usize,
+        body: Box<dyn FnOnce() -> Html + Send + Sync + 'a>,
+        relative: Option<RelativeNavigation>,
+    ) -> (String, DynLazy<'a, Vec<u8>>) {
+        let config = &self.config;
+        let pages = pages.to_vec();
+        let mut title = archive_name(year, month);
+        if page != 0 {
+            write!(title, " (page {})", page + 1).unwrap();
+        }
+        let description = match year {
+            Some(_) => format!("Photos from {}", archive_name(year, month)),
+            None => format!("Photos from {}, by date", config.title),
+        };
+        (
+            config.archive_html::<false>(year, month, page),
+            LazyLock::new(Box::new(move || {
+                render_html(AppProps {
+                    canonical: config.archive_html::<true>(year, month, page),
+                    gallery: self,
+                    title: title.into(),
+                    description: Some(description.into()),
+                    head: Default::default(),
+                    body: body(),
+                    sidebar: Html::default(),
+                    pages,
+                    path: archive_path(year, month),
+                    relative,
+                    og_image,
+                    index: true,
+                })
+            })),
+        )
+    }
+}
+
+/// Breadcrumb path of an archive page.
+fn archive_path(year: Option<i32>, month: Option<u32>) -> CategoryPath {
+    let mut ret = CategoryPath::ROOT.push("archive".to_owned());
+    if let Some(year) = year {
+        ret = ret.push(year.to_string());
+        if let Some(month) = month {
+            ret = ret.push(format!("{month:02}"));
+        }
+    }
+    ret
+}
+
+/// "Archive", "2024", or "May 2024".
+pub fn archive_name(year: Option<i32>, month: Option<u32>) -> String {
+    match (year, month) {
+        (Some(year), Some(month)) => NaiveDate::from_ymd_opt(year, month, 1)
+            .map(|date| date.format("%B %Y").to_string())
+            .unwrap_or_else(|| format!("{year}-{month:02}")),
+        (Some(year), None) => year.to_string(),
+        (None, _) => "Archive".to_owned(),
+    }
+}
+
+/// Parses the year and month from the path of an archive page.
+pub fn parse_archive_path(path: &CategoryPath) -> Option<(Option<i32>, Option<u32>)> {
+    let mut segments = path.iter_segments();
+    if segments.next() != Some("archive") {
+        return None;
+    }
+    let year = segments.next().and_then(|s| s.parse().ok());
+    let month = segments.next().and_then(|s| s.parse().ok());
+    Some((year, month))
+}
+
+/// Links to a year or month, using its first photo as the thumbnail.
+fn render_period_item<'a>(
+    gallery: &Gallery,
+    year: Option<i32>,
+    month: Option<u32>,
+    mut photos: impl Iterator<Item = &'a (CategoryPath, &'a Photo)>,
+) -> Html {
+    let Some((photo_path, photo)) = photos.next() else {
+        return Html::default();
+    };
+    let count = photos.count() + 1;
+    let name = archive_name(year, month);
+    let href = gallery.config.archive_html::<true>(year, month, 0);
+    let thumbnail_url = gallery.config.thumbnail::<true>(photo_path, &photo.slug());
+    let count_text = format!("{count} photo{}", if count == 1 { "" } else { "s" });
+
+    if gallery.theme.overrides("item.html") {
+        if let Some(html) = gallery.theme.render(
+            "item.html",
+            ItemThemeContext {
+                gallery: ThemeGallery::new(&gallery.config),
+                item: ThemeItem {
+                    kind: "period",
+                    name: &name,
+                    href: href.clone(),
+                    thumbnail: thumbnail_url.clone(),
+                    alt: photo.output_name().to_owned(),
+                    description: None,
+                    first_date: None,
+                    last_date: None,
+                    count: Some(count),
+                    draft: false,
+                    structured_data: Value::from_safe_string(String::new()),
+                },
+            },
+        ) {
+            return html;
+        }
+    }
+
+    html! {
+        <a
+            class="thumbnail_container category_item"
+            {href}
+        >
+            {render_picture(gallery, photo_path, photo, true, photo.output_name().to_owned(), None)}
+            <div class="category_item_info">
+                <h2 class="category_item_name">
+                    {name}
+                </h2>
+                <div class="category_item_dates">
+                    {count_text}
+                </div>
+            </div>
+        </a>
+    }
+}
@@ -1,32 +1,52 @@
-use crate::{gallery::Gallery, output::DynLazy, util::{progress_bar, recursively_remove_empty_dirs_of_contents}};
+use crate::{
+    gallery::Gallery,
+    output::{
+        is_compressible, sibling_of, Cache, Encoding, Manifest, ManifestEntry, Output, OutputFormat,
+    },
+    util::{checksum, progress_bar, recursively_remove_empty_dirs_of_contents},
+};
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
 use std::{
-    collections::HashMap,
+    borrow::Cow,
     fs,
-    sync::atomic::{AtomicUsize, Ordering},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
     time::Instant,
 };
 use wax::Glob;
 
-pub fn build(
-    start: Instant,
-    gallery: &Gallery,
-    output: HashMap<String, (DynLazy<'_, Vec<u8>>, Option<DynLazy<'_, String>>)>,
-) {
+pub fn build(start: Instant, gallery: &Gallery, output: Output<'_>) {
     let progress = progress_bar("Saving website...", output.len(), start);
 
-    let reused = AtomicUsize::new(0);
-    let total = AtomicUsize::new(0);
+    let reused_images = AtomicUsize::new(0);
+    let cached_images = AtomicUsize::new(0);
+    let total_images = AtomicUsize::new(0);
+    let reused_pages = AtomicUsize::new(0);
+    let total_pages = AtomicUsize::new(0);
     let mut removals = 0usize;
 
-    // Remove obsolete files.
+    let cache = &Cache::new(&gallery.config);
+    let manifest = Manifest::load(&gallery.config);
+    let new_manifest = Mutex::new(Manifest::default());
+
+    fs::create_dir_all(&gallery.config.output).unwrap();
+
+    let precompress = gallery.config.precompress;
+    let expected = |path: &str| {
+        output.contains_key(path)
+            || (precompress && sibling_of(path).is_some_and(|(path, _)| output.contains_key(path)))
+    };
+
+    // Remove obsolete files, whether or not they're in the manifest.
     for file in Glob::new("**").unwrap().walk(&gallery.config.output) {
         let file = file.unwrap();
         if !file.file_type().is_file() {
             continue;
         }
         let path = format!("/{}", file.matched().complete());
-        if output.get(&path).is_none() {
+        if !expected(&path) {
             //println!("removing obsolete {path}");
             fs::remove_file(file.path()).unwrap();
             removals += 1;
@@ -34,51 +54,188 @@ pub fn build(
     }
     recursively_remove_empty_dirs_of_contents(&gallery.config.output).unwrap();
 
+    // Keep each photo's variants together, so it's only decoded once.
+    let mut output = output.into_iter().collect::<Vec<_>>();
+    output.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
     output
         .into_par_iter()
-        .for_each(|(path, (generator, hasher))| {
-            let path = gallery.config.subdirectory(path.strip_prefix('/').unwrap());
+        .for_each(|(output_path, (generator, hasher))| {
+            let path = gallery
+                .config
+                .subdirectory(output_path.strip_prefix('/').unwrap());
+            let old = manifest
+                .as_ref()
+                .and_then(|manifest| manifest.files.get(&output_path));
 
-            let new_hash = hasher.as_ref().map(|hasher| (&**hasher).as_bytes());
+            let image = OutputFormat::from_path(&path).is_some();
+            let (reused, total) = if image {
+                (&reused_images, &total_images)
+            } else {
+                (&reused_pages, &total_pages)
+            };
+            let new_hash = hasher.as_ref().map(|hasher| hasher.as_str());
 
             let mut reuse = false;
             if let Some(new_hash) = new_hash {
                 if fs::exists(&path).unwrap() {
-                    if let Ok(Some(old_hash)) = fsquirrel::get(&path, "chillphotohash") {
-                        if old_hash == new_hash {
+                    if old.is_some_and(|old| old.input.as_deref() == Some(new_hash)) {
+                        reuse = true;
+                    } else if let Ok(Some(old_hash)) = fsquirrel::get(&path, "chillphotohash") {
+                        // Output from before the manifest, or without it.
+                        if old_hash == new_hash.as_bytes() {
                             reuse = true;
                         }
                     }
                 }
-
-                total.fetch_add(1, Ordering::Relaxed);
-                if reuse {
-                    reused.fetch_add(1, Ordering::Relaxed);
-                }
             }
 
-            if !reuse {
-                let contents = &**generator;
-                if let Some((dir, _)) = path.rsplit_once('/') {
-                    std::fs::create_dir_all(dir).unwrap();
+            let cached;
+            let mut from_cache = false;
+            let (content, contents) = if reuse {
+                let content = match old {
+                    Some(old) if old.input.as_deref() == new_hash => old.content.clone(),
+                    _ => checksum(&fs::read(&path).unwrap()),
+                };
+                (content, None)
+            } else {
+                // Only images are worth caching outside the output directory.
+                cached = hasher
+                    .as_ref()
+                    .filter(|_| image)
+                    .and_then(|hasher| cache.get(hasher));
+                from_cache = cached.is_some();
+                let contents = match cached.as_deref() {
+                    Some(contents) => contents,
+                    None => {
+                        let Some(contents) = generator.as_deref() else {
+                            // Reported by `Gallery::output`. Left out of the
+                            // manifest, so the next build tries again.
+                            let _ = fs::remove_file(&path);
+                            progress.inc(1);
+                            return;
+                        };
+                        if let Some(hasher) = hasher.as_ref().filter(|_| image) {
+                            cache.insert(hasher, contents);
+                        }
+                        contents
+                    }
+                };
+                let content = checksum(contents);
+                // Leave unchanged files, and their modification times, alone.
+                let unchanged = match old {
+                    Some(old) => old.content == content && fs::exists(&path).unwrap(),
+                    None => fs::read(&path).is_ok_and(|old| old == contents),
+                };
+                if unchanged {
+                    reuse = true;
+                } else {
+                    if let Some((dir, _)) = path.rsplit_once('/') {
+                        std::fs::create_dir_all(dir).unwrap();
+                    }
+                    let _ = fsquirrel::remove(&path, "chillphotohash");
+                    std::fs::write(&path, contents).unwrap();
                 }
-                let _ = fsquirrel::remove(&path, "chillphotohash");
-                std::fs::write(&path, contents).unwrap();
                 if let Some(new_hash) = new_hash {
-                    fsquirrel::set(&path, "chillphotohash", new_hash).unwrap();
+                    // Not all file systems support extended attributes.
+                    let _ = fsquirrel::set(&path, "chillphotohash", new_hash.as_bytes());
+                }
+                (content, Some(contents))
+            };
+
+            if precompress && is_compressible(&output_path) {
+                write_compressed(
+                    gallery,
+                    manifest.as_ref(),
+                    &new_manifest,
+                    &output_path,
+                    &content,
+                    contents,
+                );
+            }
+
+            new_manifest.lock().unwrap().files.insert(
+                output_path,
+                ManifestEntry {
+                    input: new_hash.map(str::to_owned),
+                    content,
+                },
+            );
+
+            // Unhashed images, like favicons, aren't interesting.
+            if !image || new_hash.is_some() {
+                total.fetch_add(1, Ordering::Relaxed);
+                if reuse {
+                    reused.fetch_add(1, Ordering::Relaxed);
+                } else if from_cache {
+                    cached_images.fetch_add(1, Ordering::Relaxed);
                 }
             }
 
             progress.inc(1);
         });
 
+    new_manifest.into_inner().unwrap().save(&gallery.config);
     progress.finish_and_clear();
+    cache.prune(None);
 
     println!(
-        "({:.1}s) Saved website to {}, reusing {}/{} images files, removed {removals} obsolete files",
+        "({:.1}s) Saved website to {}, reusing {}/{} images ({} more from the cache) and {}/{} pages, removed {removals} obsolete files",
         start.elapsed().as_secs_f32(),
         gallery.config.output,
-        reused.load(Ordering::Relaxed),
-        total.load(Ordering::Relaxed),
+        reused_images.load(Ordering::Relaxed),
+        total_images.load(Ordering::Relaxed),
+        cached_images.load(Ordering::Relaxed),
+        reused_pages.load(Ordering::Relaxed),
+        total_pages.load(Ordering::Relaxed),
     );
 }
+
+/// Writes the siblings of the text output at `output_path`, with checksum
+/// `content`, in each [`Encoding`], or removes them if it's too small to
+/// be worth compressing. Those compressed from the same content are reused.
+fn write_compressed(
+    gallery: &Gallery,
+    manifest: Option<&Manifest>,
+    new_manifest: &Mutex<Manifest>,
+    output_path: &str,
+    content: &str,
+    contents: Option<&[u8]>,
+) {
+    let path = gallery
+        .config
+        .subdirectory(output_path.strip_prefix('/').unwrap());
+    let mut contents = contents.map(Cow::Borrowed);
+    let size = match &contents {
+        Some(contents) => contents.len() as u64,
+        None => fs::metadata(&path).map_or(0, |metadata| metadata.len()),
+    };
+    for encoding in Encoding::ALL {
+        let sibling_output_path = format!("{output_path}.{}", encoding.extension());
+        let sibling_path = format!("{path}.{}", encoding.extension());
+        if size < gallery.config.precompress_min_size {
+            let _ = fs::remove_file(&sibling_path);
+            continue;
+        }
+        let entry = match manifest.and_then(|manifest| manifest.files.get(&sibling_output_path)) {
+            Some(old)
+                if old.input.as_deref() == Some(content) && fs::exists(&sibling_path).unwrap() =>
+            {
+                old.clone()
+            }
+            _ => {
+                let contents = contents.get_or_insert_with(|| Cow::Owned(fs::read(&path).unwrap()));
+                let compressed = encoding.encode(contents);
+                fs::write(&sibling_path, &compressed).unwrap();
+                ManifestEntry {
+                    input: Some(content.to_owned()),
+                    content: checksum(&compressed),
+                }
+            }
+        };
+        new_manifest
+            .lock()
+            .unwrap()
+            .files
+            .insert(sibling_output_path, entry);
+    }
+}
@@ -0,0 +1,108 @@
+use crate::gallery::GalleryConfig;
+use std::{
+    collections::HashSet,
+    fs::{self, File},
+    io::Write,
+    path::PathBuf,
+    time::SystemTime,
+};
+
+/// Encoded images, by the checksum of everything they're derived from.
+/// Shared by `build` and `serve`, and survives `clean`.
+///
+/// Each is a file, whose modification time is when it was last used.
+pub struct Cache {
+    dir: PathBuf,
+    /// In bytes.
+    max_size: u64,
+}
+
+impl Cache {
+    pub fn new(config: &GalleryConfig) -> Self {
+        Self {
+            dir: PathBuf::from(&config.cache),
+            max_size: config.cache_size_mb << 20,
+        }
+    }
+
+    fn path(&self, hash: &str) -> PathBuf {
+        // Base64 may contain slashes.
+        self.dir.join(hash.replace('/', "_"))
+    }
+
+    pub fn get(&self, hash: &str) -> Option<Vec<u8>> {
+        if self.max_size == 0 {
+            return None;
+        }
+        let path = self.path(hash);
+        let contents = fs::read(&path).ok()?;
+        if let Ok(file) = File::options().write(true).open(&path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(contents)
+    }
+
+    /// Best effort, as the cache is only an optimization.
+    pub fn insert(&self, hash: &str, contents: &[u8]) {
+        if self.max_size == 0 {
+            return;
+        }
+        // So that a partially-written file is never read.
+        let Ok(mut file) =
+            fs::create_dir_all(&self.dir).and_then(|_| tempfile::NamedTempFile::new_in(&self.dir))
+        else {
+            return;
+        };
+        if file.write_all(contents).is_ok() {
+            let _ = file.persist(self.path(hash));
+        }
+    }
+
+    /// Removes entries that aren't in `keep`, if any, then the least
+    /// recently used until the cache is within its maximum size.
+    ///
+    /// Returns the number of files and bytes removed.
+    pub fn prune(&self, keep: Option<&HashSet<String>>) -> (usize, u64) {
+        let Ok(entries) = fs::read_dir(&self.dir) else {
+            return (0, 0);
+        };
+        let keep = keep.map(|keep| {
+            keep.iter()
+                .map(|hash| self.path(hash))
+                .collect::<HashSet<_>>()
+        });
+        let mut files = Vec::new();
+        let mut size = 0;
+        let mut removed = (0, 0);
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let path = entry.path();
+            if keep.as_ref().is_some_and(|keep| !keep.contains(&path)) {
+                if fs::remove_file(&path).is_ok() {
+                    removed.0 += 1;
+                    removed.1 += metadata.len();
+                }
+                continue;
+            }
+            size += metadata.len();
+            files.push((metadata.modified().ok(), metadata.len(), path));
+        }
+        files.sort_unstable();
+        for (_, len, path) in files {
+            if size <= self.max_size {
+                break;
+            }
+            if fs::remove_file(&path).is_ok() {
+                size -= len;
+                removed.0 += 1;
+                removed.1 += len;
+            }
+        }
+        removed
+    }
+}
@@ -0,0 +1,126 @@
+use crate::output::content_type;
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+/// A `Content-Encoding` that text outputs are pre-compressed with.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Encoding {
+    Gzip,
+    Brotli,
+}
+
+impl Encoding {
+    /// Most preferred last.
+    pub const ALL: [Self; 2] = [Self::Gzip, Self::Brotli];
+
+    /// Of the sibling file, like `index.html.br`.
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Gzip => "gz",
+            Self::Brotli => "br",
+        }
+    }
+
+    /// As in `Accept-Encoding` and `Content-Encoding`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Brotli => "br",
+        }
+    }
+
+    /// The most preferred encoding accepted by an `Accept-Encoding` header.
+    pub fn negotiate(accept_encoding: &str) -> Option<Self> {
+        Self::ALL.into_iter().rev().find(|encoding| {
+            accept_encoding.split(',').any(|accepted| {
+                let mut parts = accepted.split(';').map(str::trim);
+                parts.next() == Some(encoding.name())
+                    && !parts.any(|parameter| {
+                        parameter
+                            .strip_prefix("q=")
+                            .and_then(|q| q.parse::<f32>().ok())
+                            == Some(0.0)
+                    })
+            })
+        })
+    }
+
+    /// With the highest compression, as it's done once per build.
+    pub fn encode(self, contents: &[u8]) -> Vec<u8> {
+        let mut ret = Vec::new();
+        match self {
+            Self::Gzip => {
+                let mut encoder = GzEncoder::new(&mut ret, Compression::best());
+                encoder.write_all(contents).unwrap();
+                encoder.finish().unwrap();
+            }
+            Self::Brotli => {
+                let mut encoder = brotli::CompressorWriter::new(&mut ret, 4096, 11, 22);
+                encoder.write_all(contents).unwrap();
+                // Finishes the stream.
+                encoder.into_inner();
+            }
+        }
+        ret
+    }
+}
+
+/// The text output that `path` is a compressed sibling of, if it is one.
+pub fn sibling_of(path: &str) -> Option<(&str, Encoding)> {
+    Encoding::ALL.into_iter().find_map(|encoding| {
+        let (parent, extension) = path.rsplit_once('.')?;
+        (extension == encoding.extension() && is_compressible(parent)).then_some((parent, encoding))
+    })
+}
+
+/// Whether the output file at `path` is text, which is worth compressing.
+pub fn is_compressible(path: &str) -> bool {
+    // Paginated by query, which can't be served from a sibling.
+    if path.contains('?') {
+        return false;
+    }
+    let content_type = content_type(path);
+    content_type.starts_with("text/")
+        || content_type.contains("json")
+        || content_type.contains("xml")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negotiates_preferred_encoding() {
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::negotiate("gzip"), Some(Encoding::Gzip));
+        assert_eq!(
+            Encoding::negotiate("gzip;q=0.5, br;q=1.0"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::negotiate("identity"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn refuses_rejected_encodings() {
+        assert_eq!(Encoding::negotiate("br;q=0, gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("br; q=0.0, gzip;q=0"), None);
+    }
+
+    #[test]
+    fn finds_siblings() {
+        assert_eq!(
+            sibling_of("/index.html.br"),
+            Some(("/index.html", Encoding::Brotli))
+        );
+        assert_eq!(
+            sibling_of("/feed.xml.gz"),
+            Some(("/feed.xml", Encoding::Gzip))
+        );
+        assert_eq!(sibling_of("/photo.jpg.gz"), None);
+        assert_eq!(sibling_of("/index.html"), None);
+    }
+}
@@ -0,0 +1,640 @@
+use crate::{
+    diagnostics::Diagnostics,
+    gallery::GalleryConfig,
+    output::{content_type, sibling_of, Manifest, OutputFormat},
+    util::{progress_bar, remove_empty_ancestors},
+};
+use hmac::{Hmac, Mac};
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::Write as _,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    time::Instant,
+};
+
+/// Somewhere `deploy` can upload the output of `build`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DeployTarget {
+    /// An S3-compatible bucket, addressed by path (e.g. MinIO).
+    S3 {
+        /// Like `https://s3.us-east-1.amazonaws.com` or `http://localhost:9000`.
+        endpoint: String,
+        bucket: String,
+        #[serde(default = "default_region")]
+        region: String,
+        /// Prepended to every key, like `photos/`.
+        #[serde(default)]
+        prefix: String,
+        /// Defaults to the `AWS_ACCESS_KEY_ID` environment variable.
+        access_key_id: Option<String>,
+        /// Defaults to the `AWS_SECRET_ACCESS_KEY` environment variable.
+        secret_access_key: Option<String>,
+        #[serde(default = "default_image_cache_control")]
+        image_cache_control: String,
+        #[serde(default = "default_html_cache_control")]
+        html_cache_control: String,
+        /// For everything else, like feeds and the sitemap.
+        #[serde(default = "default_other_cache_control")]
+        other_cache_control: String,
+    },
+    /// A local or mounted directory.
+    Directory { path: String },
+    /// An `rsync` destination, like `user@host:/var/www/photos` (over ssh).
+    Rsync { destination: String },
+}
+
+fn default_region() -> String {
+    "us-east-1".to_owned()
+}
+
+fn default_image_cache_control() -> String {
+    "public, max-age=31536000, immutable".to_owned()
+}
+
+fn default_html_cache_control() -> String {
+    "public, max-age=300".to_owned()
+}
+
+fn default_other_cache_control() -> String {
+    "public, max-age=3600".to_owned()
+}
+
+/// Uploads files that changed since the last deploy to `target` (which may
+/// be omitted if there's only one), and deletes those that are no longer
+/// built. Compares the manifest of the last `build` with a record of the
+/// last deploy, kept in the cache directory (not on the target, where it
+/// would be public), so files that other tools put there are left alone.
+///
+/// Reports problems to `diagnostics`.
+pub fn deploy(
+    start: Instant,
+    config: &GalleryConfig,
+    target: Option<&str>,
+    diagnostics: &Diagnostics,
+) {
+    let (name, target) = match target {
+        Some(name) => match config.deploy.get_key_value(name) {
+            Some(target) => target,
+            None => {
+                diagnostics.error(
+                    GalleryConfig::PATH,
+                    format!("no deploy target named {name:?}"),
+                );
+                return;
+            }
+        },
+        None => {
+            let mut targets = config.deploy.iter();
+            match (targets.next(), targets.next()) {
+                (Some(target), None) => target,
+                (None, _) => {
+                    diagnostics.error(GalleryConfig::PATH, "no deploy targets configured");
+                    return;
+                }
+                (Some(_), Some(_)) => {
+                    diagnostics.error(
+                        GalleryConfig::PATH,
+                        format!(
+                            "multiple deploy targets, so one must be chosen: {}",
+                            config.deploy.keys().cloned().collect::<Vec<_>>().join(", ")
+                        ),
+                    );
+                    return;
+                }
+            }
+        }
+    };
+
+    let Some(manifest) = Manifest::load(config) else {
+        diagnostics.error(
+            &config.output,
+            "no build manifest; run `chillphoto build` first",
+        );
+        return;
+    };
+
+    let remote: Box<dyn Remote> = match target {
+        DeployTarget::S3 {
+            endpoint,
+            bucket,
+            region,
+            prefix,
+            access_key_id,
+            secret_access_key,
+            image_cache_control,
+            html_cache_control,
+            other_cache_control,
+        } => {
+            let credential = |configured: &Option<String>, variable: &str| {
+                configured
+                    .clone()
+                    .or_else(|| std::env::var(variable).ok())
+                    .ok_or_else(|| {
+                        diagnostics.error(
+                            GalleryConfig::PATH,
+                            format!("deploy target {name:?} needs {variable}"),
+                        );
+                    })
+            };
+            let (Ok(access_key_id), Ok(secret_access_key)) = (
+                credential(access_key_id, "AWS_ACCESS_KEY_ID"),
+                credential(secret_access_key, "AWS_SECRET_ACCESS_KEY"),
+            ) else {
+                return;
+            };
+            Box::new(S3 {
+                client: reqwest::blocking::Client::new(),
+                endpoint: endpoint.trim_end_matches('/').to_owned(),
+                bucket: bucket.clone(),
+                region: region.clone(),
+                prefix: prefix.clone(),
+                access_key_id,
+                secret_access_key,
+                image_cache_control: image_cache_control.clone(),
+                html_cache_control: html_cache_control.clone(),
+                other_cache_control: other_cache_control.clone(),
+            })
+        }
+        DeployTarget::Directory { path } => Box::new(Directory { path: path.clone() }),
+        DeployTarget::Rsync { destination } => Box::new(Rsync {
+            destination: destination.trim_end_matches('/').to_owned(),
+        }),
+    };
+
+    let record = Deployed::path(config, name);
+    let old = match Deployed::load(&record) {
+        Ok(Some(deployed)) if deployed.location == remote.location("/") => deployed.manifest,
+        Ok(_) => Manifest::default(),
+        Err(e) => {
+            diagnostics.error(&record, format!("couldn't read deploy record: {e}"));
+            return;
+        }
+    };
+
+    let uploads = manifest
+        .files
+        .iter()
+        .filter(|(path, entry)| {
+            old.files
+                .get(*path)
+                .is_none_or(|old| old.content != entry.content)
+        })
+        .map(|(path, _)| path.as_str())
+        .collect::<Vec<_>>();
+    let removals = old
+        .files
+        .keys()
+        .filter(|path| !manifest.files.contains_key(*path))
+        .map(String::as_str)
+        .collect::<Vec<_>>();
+
+    let progress = progress_bar("Deploying website...", uploads.len(), start);
+    let result = if uploads.is_empty() && removals.is_empty() {
+        Ok(())
+    } else {
+        remote.sync(config, &uploads, &removals, &|| progress.inc(1))
+    };
+    progress.finish_and_clear();
+    if let Err(errors) = result {
+        for (path, e) in errors {
+            diagnostics.error(remote.location(&path), e);
+        }
+        // The next deploy will retry what failed, as the record still
+        // describes what was there before.
+        return;
+    }
+
+    let (uploads, removals) = (uploads.len(), removals.len());
+    // Last, so an interrupted deploy is resumed by the next one.
+    let deployed = Deployed {
+        location: remote.location("/"),
+        manifest,
+    };
+    if let Err(e) = deployed.save(&record) {
+        diagnostics.error(&record, format!("couldn't write deploy record: {e}"));
+        return;
+    }
+
+    println!(
+        "({:.1}s) Deployed website to {name}, uploading {}/{} files, removed {} obsolete files",
+        start.elapsed().as_secs_f32(),
+        uploads,
+        deployed.manifest.files.len(),
+        removals,
+    );
+}
+
+/// What was last deployed to a target.
+#[derive(Serialize, Deserialize)]
+struct Deployed {
+    /// Of the target, so that a record isn't used if the target changes.
+    location: String,
+    manifest: Manifest,
+}
+
+impl Deployed {
+    fn path(config: &GalleryConfig, name: &str) -> PathBuf {
+        Path::new(&config.cache)
+            .join("deploy")
+            .join(format!("{name}.json"))
+    }
+
+    /// `None` if nothing was deployed.
+    fn load(path: &Path) -> Result<Option<Self>, String> {
+        match fs::read(path) {
+            Ok(json) => serde_json::from_slice(&json)
+                .map(Some)
+                .map_err(|e| e.to_string()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    fn save(&self, path: &Path) -> std::io::Result<()> {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        fs::create_dir_all(dir)?;
+        // So that an interrupted deploy never leaves a truncated record.
+        let mut file = tempfile::NamedTempFile::new_in(dir)?;
+        file.write_all(serde_json::to_string_pretty(self).unwrap().as_bytes())?;
+        file.persist(path)?;
+        Ok(())
+    }
+}
+
+/// Problems with individual files, by output path.
+type SyncErrors = Vec<(String, String)>;
+
+trait Remote: Sync {
+    /// For reporting problems with the file at output `path`.
+    fn location(&self, path: &str) -> String;
+
+    /// Uploads and deletes files, by output path, calling `uploaded` after
+    /// each upload.
+    fn sync(
+        &self,
+        config: &GalleryConfig,
+        uploads: &[&str],
+        removals: &[&str],
+        uploaded: &(dyn Fn() + Sync),
+    ) -> Result<(), SyncErrors>;
+}
+
+/// Output path, without the leading slash. An error if it could refer to
+/// something outside the output (or target), as paths in a deploy record
+/// could have been edited.
+fn relative(path: &str) -> Result<&str, String> {
+    path.strip_prefix('/')
+        .filter(|relative| !relative.split('/').any(|part| part == ".."))
+        .ok_or_else(|| format!("invalid output path {path:?}"))
+}
+
+fn local_path(config: &GalleryConfig, path: &str) -> Result<PathBuf, String> {
+    Ok(Path::new(&config.output).join(relative(path)?))
+}
+
+struct Directory {
+    path: String,
+}
+
+impl Directory {
+    fn destination(&self, path: &str) -> Result<PathBuf, String> {
+        Ok(Path::new(&self.path).join(relative(path)?))
+    }
+}
+
+impl Remote for Directory {
+    fn location(&self, path: &str) -> String {
+        Path::new(&self.path)
+            .join(path.trim_start_matches('/'))
+            .display()
+            .to_string()
+    }
+
+    fn sync(
+        &self,
+        config: &GalleryConfig,
+        uploads: &[&str],
+        removals: &[&str],
+        uploaded: &(dyn Fn() + Sync),
+    ) -> Result<(), SyncErrors> {
+        let mut errors = uploads
+            .par_iter()
+            .filter_map(|path| {
+                let result = self.destination(path).and_then(|destination| {
+                    let source = local_path(config, path)?;
+                    destination
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .and_then(|_| fs::copy(source, &destination))
+                        .map(|_| ())
+                        .map_err(|e| e.to_string())
+                });
+                uploaded();
+                result.err().map(|e| (path.to_string(), e))
+            })
+            .collect::<Vec<_>>();
+        for path in removals {
+            let result = self.destination(path).and_then(|destination| {
+                match fs::remove_file(&destination) {
+                    Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+                    // Only prune directories that held what was removed.
+                    _ => remove_empty_ancestors(&destination, Path::new(&self.path)),
+                }
+                .map_err(|e| e.to_string())
+            });
+            if let Err(e) = result {
+                errors.push((path.to_string(), e));
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+struct Rsync {
+    destination: String,
+}
+
+impl Remote for Rsync {
+    fn location(&self, path: &str) -> String {
+        format!("{}{path}", self.destination)
+    }
+
+    fn sync(
+        &self,
+        config: &GalleryConfig,
+        uploads: &[&str],
+        removals: &[&str],
+        uploaded: &(dyn Fn() + Sync),
+    ) -> Result<(), SyncErrors> {
+        let result = self.rsync(Path::new(&config.output), uploads);
+        uploads.iter().for_each(|_| uploaded());
+        result?;
+        // From an empty directory, so listed files are deleted even if they
+        // are in the output.
+        let empty = tempfile::tempdir().map_err(|e| vec![(String::from("/"), e.to_string())])?;
+        self.rsync(empty.path(), removals)
+    }
+}
+
+impl Rsync {
+    /// Copies `paths` from `source`, deleting those that aren't there.
+    fn rsync(&self, source: &Path, paths: &[&str]) -> Result<(), SyncErrors> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+        let mut list = String::new();
+        let mut errors = SyncErrors::new();
+        for path in paths {
+            match relative(path) {
+                Ok(relative) => write!(list, "{relative}\0").unwrap(),
+                Err(e) => errors.push((path.to_string(), e)),
+            }
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        let error = |e: String| vec![(String::from("/"), e)];
+        let mut child = Command::new("rsync")
+            .args(["--recursive", "--times", "--compress", "--mkpath"])
+            // Deletes listed files that aren't in `source`.
+            .args(["--delete-missing-args", "--from0", "--files-from=-"])
+            .arg(format!("{}/", source.display()))
+            .arg(format!("{}/", self.destination))
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| error(format!("couldn't run rsync: {e}")))?;
+        child
+            .stdin
+            .take()
+            .ok_or_else(|| error("couldn't write to rsync".to_owned()))?
+            .write_all(list.as_bytes())
+            .map_err(|e| error(e.to_string()))?;
+        let status = child.wait().map_err(|e| error(e.to_string()))?;
+        if status.success() {
+            Ok(())
+        } else {
+            Err(error(format!("rsync failed ({status})")))
+        }
+    }
+}
+
+struct S3 {
+    client: reqwest::blocking::Client,
+    endpoint: String,
+    bucket: String,
+    region: String,
+    prefix: String,
+    access_key_id: String,
+    secret_access_key: String,
+    image_cache_control: String,
+    html_cache_control: String,
+    other_cache_control: String,
+}
+
+impl S3 {
+    /// Canonical (URI-encoded) path of the object at output `path`.
+    fn object_path(&self, path: &str) -> Result<String, String> {
+        Ok(format!(
+            "/{}/{}",
+            uri_encode(&self.bucket),
+            uri_encode(&format!("{}{}", self.prefix, relative(path)?))
+        ))
+    }
+
+    fn cache_control(&self, path: &str) -> &str {
+        if OutputFormat::from_path(path).is_some() {
+            &self.image_cache_control
+        } else if content_type(path).starts_with("text/html") {
+            &self.html_cache_control
+        } else {
+            &self.other_cache_control
+        }
+    }
+
+    /// Sends a request signed with AWS Signature Version 4.
+    fn send(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Vec<u8>,
+        headers: &[(&str, &str)],
+    ) -> Result<reqwest::blocking::Response, String> {
+        let object_path = self.object_path(path)?;
+        let url = reqwest::Url::parse(&format!("{}{object_path}", self.endpoint))
+            .map_err(|e| format!("invalid endpoint: {e}"))?;
+        let host = match url.port() {
+            Some(port) => format!("{}:{port}", url.host_str().unwrap_or_default()),
+            None => url.host_str().unwrap_or_default().to_owned(),
+        };
+        let now = chrono::Utc::now();
+        let timestamp = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+        let payload_hash = hex(&Sha256::digest(&body));
+
+        let canonical_request = format!(
+            "{method}\n{object_path}\n\nhost:{host}\nx-amz-content-sha256:{payload_hash}\nx-amz-date:{timestamp}\n\nhost;x-amz-content-sha256;x-amz-date\n{payload_hash}"
+        );
+        let scope = format!("{date}/{}/s3/aws4_request", self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{timestamp}\n{scope}\n{}",
+            hex(&Sha256::digest(canonical_request.as_bytes()))
+        );
+        let mut key = format!("AWS4{}", self.secret_access_key).into_bytes();
+        for part in [date.as_str(), &self.region, "s3", "aws4_request"] {
+            key = hmac(&key, part.as_bytes());
+        }
+        let signature = hex(&hmac(&key, string_to_sign.as_bytes()));
+
+        let mut request = self
+            .client
+            .request(method, url)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", timestamp)
+            .header(
+                "Authorization",
+                format!(
+                    "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature={signature}",
+                    self.access_key_id
+                ),
+            )
+            .body(body);
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+        request.send().map_err(|e| e.to_string())
+    }
+
+    fn check(response: reqwest::blocking::Response) -> Result<(), String> {
+        let status = response.status();
+        if status.is_success() {
+            Ok(())
+        } else {
+            let body = response.text().unwrap_or_default();
+            Err(format!("{status}: {}", body.trim()))
+        }
+    }
+}
+
+impl Remote for S3 {
+    fn location(&self, path: &str) -> String {
+        format!(
+            "s3://{}/{}{}",
+            self.bucket,
+            self.prefix,
+            path.trim_start_matches('/')
+        )
+    }
+
+    fn sync(
+        &self,
+        config: &GalleryConfig,
+        uploads: &[&str],
+        removals: &[&str],
+        uploaded: &(dyn Fn() + Sync),
+    ) -> Result<(), SyncErrors> {
+        let mut errors = uploads
+            .par_iter()
+            .filter_map(|path| {
+                let result = local_path(config, path)
+                    .and_then(|source| fs::read(source).map_err(|e| e.to_string()))
+                    .and_then(|body| {
+                        // So that compressed siblings can be served in place
+                        // of the original.
+                        let (original, encoding) = sibling_of(path)
+                            .map_or((*path, None), |(path, encoding)| (path, Some(encoding)));
+                        let mut headers = vec![
+                            ("Content-Type", content_type(original)),
+                            ("Cache-Control", self.cache_control(original)),
+                        ];
+                        if let Some(encoding) = encoding {
+                            headers.push(("Content-Encoding", encoding.name()));
+                        }
+                        self.send(reqwest::Method::PUT, path, body, &headers)
+                    })
+                    .and_then(Self::check);
+                uploaded();
+                result.err().map(|e| (path.to_string(), e))
+            })
+            .collect::<Vec<_>>();
+        errors.extend(
+            removals
+                .par_iter()
+                .filter_map(|path| {
+                    self.send(reqwest::Method::DELETE, path, Vec::new(), &[])
+                        .and_then(Self::check)
+                        .err()
+                        .map(|e| (path.to_string(), e))
+                })
+                .collect::<Vec<_>>(),
+        );
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Percent-encodes all but unreserved characters and slashes, as S3 expects.
+fn uri_encode(s: &str) -> String {
+    let mut ret = String::new();
+    for byte in s.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.~/".contains(&byte) {
+            ret.push(byte as char);
+        } else {
+            write!(ret, "%{byte:02X}").unwrap();
+        }
+    }
+    ret
+}
+
+fn hmac(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_paths_stay_inside() {
+        assert_eq!(relative("/a/index.html"), Ok("a/index.html"));
+        assert_eq!(relative("/a..b.jpg"), Ok("a..b.jpg"));
+        assert!(relative("a/index.html").is_err());
+        assert!(relative("/../index.html").is_err());
+        assert!(relative("/a/../../index.html").is_err());
+        assert!(relative("/a/..").is_err());
+    }
+
+    #[test]
+    fn encodes_uris_like_s3() {
+        assert_eq!(uri_encode("photos/a-b_c.d~e.jpg"), "photos/a-b_c.d~e.jpg");
+        assert_eq!(uri_encode("a b+c.jpg"), "a%20b%2Bc.jpg");
+        assert_eq!(uri_encode("ü?"), "%C3%BC%3F");
+    }
+
+    #[test]
+    fn signs_with_hmac_sha256() {
+        // From RFC 4231.
+        assert_eq!(
+            hex(&hmac(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::{
+    gallery::{CategoryPath, Gallery, Item, Photo},
+    output::{render_fragment, rich_text_html},
+};
+use chrono::{NaiveDateTime, SecondsFormat};
+use serde::Serialize;
+use std::fmt::Write;
+use yew::html;
+
+/// Maximum number of photos in a feed.
+const FEED_ENTRIES: usize = 50;
+
+struct FeedEntry<'a> {
+    path: CategoryPath,
+    photo: &'a Photo,
+    date_time: NaiveDateTime,
+}
+
+/// The newest photos in `category`, including its subcategories, newest first.
+fn feed_entries<'a>(gallery: &'a Gallery, category: &CategoryPath) -> Vec<FeedEntry<'a>> {
+    let mut ret = Vec::new();
+    let mut visitor = |path: &CategoryPath, item: &'a Item| {
+        let Some(photo) = item.photo() else {
+            return;
+        };
+        let Some(date_time) = photo.date_time() else {
+            return;
+        };
+        ret.push(FeedEntry {
+            path: path.clone(),
+            photo,
+            date_time,
+        });
+    };
+    if let Some(parent) = category.pop() {
+        if let Some(category) = gallery.category(category) {
+            if let Some(album) = &category.album {
+                for (path, photo) in gallery.album_photos(album) {
+                    if let Some(date_time) = photo.date_time() {
+                        ret.push(FeedEntry {
+                            path,
+                            photo,
+                            date_time,
+                        });
+                    }
+                }
+            } else {
+                category.visit_items(&parent, &mut visitor);
+            }
+        }
+    } else {
+        gallery.visit_items(&mut visitor);
+    }
+    ret.sort_by(|a, b| {
+        b.date_time
+            .cmp(&a.date_time)
+            .then_with(|| a.photo.name.cmp(&b.photo.name))
+    });
+    ret.truncate(FEED_ENTRIES);
+    ret
+}
+
+fn feed_title(gallery: &Gallery, category: &CategoryPath) -> String {
+    if category.is_root() {
+        gallery.config.title.clone()
+    } else {
+        format!("{} | {}", gallery.item_name(category), gallery.config.title)
+    }
+}
+
+fn feed_home_page(gallery: &Gallery, category: &CategoryPath) -> String {
+    if let Some(parent) = category.pop() {
+        gallery
+            .config
+            .category_html::<true>(&parent, category.last_segment().unwrap(), 0)
+    } else {
+        gallery.config.index_html::<true>(0)
+    }
+}
+
+/// Preview image followed by the caption, if any.
+fn content_html(gallery: &Gallery, root_url: &str, entry: &FeedEntry<'_>) -> String {
+    let photo = entry.photo;
+    render_fragment(html! {<>
+        <p>
+            <img
+                src={format!("{root_url}{}", gallery.config.preview::<true>(&entry.path, &photo.slug()))}
+                alt={photo.config.description.clone().unwrap_or_else(|| photo.output_name().to_owned())}
+            />
+        </p>
+        if let Some(text) = &photo.text {
+            {rich_text_html(text)}
+        }
+    </>})
+}
+
+fn author<'a>(gallery: &'a Gallery, photo: &'a Photo) -> Option<&'a str> {
+    photo
+        .config
+        .author
+        .as_deref()
+        .or(gallery.config.author.as_deref())
+}
+
+fn license_url<'a>(gallery: &'a Gallery, photo: &'a Photo) -> Option<&'a str> {
+    photo
+        .config
+        .license_url
+        .as_deref()
+        .or(gallery.config.license_url.as_deref())
+}
+
+fn rfc3339(gallery: &Gallery, date_time: NaiveDateTime, photo: Option<&Photo>) -> String {
+    gallery
+        .config
+        .date_time_with_offset(date_time, photo.and_then(Photo::utc_offset))
+        .to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn escape_xml(s: &str) -> String {
+    let mut ret = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => ret.push_str("&amp;"),
+            '<' => ret.push_str("&lt;"),
+            '>' => ret.push_str("&gt;"),
+            '"' => ret.push_str("&quot;"),
+            '\'' => ret.push_str("&apos;"),
+            c => ret.push(c),
+        }
+    }
+    ret
+}
+
+/// Atom feed of the newest photos in `category`.
+pub fn write_atom_feed(gallery: &Gallery, category: &CategoryPath) -> Vec<u8> {
+    let config = &gallery.config;
+    let root_url = config.root_url.as_deref().unwrap_or_default();
+    let entries = feed_entries(gallery, category);
+    let home_page = format!("{root_url}{}", feed_home_page(gallery, category));
+
+    let mut xml = String::new();
+    writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#).unwrap();
+    writeln!(xml, r#"<feed xmlns="http://www.w3.org/2005/Atom">"#).unwrap();
+    writeln!(
+        xml,
+        "\t<title>{}</title>",
+        escape_xml(&feed_title(gallery, category))
+    )
+    .unwrap();
+    let description = gallery
+        .category(category)
+        .and_then(|c| c.config.description.as_ref())
+        .or(config.description.as_ref());
+    if let Some(description) = description {
+        writeln!(xml, "\t<subtitle>{}</subtitle>", escape_xml(description)).unwrap();
+    }
+    writeln!(xml, "\t<id>{}</id>", escape_xml(&home_page)).unwrap();
+    writeln!(xml, r#"	<link href="{}"/>"#, escape_xml(&home_page)).unwrap();
+    writeln!(
+        xml,
+        r#"	<link rel="self" href="{}{}"/>"#,
+        escape_xml(root_url),
+        escape_xml(&config.feed_xml::<true>(category))
+    )
+    .unwrap();
+    writeln!(
+        xml,
+        "\t<updated>{}</updated>",
+        entries.first().map_or_else(
+            || rfc3339(gallery, NaiveDateTime::UNIX_EPOCH, None),
+            |e| rfc3339(gallery, e.date_time, Some(e.photo))
+        )
+    )
+    .unwrap();
+    // Required, unless every entry has one.
+    write!(
+        xml,
+        "\t<author><name>{}</name>",
+        escape_xml(config.author.as_ref().unwrap_or(&config.title))
+    )
+    .unwrap();
+    if let Some(author_url) = &config.author_url {
+        write!(xml, "<uri>{}</uri>", escape_xml(author_url)).unwrap();
+    }
+    writeln!(xml, "</author>").unwrap();
+    if let Some(license_url) = &config.license_url {
+        writeln!(xml, "\t<rights>{}</rights>", escape_xml(license_url)).unwrap();
+    }
+    writeln!(
+        xml,
+        r#"	<generator uri="https://github.com/finnbear/chillphoto">chillphoto</generator>"#
+    )
+    .unwrap();
+    if gallery.favicon.is_some() {
+        writeln!(
+            xml,
+            "\t<icon>{}{}</icon>",
+            escape_xml(root_url),
+            escape_xml(&config.favicon::<true>())
+        )
+        .unwrap();
+    }
+
+    for entry in &entries {
+        let photo = entry.photo;
+        let url = format!(
+            "{root_url}{}",
+            config.photo_html::<true>(&entry.path, &photo.slug())
+        );
+        writeln!(xml, "\t<entry>").unwrap();
+        writeln!(
+            xml,
+            "\t\t<title>{}</title>",
+            escape_xml(photo.output_name())
+        )
+        .unwrap();
+        writeln!(xml, "\t\t<id>{}</id>", escape_xml(&url)).unwrap();
+        writeln!(
+            xml,
+            r#"		<link rel="alternate" type="text/html" href="{}"/>"#,
+            escape_xml(&url)
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            r#"		<link rel="enclosure" type="{}" href="{}{}"/>"#,
+            config.preview_format.mime_type(),
+            escape_xml(root_url),
+            escape_xml(&config.preview::<true>(&entry.path, &photo.slug()))
+        )
+        .unwrap();
+        writeln!(
+            xml,
+            "\t\t<updated>{}</updated>",
+            rfc3339(gallery, entry.date_time, Some(photo))
+        )
+        .unwrap();
+        if let Some(author) = author(gallery, photo) {
+            writeln!(
+                xml,
+                "\t\t<author><name>{}</name></author>",
+                escape_xml(author)
+            )
+            .unwrap();
+        }
+        if let Some(license_url) = license_url(gallery, photo) {
+            writeln!(xml, "\t\t<rights>{}</rights>", escape_xml(license_url)).unwrap();
+        }
+        if let Some(description) = &photo.config.description {
+            writeln!(xml, "\t\t<summary>{}</summary>", escape_xml(description)).unwrap();
+        }
+        writeln!(
+            xml,
+            r#"		<content type="html">{}</content>"#,
+            escape_xml(&content_html(gallery, root_url, entry))
+        )
+        .unwrap();
+        writeln!(xml, "\t</entry>").unwrap();
+    }
+
+    writeln!(xml, "</feed>").unwrap();
+    xml.into_bytes()
+}
+
+/// JSON Feed (version 1.1) of the newest photos in `category`.
+pub fn write_json_feed(gallery: &Gallery, category: &CategoryPath) -> Vec<u8> {
+    let config = &gallery.config;
+    let root_url = config.root_url.as_deref().unwrap_or_default();
+
+    let feed = JsonFeed {
+        version: "https://jsonfeed.org/version/1.1",
+        title: feed_title(gallery, category),
+        home_page_url: format!("{root_url}{}", feed_home_page(gallery, category)),
+        feed_url: format!("{root_url}{}", config.feed_json::<true>(category)),
+        description: gallery
+            .category(category)
+            .and_then(|c| c.config.description.clone())
+            .or(config.description.clone()),
+        favicon: gallery
+            .favicon
+            .is_some()
+            .then(|| format!("{root_url}{}", config.favicon::<true>())),
+        authors: config
+            .author
+            .iter()
+            .map(|name| JsonFeedAuthor {
+                name: name.clone(),
+                url: config.author_url.clone(),
+            })
+            .collect(),
+        language: config.language.clone(),
+        items: feed_entries(gallery, category)
+            .iter()
+            .map(|entry| {
+                let photo = entry.photo;
+                let url = format!(
+                    "{root_url}{}",
+                    config.photo_html::<true>(&entry.path, &photo.slug())
+                );
+                let preview = format!(
+                    "{root_url}{}",
+                    config.preview::<true>(&entry.path, &photo.slug())
+                );
+                JsonFeedItem {
+                    id: url.clone(),
+                    url,
+                    title: photo.output_name().to_owned(),
+                    content_html: content_html(gallery, root_url, entry),
+                    summary: photo.config.description.clone(),
+                    image: preview.clone(),
+                    date_published: rfc3339(gallery, entry.date_time, Some(photo)),
+                    authors: author(gallery, photo)
+                        .map(|name| JsonFeedAuthor {
+                            name: name.to_owned(),
+                            url: None,
+                        })
+                        .into_iter()
+                        .collect(),
+                    attachments: vec![JsonFeedAttachment {
+                        url: preview,
+                        mime_type: config.preview_format.mime_type(),
+                    }],
+                    license: license_url(gallery, photo).map(|url| JsonFeedLicense {
+                        url: url.to_owned(),
+                    }),
+                }
+            })
+            .collect(),
+    };
+
+    serde_json::to_string(&feed).unwrap().into_bytes()
+}
+
+#[derive(Serialize)]
+struct JsonFeed {
+    version: &'static str,
+    title: String,
+    home_page_url: String,
+    feed_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    favicon: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<JsonFeedAuthor>,
+    language: String,
+    items: Vec<JsonFeedItem>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAuthor {
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedItem {
+    id: String,
+    url: String,
+    title: String,
+    content_html: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    summary: Option<String>,
+    image: String,
+    date_published: String,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    authors: Vec<JsonFeedAuthor>,
+    attachments: Vec<JsonFeedAttachment>,
+    /// Extension, since JSON Feed has no license field.
+    #[serde(rename = "_license", skip_serializing_if = "Option::is_none")]
+    license: Option<JsonFeedLicense>,
+}
+
+#[derive(Serialize)]
+struct JsonFeedAttachment {
+    url: String,
+    mime_type: &'static str,
+}
+
+#[derive(Serialize)]
+struct JsonFeedLicense {
+    url: String,
+}
@@ -1,3 +1,8 @@
+use image::{
+    codecs::{avif::AvifEncoder, png::PngEncoder, webp::WebPEncoder},
+    ImageEncoder, RgbImage,
+};
+use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
 use serde::{Deserialize, Serialize};
 use std::{fmt::Display, str::FromStr};
 
@@ -9,6 +14,8 @@ pub enum OutputFormat {
     Jpg,
     #[serde(rename = "webp")]
     WebP,
+    #[serde(rename = "avif")]
+    Avif,
 }
 
 impl Display for OutputFormat {
@@ -33,4 +40,87 @@ impl OutputFormat {
             .trim_matches('"')
             .to_owned()
     }
+
+    /// Of an output file, if it's an image.
+    pub fn from_path(path: &str) -> Option<Self> {
+        path.rsplit_once('.')?.1.parse().ok()
+    }
+
+    pub fn mime_type(self) -> &'static str {
+        match self {
+            Self::Png => "image/png",
+            Self::Jpg => "image/jpeg",
+            Self::WebP => "image/webp",
+            Self::Avif => "image/avif",
+        }
+    }
+
+    pub fn encode(self, img: &RgbImage, options: &EncodeOptions) -> Vec<u8> {
+        let mut ret = Vec::new();
+        let (width, height) = img.dimensions();
+        match self {
+            Self::Png => PngEncoder::new(&mut ret)
+                .write_image(img, width, height, image::ExtendedColorType::Rgb8)
+                .unwrap(),
+            Self::Jpg => {
+                let mut encoder =
+                    Encoder::new(&mut ret, options.quality.unwrap_or(75).clamp(1, 100));
+                encoder.set_sampling_factor(match options.chroma_subsampling.unwrap_or_default() {
+                    ChromaSubsampling::Full => SamplingFactor::R_4_4_4,
+                    ChromaSubsampling::Half => SamplingFactor::R_4_2_2,
+                    ChromaSubsampling::Quarter => SamplingFactor::R_4_2_0,
+                });
+                encoder.set_progressive(options.progressive);
+                encoder
+                    .encode(img, width as u16, height as u16, ColorType::Rgb)
+                    .unwrap();
+            }
+            Self::WebP => WebPEncoder::new_lossless(&mut ret)
+                .write_image(img, width, height, image::ExtendedColorType::Rgb8)
+                .unwrap(),
+            Self::Avif => AvifEncoder::new_with_speed_quality(
+                &mut ret,
+                options.speed.unwrap_or(4).clamp(1, 10),
+                options.quality.unwrap_or(80).clamp(1, 100),
+            )
+            .write_image(img, width, height, image::ExtendedColorType::Rgb8)
+            .unwrap(),
+        }
+        ret
+    }
+}
+
+/// Encoder settings for one of the output formats. Options that don't
+/// apply to the format are ignored.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EncodeOptions {
+    /// 1 to 100, for JPEG (default 75) and AVIF (default 80).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub quality: Option<u8>,
+    /// 1 (slowest, smallest) to 10 (fastest), for AVIF (default 4).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub speed: Option<u8>,
+    /// For JPEG (default 4:4:4).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chroma_subsampling: Option<ChromaSubsampling>,
+    /// For JPEG.
+    #[serde(default)]
+    pub progressive: bool,
+}
+
+impl EncodeOptions {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ChromaSubsampling {
+    #[default]
+    #[serde(rename = "4:4:4")]
+    Full,
+    #[serde(rename = "4:2:2")]
+    Half,
+    #[serde(rename = "4:2:0")]
+    Quarter,
 }
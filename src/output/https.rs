@@ -0,0 +1,116 @@
+use crate::{gallery::GalleryConfig, util::checksum};
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection, StreamOwned,
+};
+use std::{
+    fs,
+    io::{self, Read, Write},
+    net::{IpAddr, TcpStream, UdpSocket},
+    path::Path,
+    sync::Arc,
+};
+
+/// A connection accepted by `serve`, which may be encrypted.
+pub enum Connection {
+    Plain(TcpStream),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Connection {
+    /// Encrypted if there is a `tls` config. The handshake happens on
+    /// the first read.
+    pub fn new(stream: TcpStream, tls: Option<&Arc<ServerConfig>>) -> io::Result<Self> {
+        Ok(match tls {
+            Some(tls) => Self::Tls(Box::new(StreamOwned::new(
+                ServerConnection::new(Arc::clone(tls)).map_err(io::Error::other)?,
+                stream,
+            ))),
+            None => Self::Plain(stream),
+        })
+    }
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.read(buf),
+            Self::Tls(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(stream) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(stream) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// The address other devices on the local network can reach this one at,
+/// if it can be determined.
+pub fn lan_address() -> Option<IpAddr> {
+    // Doesn't send anything, but picks the interface with the default route.
+    let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+    socket.connect("192.0.2.1:80").ok()?;
+    Some(socket.local_addr().ok()?.ip()).filter(|ip| !ip.is_loopback())
+}
+
+/// Uses `serve_https_cert` and `serve_https_key`, if configured, and
+/// otherwise a self-signed certificate for `hosts`. That is kept in the
+/// cache directory, so browsers needn't be told to trust a new one every
+/// time.
+pub fn tls_config(config: &GalleryConfig, hosts: &[String]) -> Result<Arc<ServerConfig>, String> {
+    let (certificates, key) = match (&config.serve_https_cert, &config.serve_https_key) {
+        (Some(cert), Some(key)) => (
+            CertificateDer::pem_file_iter(cert)
+                .and_then(|certificates| certificates.collect::<Result<Vec<_>, _>>())
+                .map_err(|e| format!("couldn't read {cert}: {e}"))?,
+            PrivateKeyDer::from_pem_file(key).map_err(|e| format!("couldn't read {key}: {e}"))?,
+        ),
+        (None, None) => {
+            let path = Path::new(&config.cache)
+                .join("https")
+                .join(checksum(hosts.join(",").as_bytes()).replace('/', "_"));
+            let pem = match fs::read(&path) {
+                Ok(pem) => pem,
+                Err(_) => {
+                    let certified = rcgen::generate_simple_self_signed(hosts.to_vec())
+                        .map_err(|e| format!("couldn't generate certificate: {e}"))?;
+                    let pem = format!(
+                        "{}{}",
+                        certified.cert.pem(),
+                        certified.key_pair.serialize_pem()
+                    );
+                    // Best effort, as a new one can be generated next time.
+                    let _ = fs::create_dir_all(path.parent().unwrap())
+                        .and_then(|_| fs::write(&path, &pem));
+                    pem.into_bytes()
+                }
+            };
+            (
+                CertificateDer::pem_slice_iter(&pem)
+                    .collect::<Result<Vec<_>, _>>()
+                    .map_err(|e| e.to_string())?,
+                PrivateKeyDer::from_pem_slice(&pem).map_err(|e| e.to_string())?,
+            )
+        }
+        _ => return Err("serve_https_cert and serve_https_key must be set together".to_owned()),
+    };
+    ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+        .with_safe_default_protocol_versions()
+        .map_err(|e| e.to_string())?
+        .with_no_client_auth()
+        .with_single_cert(certificates, key)
+        .map(Arc::new)
+        .map_err(|e| e.to_string())
+}
@@ -0,0 +1,46 @@
+use crate::gallery::GalleryConfig;
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, fs, io::Write, path::{Path, PathBuf}};
+
+/// What `build` last wrote to the output directory, so that it can be
+/// reused even where extended attributes don't survive (e.g. copies,
+/// CI artifacts, or file systems without them). Kept in the cache
+/// directory, so that it isn't published with the output.
+#[derive(Default, Serialize, Deserialize)]
+pub struct Manifest {
+    /// By output path, like `/about/index.html`.
+    pub files: BTreeMap<String, ManifestEntry>,
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Checksum of everything the file is derived from, if known.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input: Option<String>,
+    /// Checksum of the file itself.
+    pub content: String,
+}
+
+impl Manifest {
+    fn path(config: &GalleryConfig) -> PathBuf {
+        // In a subdirectory, which pruning the cache leaves alone.
+        Path::new(&config.cache).join("build").join("manifest.json")
+    }
+
+    /// `None` if there is no manifest, or it can't be read.
+    pub fn load(config: &GalleryConfig) -> Option<Self> {
+        let json = fs::read(Self::path(config)).ok()?;
+        serde_json::from_slice(&json).ok()
+    }
+
+    pub fn save(&self, config: &GalleryConfig) {
+        let path = Self::path(config);
+        let json = serde_json::to_string_pretty(self).unwrap();
+        // So that an interrupted build never leaves a truncated manifest.
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir).unwrap();
+        let mut file = tempfile::NamedTempFile::new_in(dir).unwrap();
+        file.write_all(json.as_bytes()).unwrap();
+        file.persist(path).unwrap();
+    }
+}
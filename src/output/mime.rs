@@ -0,0 +1,28 @@
+use crate::output::OutputFormat;
+
+/// `Content-Type` of an output file, by its path (which may have a query).
+pub fn content_type(path: &str) -> &'static str {
+    let path = path.split('?').next().unwrap();
+    if let Some(format) = OutputFormat::from_path(path) {
+        return format.mime_type();
+    }
+    let name = path.rsplit('/').next().unwrap();
+    match name.rsplit_once('.').map(|(_, extension)| extension) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("xml") if name.starts_with("feed") => "application/atom+xml",
+        Some("xml") => "application/xml",
+        Some("json") if name.starts_with("feed") => "application/feed+json",
+        Some("json") => "application/json",
+        Some("webmanifest") => "application/manifest+json",
+        Some("svg") => "image/svg+xml",
+        Some("ico") => "image/x-icon",
+        Some("gif") => "image/gif",
+        Some("pdf") => "application/pdf",
+        Some("zip") => "application/zip",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
@@ -1,10 +1,16 @@
 use crate::{
-    gallery::{CategoryPath, Gallery, GalleryConfig, Item, Order, Page, Photo},
-    output::search::render_search,
-    util::checksum,
+    diagnostics::Diagnostics,
+    gallery::{
+        resized_dimensions, tag_slug, CategoryPath, Gallery, GalleryConfig, Item, Order, Page,
+        Photo,
+    },
+    output::{not_found::render_not_found, search::render_search},
+    util::{checksum, join},
 };
 use chrono::Datelike;
-use image::{ImageFormat, RgbImage};
+use image::RgbImage;
+use minijinja::Value;
+use serde::Serialize;
 use sitemap_rs::{
     image::Image,
     url::{ChangeFrequency, Url},
@@ -15,7 +21,6 @@ use std::{
     collections::HashMap,
     fmt::Write,
     fs,
-    io::Cursor,
     sync::{Arc, LazyLock},
 };
 use xmp_toolkit::{xmp_ns, OpenFileOptions, XmpMeta, XmpValue};
@@ -23,25 +28,52 @@ use yew::{html, Html};
 
 mod api;
 mod app;
+mod archive;
 mod build;
+mod cache;
+mod compress;
+mod deploy;
+mod feed;
 mod format;
+mod https;
+mod manifest;
+mod mime;
+mod not_found;
 mod pwa;
 mod rich_text;
 mod search;
 mod serve;
 mod structured_data;
+mod tags;
+mod theme;
+mod watch;
 
 pub use api::*;
 pub use app::*;
+pub use archive::*;
 pub use build::*;
+pub use cache::*;
+pub use compress::*;
+pub use deploy::*;
+pub use feed::*;
 pub use format::*;
+pub use https::*;
+pub use manifest::*;
+pub use mime::*;
 pub use pwa::*;
 pub use rich_text::*;
 pub use serve::*;
 pub use structured_data::*;
+pub use tags::*;
+pub use theme::*;
+pub use watch::*;
 
 pub type DynLazy<'a, T> = LazyLock<T, Box<dyn FnOnce() -> T + Send + Sync + 'a>>;
 
+/// By output path, files (`None` if they can't be generated) and hashes of
+/// what they're derived from, if known.
+pub type Output<'a> = HashMap<String, (DynLazy<'a, Option<Vec<u8>>>, Option<DynLazy<'a, String>>)>;
+
 fn page_items<'a>(gallery: &'a Gallery, path: &CategoryPath) -> Vec<(String, &'a Page)> {
     let mut ret: Vec<(String, &'a Page)> = path
         .iter_paths()
@@ -60,16 +92,16 @@ fn page_items<'a>(gallery: &'a Gallery, path: &CategoryPath) -> Vec<(String, &'a
 }
 
 impl Gallery {
-    pub fn output<'a>(
-        &'a self,
-    ) -> HashMap<String, (DynLazy<'a, Vec<u8>>, Option<DynLazy<'a, String>>)> {
+    /// Files that can't be generated, like images of photos that can't be
+    /// decoded, are `None` and reported to `diagnostics`.
+    pub fn output<'a>(&'a self, diagnostics: &'a Diagnostics) -> Output<'a> {
         let config = &self.config;
 
-        let mut ret = HashMap::<String, (DynLazy<'a, Vec<u8>>, Option<DynLazy<'a, String>>)>::new();
-        fn ret_insert<'a>(
-            ret: &mut HashMap<String, (DynLazy<'a, Vec<u8>>, Option<DynLazy<'a, String>>)>,
+        let mut ret = Output::new();
+        fn ret_insert_fallible<'a>(
+            ret: &mut Output<'a>,
             path: String,
-            file: DynLazy<'a, Vec<u8>>,
+            file: DynLazy<'a, Option<Vec<u8>>>,
             hash: Option<DynLazy<'a, String>>,
         ) {
             assert!(
@@ -77,6 +109,15 @@ impl Gallery {
                 "duplicate {path}"
             );
         }
+        fn ret_insert<'a>(
+            ret: &mut Output<'a>,
+            path: String,
+            mut file: DynLazy<'a, Vec<u8>>,
+            hash: Option<DynLazy<'a, String>>,
+        ) {
+            let file = LazyLock::new(Box::new(move || Some(std::mem::take(&mut *file))) as Box<_>);
+            ret_insert_fallible(ret, path, file, hash);
+        }
         let mut sitemap = Vec::<Url>::new();
 
         let root_og_image = self.thumbnail().map(|(path, preview)| {
@@ -86,6 +127,49 @@ impl Gallery {
             )
         });
 
+        // What every page depends on, besides its own content.
+        let site_hash = {
+            let root_og_image = root_og_image.clone();
+            Arc::new(LazyLock::new(move || {
+                /// Edit this when there is a breaking change.
+                const BREAKING_CHANGE: usize = 0;
+                let mut has_tags = false;
+                self.visit_items(|_, item| {
+                    has_tags |= item.photo().is_some_and(|photo| !photo.tags.is_empty());
+                });
+                let to_hash = format!(
+                    "{:?}{}{:?}{:?}{:?}{:?}{has_tags}{}{BREAKING_CHANGE}",
+                    self.config,
+                    self.theme.fingerprint(),
+                    self.head_html,
+                    self.favicon.is_some(),
+                    self.editable,
+                    root_og_image,
+                    env!("CARGO_PKG_VERSION"),
+                );
+                checksum(to_hash.as_bytes())
+            }))
+        };
+        // What pages listing items from across the gallery, like search,
+        // archives and feeds, depend on.
+        let whole_gallery_hash = {
+            let site_hash = Arc::clone(&site_hash);
+            Arc::new(LazyLock::new(move || {
+                let mut to_hash = format!(
+                    "{}{:?}{:?}",
+                    **site_hash, self.home_text, self.not_found_text
+                );
+                self.visit_items(|path, item| {
+                    write!(to_hash, "{path:?}{}", item_summary(self, path, item)).unwrap();
+                });
+                checksum(to_hash.as_bytes())
+            }))
+        };
+        let gallery_hash = || -> DynLazy<'a, String> {
+            let whole_gallery_hash = Arc::clone(&whole_gallery_hash);
+            LazyLock::new(Box::new(move || String::clone(&whole_gallery_hash)))
+        };
+
         self.visit_items(|path, item| {
             let path = path.clone();
             match item {
@@ -113,16 +197,17 @@ impl Gallery {
                         photo.input_image_data_checksum()
                     }));
 
-                    let hash_factory  = |key: &'static str| -> DynLazy<'a, String> {
+                    let hash_factory  = |key: String| -> DynLazy<'a, String> {
                         let input_image_data_hash = Arc::clone(&input_image_data_hash);
                         LazyLock::new(Box::new(move || {
                             /// Edit this when there is a breaking change.
                             const BREAKING_CHANGE : usize = 0;
                             let to_hash = format!(
-                                "{:?}{key:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{BREAKING_CHANGE}",
+                                "{:?}{key:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{BREAKING_CHANGE}",
                                 **input_image_data_hash,
-                                self.config,
+                                self.config.image_hash_input(),
                                 photo.config,
+                                photo.tags,
                                 photo.distinct_name,
                                 photo.exif(),
                                 photo.file_date,
@@ -137,29 +222,49 @@ impl Gallery {
                         }))
                     };
 
-                    ret_insert(&mut ret,
+                    ret_insert_fallible(&mut ret,
                         photo_path.clone(),
                         LazyLock::new(Box::new(move || {
-                            write_image(&photo.image(&self.config), &photo_path, xmp)
+                            Some(write_image(&*self.photo_image_or_report(photo, diagnostics)?, &photo_path, &self.config.photo_encoding, xmp))
                         })),
-                        Some(hash_factory("image")),
+                        Some(hash_factory("image".to_owned())),
                     );
                     let preview_path = config.preview::<false>(&path, &photo.slug());
-                    ret_insert(&mut ret,
+                    ret_insert_fallible(&mut ret,
                         preview_path.clone(),
                         LazyLock::new(Box::new(move || {
-                            write_image(&photo.preview(&self.config), &preview_path, xmp)
+                            Some(write_image(&photo.preview_of(&*self.photo_image_or_report(photo, diagnostics)?, config.preview_resolution), &preview_path, &self.config.preview_encoding, xmp))
                         })),
-                        Some(hash_factory("preview")),
+                        Some(hash_factory("preview".to_owned())),
                     );
                     let thumbnail_path = config.thumbnail::<false>(&path, &photo.slug());
-                    ret_insert(&mut ret,
+                    ret_insert_fallible(&mut ret,
                         thumbnail_path.clone(),
                         LazyLock::new(Box::new(move || {
-                            write_image(&photo.thumbnail(&self.config), &thumbnail_path, xmp)
+                            Some(write_image(&photo.thumbnail_of(&*self.photo_image_or_report(photo, diagnostics)?, config.thumbnail_resolution), &thumbnail_path, &self.config.thumbnail_encoding, xmp))
                         })),
-                        Some(hash_factory("thumbnail")),
+                        Some(hash_factory("thumbnail".to_owned())),
                     );
+                    for (resolution, format) in config.preview_variants().into_iter().skip(1) {
+                        let preview_path = config.preview_variant::<false>(&path, &photo.slug(), resolution, format);
+                        ret_insert_fallible(&mut ret,
+                            preview_path.clone(),
+                            LazyLock::new(Box::new(move || {
+                                Some(write_image(&photo.preview_of(&*self.photo_image_or_report(photo, diagnostics)?, resolution), &preview_path, &self.config.preview_encoding, xmp))
+                            })),
+                            Some(hash_factory(format!("preview-{resolution}-{format}"))),
+                        );
+                    }
+                    for (resolution, format) in config.thumbnail_variants().into_iter().skip(1) {
+                        let thumbnail_path = config.thumbnail_variant::<false>(&path, &photo.slug(), resolution, format);
+                        ret_insert_fallible(&mut ret,
+                            thumbnail_path.clone(),
+                            LazyLock::new(Box::new(move || {
+                                Some(write_image(&photo.thumbnail_of(&*self.photo_image_or_report(photo, diagnostics)?, resolution), &thumbnail_path, &self.config.thumbnail_encoding, xmp))
+                            })),
+                            Some(hash_factory(format!("thumbnail-{resolution}-{format}"))),
+                        );
+                    }
 
                     let canonical = config.photo_html::<true>(&path, &photo.slug());
                     if let Some(root) = &self.config.root_url {
@@ -173,6 +278,21 @@ impl Gallery {
                     }
 
                     let page_items = page_items(self, &path);
+                    let page_hash = {
+                        let site_hash = Arc::clone(&site_hash);
+                        let path = path.clone();
+                        let page_items = page_items.clone();
+                        LazyLock::new(Box::new(move || {
+                            let to_hash = format!(
+                                "{}{}{:?}{}",
+                                **site_hash,
+                                ancestry(self, &path),
+                                page_names(&page_items),
+                                photo_summary(self, photo),
+                            );
+                            checksum(to_hash.as_bytes())
+                        }) as Box<dyn FnOnce() -> String + Send + Sync + 'a>)
+                    };
                     ret_insert(&mut ret,
                         config.photo_html::<false>(&path, &photo.slug()),
                         LazyLock::new(Box::new(move || {
@@ -205,13 +325,17 @@ impl Gallery {
                                         class="preview_container"
                                         href={config.photo::<true>(&path, &photo.slug())}
                                     >
-                                        <img
-                                            class="preview"
-                                            width={photo.preview_dimensions(config).0.to_string()}
-                                            height={photo.preview_dimensions(config).1.to_string()}
-                                            alt={photo.config.description.clone().unwrap_or_else(|| photo.output_name().to_owned())}
-                                            src={config.preview::<true>(&path, &photo.slug())}
-                                        />
+                                        {render_picture(
+                                            self,
+                                            &path,
+                                            photo,
+                                            false,
+                                            photo.config.description.clone().unwrap_or_else(|| photo.output_name().to_owned()),
+                                            None,
+                                        )}
+                                        if !photo.published(config) {
+                                            <span class="draft_badge">{"draft"}</span>
+                                        }
                                     </a>
                                     if let Some(text) = &photo.text {
                                         {rich_text_html(text)}
@@ -238,6 +362,16 @@ impl Gallery {
                                                 {location}
                                             </div>
                                         }
+                                        if !photo.tags.is_empty() {
+                                            <div
+                                                title={"Tags"}
+                                                class={"sidebar_details_panel_text"}
+                                            >
+                                                {join(&photo.tags.iter().map(|tag| html!{
+                                                    <a href={config.tags_html::<true>(Some(&tag_slug(tag)), 0)}>{tag.clone()}</a>
+                                                }).collect::<Vec<_>>(), &html!{{", "}})}
+                                            </div>
+                                        }
                                         if let Some(date_time) = photo.date_time() {
                                             <time
                                                 datetime={date_time.date().to_string()}
@@ -314,31 +448,84 @@ impl Gallery {
                                 index: true,
                             })
                         })),
-                        None
+                        Some(page_hash),
                     );
                 }
                 Item::Category(category) => {
                     let category_path = path.push(category.slug());
                     let page_items = page_items(self, &category_path);
-                    for chunk in paginate(&category.children, category.config.items_per_page) {
+                    if self.config.root_url.is_some() {
+                        let feed_path = category_path.clone();
+                        ret_insert(&mut ret,
+                            config.feed_xml::<false>(&category_path),
+                            LazyLock::new(Box::new(move || write_atom_feed(self, &feed_path))),
+                            Some(gallery_hash()),
+                        );
+                        let feed_path = category_path.clone();
+                        ret_insert(&mut ret,
+                            config.feed_json::<false>(&category_path),
+                            LazyLock::new(Box::new(move || write_json_feed(self, &feed_path))),
+                            Some(gallery_hash()),
+                        );
+                    }
+                    let items_per_page = category.config.items_per_page;
+                    // Smart albums link to photos elsewhere in the gallery.
+                    let bodies: Vec<Box<dyn FnOnce() -> Html + Send + Sync + 'a>> = match &category.album {
+                        Some(album) => paginate(&self.album_photos(album), items_per_page).map(|chunk| {
+                            let photos = chunk.items.to_vec();
+                            Box::new(move || render_photo_items(self, &photos)) as Box<dyn FnOnce() -> Html + Send + Sync + 'a>
+                        }).collect(),
+                        None => paginate(displayed_items(&category.children), items_per_page).map(|chunk| {
+                            let category_path = category_path.clone();
+                            Box::new(move || render_items(self, &category_path, chunk.items)) as Box<dyn FnOnce() -> Html + Send + Sync + 'a>
+                        }).collect(),
+                    };
+                    let count = bodies.len();
+                    for (index, body) in bodies.into_iter().enumerate() {
                         let path = path.clone();
                         let category_path = category_path.clone();
                         let page_items = page_items.clone();
                         let mut title = category.name.clone();
-                        if chunk.index != 0 {
-                            write!(title, " (page {})", chunk.index + 1).unwrap();
+                        if index != 0 {
+                            write!(title, " (page {})", index + 1).unwrap();
                         }
+                        let page_hash = {
+                            let site_hash = Arc::clone(&site_hash);
+                            let category_path = category_path.clone();
+                            let page_items = page_items.clone();
+                            LazyLock::new(Box::new(move || {
+                                let items: String = match &category.album {
+                                    Some(album) => self
+                                        .album_photos(album)
+                                        .into_iter()
+                                        .map(|(path, photo)| format!("{path:?}{}", photo_summary(self, photo)))
+                                        .collect(),
+                                    None => displayed_items(&category.children)
+                                        .iter()
+                                        .map(|item| item_summary(self, &category_path, item))
+                                        .collect(),
+                                };
+                                let to_hash = format!(
+                                    "{}{}{:?}{:?}{index}{count}{items}",
+                                    **site_hash,
+                                    ancestry(self, &category_path),
+                                    page_names(&page_items),
+                                    category.text,
+                                );
+                                checksum(to_hash.as_bytes())
+                            }) as Box<dyn FnOnce() -> String + Send + Sync + 'a>)
+                        };
                         ret_insert(&mut ret,
-                            config.category_html::<false>(&path, &category.slug(), chunk.index),
+                            config.category_html::<false>(&path, &category.slug(), index),
                             LazyLock::new(Box::new(move || {
                                 render_html(AppProps {
-                                    canonical: config.category_html::<true>(&path, &category.slug(), chunk.index),
+                                    canonical: config.category_html::<true>(&path, &category.slug(), index),
                                     gallery: self,
                                     title: title.into(),
                                     description: category.config.description.clone().map(|d| d.into()),
                                     head: Default::default(),
                                     body: html!{<>
-                                        {render_items(self, &category_path, chunk.items)}
+                                        {body()}
                                         if let Some(text) = &category.text {
                                             {rich_text_html(text)}
                                         }
@@ -346,25 +533,40 @@ impl Gallery {
                                     sidebar: Html::default(),
                                     pages: page_items,
                                     path: category_path.clone(),
-                                    relative: (chunk.count != 1).then_some(RelativeNavigation {
-                                        index: chunk.index,
-                                        count: chunk.count,
-                                        previous: (chunk.index != 0)
-                                            .then(|| config.category_html::<true>(&path, &category.slug(), chunk.index - 1)),
-                                        next: (chunk.index != chunk.count - 1)
-                                            .then(|| config.category_html::<true>(&path, &category.slug(), chunk.index + 1)),
+                                    relative: (count != 1).then_some(RelativeNavigation {
+                                        index,
+                                        count,
+                                        previous: (index != 0)
+                                            .then(|| config.category_html::<true>(&path, &category.slug(), index - 1)),
+                                        next: (index != count - 1)
+                                            .then(|| config.category_html::<true>(&path, &category.slug(), index + 1)),
                                     }),
-                                    og_image: category.thumbnail(&path).map(|(path, preview)| (self.config.preview::<true>(&path, &preview.slug()), preview.preview_dimensions(&self.config))),
+                                    og_image: self.category_thumbnail(&path, category).map(|(path, preview)| (self.config.preview::<true>(&path, &preview.slug()), preview.preview_dimensions(&self.config))),
                                     index: true,
                                 })
                             })),
-                            None,
+                            Some(page_hash),
                         );
                     };
                 }
                 Item::Page(page) => {
                     let page_items = page_items(self, &path);
                     let root_thumbnail = root_og_image.clone();
+                    let page_hash = {
+                        let site_hash = Arc::clone(&site_hash);
+                        let path = path.clone();
+                        let page_items = page_items.clone();
+                        LazyLock::new(Box::new(move || {
+                            let to_hash = format!(
+                                "{}{}{:?}{:?}",
+                                **site_hash,
+                                ancestry(self, &path),
+                                page_names(&page_items),
+                                page,
+                            );
+                            checksum(to_hash.as_bytes())
+                        }) as Box<dyn FnOnce() -> String + Send + Sync + 'a>)
+                    };
                     ret_insert(&mut ret,
                         config.page_html::<false>(&path, &page.slug()),
                         LazyLock::new(Box::new(move || {
@@ -393,7 +595,7 @@ impl Gallery {
                                 index: true,
                             })
                         })),
-                        None
+                        Some(page_hash),
                     );
                 }
             }
@@ -405,7 +607,12 @@ impl Gallery {
                 &mut ret,
                 favicon_path.clone(),
                 LazyLock::new(Box::new(move || {
-                    write_image(self.favicon().unwrap(), &favicon_path, None)
+                    write_image(
+                        self.favicon().unwrap(),
+                        &favicon_path,
+                        &EncodeOptions::default(),
+                        None,
+                    )
                 })),
                 None,
             );
@@ -416,20 +623,24 @@ impl Gallery {
             &mut ret,
             manifest_path.clone(),
             LazyLock::new(Box::new(move || write_manifest(self))),
-            None,
+            Some(gallery_hash()),
         );
 
         if let Some((_, thumbnail)) = self.thumbnail() {
             let manifest_path = "/manifest.png".to_owned();
-            ret_insert(
+            ret_insert_fallible(
                 &mut ret,
                 manifest_path.clone(),
                 LazyLock::new(Box::new(move || {
-                    write_image(
-                        &thumbnail.custom_thumbnail(&self.config, MANIFEST_ICON_RESOLUTION),
+                    Some(write_image(
+                        &thumbnail.thumbnail_of(
+                            &*self.photo_image_or_report(thumbnail, diagnostics)?,
+                            MANIFEST_ICON_RESOLUTION,
+                        ),
                         &manifest_path,
+                        &EncodeOptions::default(),
                         None,
-                    )
+                    ))
                 })),
                 None,
             );
@@ -458,19 +669,85 @@ impl Gallery {
                         og_image: root_og_image,
                     })
                 })),
-                None,
+                Some(gallery_hash()),
+            );
+        }
+        {
+            let page_items = page_items.clone();
+            let root_og_image = root_og_image.clone();
+            ret_insert(
+                &mut ret,
+                self.config.not_found_html::<false>(),
+                LazyLock::new(Box::new(move || {
+                    render_html(AppProps {
+                        canonical: self.config.not_found_html::<true>(),
+                        gallery: self,
+                        title: format!("Page not found | {}", self.config.title).into(),
+                        description: None,
+                        head: Default::default(),
+                        body: render_not_found(self, &page_items),
+                        index: false,
+                        sidebar: Html::default(),
+                        pages: page_items,
+                        path: CategoryPath::ROOT.push("404".to_owned()),
+                        relative: None,
+                        og_image: root_og_image,
+                    })
+                })),
+                Some(gallery_hash()),
             );
         }
+        for (path, file) in self.archive_output(&page_items, root_og_image.clone()) {
+            ret_insert(&mut ret, path, file, Some(gallery_hash()));
+        }
+        for (path, file) in self.tags_output(&page_items, root_og_image.clone()) {
+            ret_insert(&mut ret, path, file, Some(gallery_hash()));
+        }
         ret_insert(
             &mut ret,
             self.config.api_json::<false>(),
             LazyLock::new(Box::new(move || render_api(self))),
-            None,
+            Some(gallery_hash()),
         );
+        if self.config.root_url.is_some() {
+            ret_insert(
+                &mut ret,
+                self.config.feed_xml::<false>(&CategoryPath::ROOT),
+                LazyLock::new(Box::new(move || write_atom_feed(self, &CategoryPath::ROOT))),
+                Some(gallery_hash()),
+            );
+            ret_insert(
+                &mut ret,
+                self.config.feed_json::<false>(&CategoryPath::ROOT),
+                LazyLock::new(Box::new(move || write_json_feed(self, &CategoryPath::ROOT))),
+                Some(gallery_hash()),
+            );
+        }
 
-        for chunk in paginate(&self.children, self.config.items_per_page) {
+        for chunk in paginate(displayed_items(&self.children), self.config.items_per_page) {
             let page_items = page_items.clone();
             let root_og_image = root_og_image.clone();
+            let page_hash = {
+                let site_hash = Arc::clone(&site_hash);
+                let page_items = page_items.clone();
+                LazyLock::new(Box::new(move || {
+                    let items: String = displayed_items(&self.children)
+                        .iter()
+                        .map(|item| item_summary(self, &CategoryPath::ROOT, item))
+                        .collect();
+                    let to_hash = format!(
+                        "{}{}{:?}{:?}{}{}{items}",
+                        **site_hash,
+                        ancestry(self, &CategoryPath::ROOT),
+                        page_names(&page_items),
+                        self.home_text,
+                        chunk.index,
+                        chunk.count,
+                    );
+                    checksum(to_hash.as_bytes())
+                })
+                    as Box<dyn FnOnce() -> String + Send + Sync + 'a>)
+            };
             ret_insert(
                 &mut ret,
                 self.config.index_html::<false>(chunk.index),
@@ -507,13 +784,15 @@ impl Gallery {
                         index: true,
                     })
                 })),
-                None,
+                Some(page_hash),
             );
         }
 
         if let Some(root_url) = &self.config.root_url {
+            let not_found = self.config.not_found_html::<false>();
             for page in ret
                 .keys()
+                .filter(|k| **k != not_found)
                 .filter_map(|k| {
                     let mut k = k.as_str();
                     let q = if let Some((p, q)) = k.split_once('?') {
@@ -557,7 +836,7 @@ impl Gallery {
                     sitemap.write(&mut ret).unwrap();
                     ret
                 })),
-                None,
+                Some(gallery_hash()),
             );
         }
 
@@ -576,7 +855,10 @@ impl Gallery {
                 }
                 robots_txt.into_bytes()
             })),
-            None,
+            {
+                let site_hash = Arc::clone(&site_hash);
+                Some(LazyLock::new(Box::new(move || String::clone(&site_hash))))
+            },
         );
 
         for file in &self.static_files {
@@ -592,29 +874,104 @@ impl Gallery {
     }
 }
 
-struct PageChunk<'a> {
-    items: &'a [Item],
+struct PageChunk<'a, T = Item> {
+    items: &'a [T],
     index: usize,
     #[allow(unused)]
     count: usize,
 }
 
-fn paginate(items: &[Item], items_per_page: usize) -> impl Iterator<Item = PageChunk<'_>> + '_ {
-    let items = &items[0..items
+/// Items that are displayed as thumbnails (i.e. not pages, which are sorted last).
+/// What a page at `path` depends on from its ancestors, which
+/// includes their names and the order of their items, for breadcrumbs
+/// and relative navigation.
+fn ancestry(gallery: &Gallery, path: &CategoryPath) -> String {
+    let mut ret = String::new();
+    for path in path.iter_paths() {
+        write!(
+            ret,
+            "{:?}{:?}{:?}",
+            gallery.item_name(&path),
+            gallery.category(&path).map(|category| &category.config),
+            gallery
+                .children(&path)
+                .map(|children| children.iter().map(|item| item.slug()).collect::<Vec<_>>()),
+        )
+        .unwrap();
+    }
+    ret
+}
+
+/// What pages showing `photo`, including listings of it, depend on from it.
+fn photo_summary(gallery: &Gallery, photo: &Photo) -> String {
+    format!(
+        "{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}{:?}",
+        photo.config,
+        photo.tags,
+        photo.distinct_name,
+        photo.exif(),
+        photo.output_name(),
+        photo.date_time(),
+        photo.slug(),
+        photo.text,
+        photo.published(&gallery.config),
+        photo.image_dimensions(&gallery.config),
+    )
+}
+
+/// What listings of the category at `path` depend on from `item`, which for
+/// a category includes its dates and thumbnail.
+fn item_summary(gallery: &Gallery, path: &CategoryPath, item: &Item) -> String {
+    match item {
+        Item::Photo(photo) => photo_summary(gallery, photo),
+        Item::Category(category) => format!(
+            "{:?}{:?}{:?}{:?}{:?}",
+            category.name,
+            category.config,
+            category.text,
+            gallery.category_dates(category),
+            gallery
+                .category_thumbnail(path, category)
+                .map(|(path, photo)| (path, photo_summary(gallery, photo))),
+        ),
+        Item::Page(page) => format!("{page:?}"),
+    }
+}
+
+/// What the sidebar shows of `page_items`.
+fn page_names<'a>(page_items: &'a [(String, &'a Page)]) -> Vec<(&'a str, &'a str)> {
+    page_items
+        .iter()
+        .map(|(href, page)| (href.as_str(), page.name.as_str()))
+        .collect()
+}
+
+fn displayed_items(items: &[Item]) -> &[Item] {
+    &items[0..items
         .iter()
         .rposition(|i| i.photo().is_some() || i.category().is_some())
-        .unwrap()
-        + 1];
-    let chunks = items.chunks(items_per_page);
+        .map_or(0, |i| i + 1)]
+}
+
+fn paginate<T>(items: &[T], items_per_page: usize) -> impl Iterator<Item = PageChunk<'_, T>> + '_ {
+    // Always at least one, possibly empty, page.
+    let chunks = if items.is_empty() {
+        vec![items]
+    } else {
+        items.chunks(items_per_page.max(1)).collect()
+    };
     let count = chunks.len();
-    chunks.enumerate().map(move |(index, items)| PageChunk {
-        items,
-        index,
-        count,
-    })
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(move |(index, items)| PageChunk {
+            items,
+            index,
+            count,
+        })
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Serialize)]
 pub struct RelativeNavigation {
     index: usize,
     count: usize,
@@ -627,45 +984,53 @@ fn render_items(gallery: &Gallery, category_path: &CategoryPath, items: &[Item])
         <section id="page_main_body_items" data-nosnippet="nosnippet">
             {items.iter().filter_map(|child| {
                 match child {
-                    Item::Photo(photo) => {
-                        let content_url = gallery.config.photo::<true>(&category_path, &photo.slug());
-                        let thumbnail_url = gallery.config.thumbnail::<true>(&category_path, &photo.slug());
-                        let html_url = gallery.config.photo_html::<true>(&category_path, &photo.slug());
-                        Some(html!{
-                            <a
-                                class="thumbnail_container"
-                                href={html_url.clone()}
-                            >
-                                <img
-                                    title={photo.output_name().to_owned()}
-                                    alt={photo.config.description.clone().unwrap_or_else(|| photo.output_name().to_owned())}
-                                    src={thumbnail_url.clone()}
-                                    class="thumbnail"
-                                />
-                                {write_structured_data(
-                                    photo_structured_data(gallery, photo, html_url, content_url, Some(thumbnail_url), false)
-                                )}
-                            </a>
-                        })
-                    }
+                    Item::Photo(photo) => Some(render_photo_item(gallery, category_path, photo)),
                     Item::Category(category) => {
-                        let (photo_path, photo) = category.thumbnail(category_path)?;
+                        let (photo_path, photo) = gallery.category_thumbnail(category_path, category)?;
                         let thumbnail_url = gallery.config.thumbnail::<true>(&photo_path, &photo.slug());
+                        let html_url = gallery.config.category_html::<true>(category_path, &category.slug(), 0);
+                        let structured_data = write_structured_data(
+                            photo_structured_data(
+                                gallery,
+                                photo,
+                                gallery.config.photo_html::<true>(&photo_path, &photo.slug()),
+                                gallery.config.photo::<true>(&photo_path, &photo.slug()),
+                                Some(thumbnail_url.clone()),
+                                false
+                            )
+                        );
+                        if gallery.theme.overrides("item.html") {
+                            let dates = gallery.category_dates(category);
+                            if let Some(html) = gallery.theme.render("item.html", ItemThemeContext {
+                                gallery: ThemeGallery::new(&gallery.config),
+                                item: ThemeItem {
+                                    kind: "category",
+                                    name: &category.name,
+                                    href: html_url.clone(),
+                                    thumbnail: thumbnail_url.clone(),
+                                    alt: photo.output_name().to_owned(),
+                                    description: category.config.description.as_deref(),
+                                    first_date: dates.map(|(first, _)| first.to_string()),
+                                    last_date: dates.map(|(_, last)| last.to_string()),
+                                    count: None,
+                                    draft: false,
+                                    structured_data: Value::from_safe_string(render_fragment(structured_data.clone())),
+                                },
+                            }) {
+                                return Some(html);
+                            }
+                        }
                         Some(html!{
                             <a
                                 class="thumbnail_container category_item"
-                                href={gallery.config.category_html::<true>(&category_path, &category.slug(), 0)}
+                                href={html_url}
                             >
-                                <img
-                                    class="thumbnail"
-                                    alt={photo.output_name().to_owned()}
-                                    src={thumbnail_url.clone()}
-                                />
+                                {render_picture(gallery, &photo_path, photo, true, photo.output_name().to_owned(), None)}
                                 <div class="category_item_info">
                                     <h2 class="category_item_name">
                                         {category.name.clone()}
                                     </h2>
-                                    if let Some((first_date, last_date)) = category.first_and_last_dates() {
+                                    if let Some((first_date, last_date)) = gallery.category_dates(category) {
                                         <div class="category_item_dates">
                                             <time
                                                 datetime={first_date.to_string()}
@@ -688,16 +1053,7 @@ fn render_items(gallery: &Gallery, category_path: &CategoryPath, items: &[Item])
                                         </div>
                                     }
                                 </div>
-                                {write_structured_data(
-                                    photo_structured_data(
-                                        gallery,
-                                        photo,
-                                        gallery.config.photo_html::<true>(&photo_path, &photo.slug()),
-                                        gallery.config.photo::<true>(&photo_path, &photo.slug()),
-                                        Some(thumbnail_url),
-                                        false
-                                    )
-                                )}
+                                {structured_data}
                             </a>
                         })
                     }
@@ -708,14 +1064,189 @@ fn render_items(gallery: &Gallery, category_path: &CategoryPath, items: &[Item])
     }
 }
 
-pub fn write_image(img: &RgbImage, path: &str, xmp: Option<(&GalleryConfig, &Photo)>) -> Vec<u8> {
-    let mut ret = Cursor::new(Vec::new());
-    let format = ImageFormat::from_path(path).unwrap();
-    img.write_to(&mut ret, format).unwrap();
-    let mut buf = ret.into_inner();
-    if let Some((config, photo)) = xmp.filter(|_| !matches!(format, ImageFormat::WebP)) {
+fn render_photo_item(gallery: &Gallery, category_path: &CategoryPath, photo: &Photo) -> Html {
+    let content_url = gallery.config.photo::<true>(category_path, &photo.slug());
+    let thumbnail_url = gallery
+        .config
+        .thumbnail::<true>(category_path, &photo.slug());
+    let html_url = gallery
+        .config
+        .photo_html::<true>(category_path, &photo.slug());
+    let alt = photo
+        .config
+        .description
+        .clone()
+        .unwrap_or_else(|| photo.output_name().to_owned());
+    let structured_data = write_structured_data(photo_structured_data(
+        gallery,
+        photo,
+        html_url.clone(),
+        content_url,
+        Some(thumbnail_url.clone()),
+        false,
+    ));
+    let draft = !photo.published(&gallery.config);
+    if gallery.theme.overrides("item.html") {
+        let date = photo.date_time().map(|d| d.date().to_string());
+        if let Some(html) = gallery.theme.render(
+            "item.html",
+            ItemThemeContext {
+                gallery: ThemeGallery::new(&gallery.config),
+                item: ThemeItem {
+                    kind: "photo",
+                    name: photo.output_name(),
+                    href: html_url.clone(),
+                    thumbnail: thumbnail_url.clone(),
+                    alt: alt.clone(),
+                    description: photo.config.description.as_deref(),
+                    first_date: date.clone(),
+                    last_date: date,
+                    count: None,
+                    draft,
+                    structured_data: Value::from_safe_string(render_fragment(
+                        structured_data.clone(),
+                    )),
+                },
+            },
+        ) {
+            return html;
+        }
+    }
+    html! {
+        <a
+            class="thumbnail_container"
+            href={html_url}
+        >
+            {render_picture(
+                gallery,
+                category_path,
+                photo,
+                true,
+                alt,
+                Some(photo.output_name().to_owned()),
+            )}
+            if draft {
+                <span class="draft_badge">{"draft"}</span>
+            }
+            {structured_data}
+        </a>
+    }
+}
+
+/// Every resolution and format of a photo's thumbnail or preview.
+pub(crate) fn render_picture(
+    gallery: &Gallery,
+    category_path: &CategoryPath,
+    photo: &Photo,
+    thumbnail: bool,
+    alt: String,
+    title: Option<String>,
+) -> Html {
+    let config = &gallery.config;
+    let slug = photo.slug();
+    let (variants, (width, height), sizes) = if thumbnail {
+        let resolution = config.thumbnail_resolution;
+        (
+            config.thumbnail_variants(),
+            (resolution, resolution),
+            format!("{resolution}px"),
+        )
+    } else {
+        let (width, height) = photo.preview_dimensions(config);
+        (
+            config.preview_variants(),
+            (width, height),
+            format!("(max-width: {width}px) 100vw, {width}px"),
+        )
+    };
+    // Only compute these once, as they may require decoding the photo.
+    let image_dimensions = (!thumbnail).then(|| photo.image_dimensions(config));
+    let srcset = |format: OutputFormat| {
+        let mut widths = Vec::new();
+        variants
+            .iter()
+            .filter(|(_, f)| *f == format)
+            .filter_map(|&(resolution, format)| {
+                let (url, width) = if let Some(image_dimensions) = image_dimensions {
+                    (
+                        config.preview_variant::<true>(category_path, &slug, resolution, format),
+                        resized_dimensions(image_dimensions, resolution).0,
+                    )
+                } else {
+                    (
+                        config.thumbnail_variant::<true>(category_path, &slug, resolution, format),
+                        resolution,
+                    )
+                };
+                // Resolutions beyond that of the photo are redundant.
+                (!widths.contains(&width)).then(|| {
+                    widths.push(width);
+                    format!("{url} {width}w")
+                })
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let (_, format) = variants[0];
+    let mut alternate_formats = Vec::new();
+    for &(_, alternate_format) in &variants {
+        if alternate_format != format && !alternate_formats.contains(&alternate_format) {
+            alternate_formats.push(alternate_format);
+        }
+    }
+    let src = if thumbnail {
+        config.thumbnail::<true>(category_path, &slug)
+    } else {
+        config.preview::<true>(category_path, &slug)
+    };
+
+    html! {
+        <picture>
+            {alternate_formats.into_iter().map(|format| html!{
+                <source
+                    type={format.mime_type()}
+                    srcset={srcset(format)}
+                    sizes={sizes.clone()}
+                />
+            }).collect::<Html>()}
+            <img
+                class={if thumbnail { "thumbnail" } else { "preview" }}
+                width={width.to_string()}
+                height={height.to_string()}
+                {alt}
+                {title}
+                {src}
+                srcset={srcset(format)}
+                sizes={sizes.clone()}
+                // The preview is the main content of its page.
+                loading={thumbnail.then_some("lazy")}
+            />
+        </picture>
+    }
+}
+
+/// Photos from any categories.
+pub(crate) fn render_photo_items(gallery: &Gallery, photos: &[(CategoryPath, &Photo)]) -> Html {
+    html! {
+        <section id="page_main_body_items" data-nosnippet="nosnippet">
+            {photos.iter().map(|(path, photo)| render_photo_item(gallery, path, photo)).collect::<Html>()}
+        </section>
+    }
+}
+
+pub fn write_image(
+    img: &RgbImage,
+    path: &str,
+    options: &EncodeOptions,
+    xmp: Option<(&GalleryConfig, &Photo)>,
+) -> Vec<u8> {
+    let format = OutputFormat::from_path(path).unwrap();
+    let mut buf = format.encode(img, options);
+    if let Some((config, photo)) =
+        xmp.filter(|_| !matches!(format, OutputFormat::WebP | OutputFormat::Avif))
+    {
         // Awaiting https://github.com/adobe/xmp-toolkit-rs/issues/265
-        let ext = format.extensions_str()[0];
+        let ext = format.extension();
         let file = tempfile::Builder::new()
             .suffix(&format!(".xmp.{}", ext))
             .tempfile()
@@ -731,7 +1262,7 @@ pub fn write_image(img: &RgbImage, path: &str, xmp: Option<(&GalleryConfig, &Pho
                     .only_xmp()
                     .use_smart_handler(),
             )
-            .expect(ext);
+            .expect(&ext);
         let mut xmp = XmpMeta::new().unwrap();
 
         static ONCE: std::sync::Once = std::sync::Once::new();
@@ -808,6 +1339,15 @@ pub fn write_image(img: &RgbImage, path: &str, xmp: Option<(&GalleryConfig, &Pho
             .unwrap();
         }
 
+        for tag in &photo.tags {
+            xmp.append_array_item(
+                xmp_ns::DC,
+                &XmpValue::new("subject".to_owned()).set_is_array(true),
+                &XmpValue::new(tag.clone()),
+            )
+            .unwrap();
+        }
+
         if let Some(description) = photo.config.description.clone() {
             xmp.set_property(
                 xmp_ns::IPTC_CORE,
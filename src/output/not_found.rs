@@ -0,0 +1,40 @@
+use crate::{
+    gallery::{CategoryPath, Gallery, Page},
+    output::rich_text_html,
+};
+use yew::{html, Html};
+
+/// Caption, or a default message, followed by links to the
+/// top of the gallery.
+pub fn render_not_found(gallery: &Gallery, pages: &[(String, &Page)]) -> Html {
+    let config = &gallery.config;
+    let link = |href: String, name: String| {
+        html! {
+            <li><a {href}>{name}</a></li>
+        }
+    };
+
+    html! {<>
+        if let Some(text) = &gallery.not_found_text {
+            {rich_text_html(text)}
+        } else {
+            <p>{"The page you're looking for doesn't exist. It may have been moved or removed."}</p>
+        }
+        <nav id="not_found_links" data-nosnippet="nosnippet">
+            <ul>
+                {link(config.index_html::<true>(0), "Home".to_owned())}
+                {gallery.children.iter().filter_map(|item| item.category()).map(|category| {
+                    link(
+                        config.category_html::<true>(&CategoryPath::ROOT, &category.slug(), 0),
+                        category.name.clone(),
+                    )
+                }).collect::<Html>()}
+                {pages.iter().map(|(href, page)| link(href.clone(), page.name.clone())).collect::<Html>()}
+                if gallery.thumbnail().is_some() {
+                    {link(config.archive_html::<true>(None, None, 0), "Archive".to_owned())}
+                }
+                {link(config.search_html::<true>(), "Search".to_owned())}
+            </ul>
+        </nav>
+    </>}
+}
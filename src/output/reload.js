@@ -0,0 +1,13 @@
+function pollForReload() {
+    fetch(`/_chillphoto/reload?generation=${GENERATION}`, { cache: "no-store" })
+        .then((response) => {
+            if (response.ok) {
+                location.reload();
+            } else {
+                setTimeout(pollForReload, 1000);
+            }
+        })
+        .catch(() => setTimeout(pollForReload, 1000));
+}
+
+pollForReload();
@@ -45,6 +45,11 @@ for (const item of items) {
             eat(category);
         }
     }
+    if (Array.isArray(item.tags)) {
+        for (const tag of item.tags) {
+            eat(tag);
+        }
+    }
     eat(item.description);
     eat(item.location);
     eat(item.pageTextContent);
@@ -22,6 +22,7 @@ pub fn render_search(gallery: &Gallery) -> Html {
             name: photo.output_name().to_string(),
             page_text_content: photo.text.as_ref().map(|t| t.content.clone()),
             location: photo.config.location.clone(),
+            tags: photo.tags.clone(),
             description: photo.config.description.clone(),
             path: gallery.config.photo_html::<true>(&path, &photo.slug()),
             thumbnail_path: gallery.config.thumbnail::<true>(&path, &photo.slug()),
@@ -71,6 +72,8 @@ struct SearchItem {
     page_text_content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     location: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tags: Vec<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
     path: String,
@@ -2,33 +2,231 @@ use http::{HeaderValue, Method, Uri, Version};
 use httparse::Status;
 use serde::Deserialize;
 use std::io::{self, ErrorKind};
-use std::net::TcpStream;
+use std::net::{IpAddr, TcpListener};
+use std::ops::Range;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::PathBuf;
 use std::str::FromStr;
-use std::time::Instant;
+use std::time::{Instant, SystemTime};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     io::{Read, Write},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
     },
     thread::available_parallelism,
     time::Duration,
 };
 
-use crate::gallery::{CategoryPath, Gallery, PhotoConfig, RichTextFormat};
-use crate::output::DynLazy;
+use crate::diagnostics::Diagnostics;
+use crate::gallery::{CategoryPath, Gallery, GalleryConfig, PhotoConfig, RichTextFormat};
+use crate::output::{
+    content_type, is_compressible, lan_address, tls_config, Cache, Connection, Encoding, Output,
+    OutputFormat, SourceWatcher,
+};
+use crate::util::checksum;
+
+/// Encoded images and rendered pages, by output path, along with their
+/// hash and when they were generated. Survives reloads so unchanged ones
+/// needn't be generated again.
+type ImageCache = Mutex<HashMap<String, (String, Arc<Vec<u8>>, SystemTime)>>;
+
+pub struct ServeOptions {
+    /// See `--background`.
+    pub background: bool,
+    pub host: String,
+    pub port: u16,
+    pub https: bool,
+}
 
+/// Serves the gallery, calling `reload` with the absolute paths of
+/// source files that changed to replace it.
+///
+/// Reports problems starting the server to `diagnostics`.
 pub fn serve(
+    start: Instant,
+    options: ServeOptions,
+    mut gallery: Gallery,
+    diagnostics: &Diagnostics,
+    reload: impl Fn(&Gallery, &HashSet<PathBuf>) -> Option<Gallery>,
+) {
+    let ServeOptions {
+        background,
+        host,
+        port,
+        https,
+    } = options;
+    let all_interfaces = host.parse::<IpAddr>().is_ok_and(|ip| ip.is_unspecified());
+    let lan_address = all_interfaces.then(lan_address).flatten();
+
+    let tls = if https {
+        let mut hosts = vec!["localhost".to_owned(), "127.0.0.1".to_owned()];
+        if !all_interfaces && !hosts.contains(&host) {
+            hosts.push(host.clone());
+        }
+        hosts.extend(lan_address.map(|ip| ip.to_string()));
+        match tls_config(&gallery.config, &hosts) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                diagnostics.error(GalleryConfig::PATH, format!("couldn't set up HTTPS: {e}"));
+                return;
+            }
+        }
+    } else {
+        None
+    };
+
+    let listener = match TcpListener::bind((host.as_str(), port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            diagnostics.error(
+                GalleryConfig::PATH,
+                format!("couldn't listen on {host}:{port}: {e}"),
+            );
+            return;
+        }
+    };
+    listener.set_nonblocking(true).unwrap();
+
+    let scheme = if https { "https" } else { "http" };
+    let url = |host: &dyn std::fmt::Display| match host.to_string().parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("{scheme}://[{ip}]:{port}"),
+        _ => format!("{scheme}://{host}:{port}"),
+    };
+    if all_interfaces {
+        println!(
+            "({:.1}s) Serving on {} and the local network{}",
+            start.elapsed().as_secs_f32(),
+            url(&"localhost"),
+            lan_address.map_or(String::new(), |ip| format!(" at {}", url(&ip))),
+        );
+    } else {
+        println!(
+            "({:.1}s) Serving on {}",
+            start.elapsed().as_secs_f32(),
+            url(&host)
+        );
+    }
+
+    let Some(mut watcher) = SourceWatcher::new(&gallery.config) else {
+        diagnostics.error(GalleryConfig::PATH, "invalid input glob");
+        return;
+    };
+    let image_cache = ImageCache::default();
+    let mut generation = 0usize;
+    loop {
+        let changed = {
+            let output_diagnostics = Diagnostics::default();
+            let output = gallery.output(&output_diagnostics);
+            image_cache
+                .lock()
+                .unwrap()
+                .retain(|path, _| output.contains_key(path));
+            serve_generation(
+                start,
+                background,
+                generation,
+                &gallery,
+                &output,
+                &output_diagnostics,
+                &image_cache,
+                &listener,
+                tls.as_ref(),
+                &mut watcher,
+            )
+        };
+        Cache::new(&gallery.config).prune(None);
+
+        println!(
+            "({:.1}s) Source files changed, reloading",
+            start.elapsed().as_secs_f32()
+        );
+        if let Some(new_gallery) = reload(&gallery, &changed) {
+            if new_gallery.config.input != gallery.config.input
+                || new_gallery.config.output != gallery.config.output
+                || new_gallery.config.cache != gallery.config.cache
+            {
+                if let Some(new_watcher) = SourceWatcher::new(&new_gallery.config) {
+                    watcher = new_watcher;
+                }
+            }
+            gallery = new_gallery;
+            generation += 1;
+        }
+    }
+}
+
+/// Returns the source files once they change. Problems generating `output`,
+/// which are reported to `diagnostics`, are reported after each request.
+#[allow(clippy::too_many_arguments)]
+fn serve_generation(
     start: Instant,
     background: bool,
+    generation: usize,
     gallery: &Gallery,
-    output: &HashMap<String, (DynLazy<'_, Vec<u8>>, Option<DynLazy<'_, String>>)>,
-) {
+    output: &Output<'_>,
+    diagnostics: &Diagnostics,
+    image_cache: &ImageCache,
+    listener: &TcpListener,
+    tls: Option<&Arc<rustls::ServerConfig>>,
+    watcher: &mut SourceWatcher,
+) -> HashSet<PathBuf> {
+    let changed = &AtomicBool::new(false);
+    let disk_cache = Cache::new(&gallery.config);
+    // Unhashed outputs may have changed whenever the gallery is reloaded.
+    let generated = SystemTime::now();
+    let contents = |path: &str| -> Option<(Arc<Vec<u8>>, SystemTime)> {
+        let (file, hasher) = output.get(path)?;
+        let Some(hasher) = hasher else {
+            return Some((Arc::new((**file).clone()?), generated));
+        };
+        if let Some((hash, contents, modified)) = image_cache.lock().unwrap().get(path) {
+            if *hash == **hasher {
+                return Some((Arc::clone(contents), *modified));
+            }
+        }
+        // Only images are worth caching on disk.
+        let image = OutputFormat::from_path(path).is_some();
+        let cached = if image { disk_cache.get(hasher) } else { None };
+        let contents = Arc::new(match cached {
+            Some(contents) => contents,
+            None => {
+                let contents = (**file).clone()?;
+                if image {
+                    disk_cache.insert(hasher, &contents);
+                }
+                contents
+            }
+        });
+        let modified = SystemTime::now();
+        image_cache.lock().unwrap().insert(
+            path.to_owned(),
+            (String::clone(hasher), Arc::clone(&contents), modified),
+        );
+        Some((contents, modified))
+    };
+    let contents = &contents;
+    // Of text files, with the reload script, so only for this generation.
+    let compressed_cache = Mutex::new(HashMap::<(String, Encoding), Arc<Vec<u8>>>::new());
+    let compressed = |path: &str, encoding: Encoding, body: &[u8]| -> Arc<Vec<u8>> {
+        let key = (path.to_owned(), encoding);
+        if let Some(compressed) = compressed_cache.lock().unwrap().get(&key) {
+            return Arc::clone(compressed);
+        }
+        let compressed = Arc::new(encoding.encode(body));
+        compressed_cache
+            .lock()
+            .unwrap()
+            .insert(key, Arc::clone(&compressed));
+        compressed
+    };
+    let compressed = &compressed;
     let background_threads = &AtomicUsize::new(0);
     let http_threads = &AtomicUsize::new(0);
     let mut queue = output.iter().collect::<Vec<_>>();
-    queue.sort_by_key(|(path, _)| !path.contains("_thumbnail"));
+    // Keep each photo's variants together, so it's only decoded once.
+    queue.sort_by_key(|(path, _)| *path);
     let work = &Mutex::new(queue.iter());
     let available_parallelism = available_parallelism()
         .map(|n| n.get())
@@ -45,12 +243,16 @@ pub fn serve(
             let _guard = Guard::new(background_threads);
             scope.spawn(move || {
                 let _guard = _guard;
-                while let Some((_name, (i, _))) = {
+                while let Some((name, _)) = {
                     let next = work.lock().unwrap().next();
                     next
                 } {
-                    LazyLock::force(i);
-                    //println!("[background] {_name}");
+                    if changed.load(Ordering::SeqCst) {
+                        return;
+                    }
+                    // Reported if requested.
+                    let _ = catch_unwind(AssertUnwindSafe(|| contents(name)));
+                    //println!("[background] {name}");
                     while http_threads.load(Ordering::SeqCst) > thread {
                         std::thread::sleep(Duration::from_millis(1000));
                     }
@@ -67,92 +269,298 @@ pub fn serve(
             });
         }
 
-        let addr = "0.0.0.0:8080";
-        let listener = std::net::TcpListener::bind(addr).unwrap();
-
-        println!(
-            "({:.1}s) Serving on http://{addr}",
-            start.elapsed().as_secs_f32()
-        );
+        // Watch for changes.
+        let watch = scope.spawn(move || {
+            let sources = watcher.wait();
+            changed.store(true, Ordering::SeqCst);
+            sources
+        });
 
         loop {
-            let mut stream = if let Ok((stream, _)) = listener.accept() {
-                stream
-            } else {
+            let stream = match listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                    if changed.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    std::thread::sleep(Duration::from_millis(10));
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            // So connections can be closed when reloading.
+            if stream.set_nonblocking(false).is_err()
+                || stream
+                    .set_read_timeout(Some(Duration::from_millis(250)))
+                    .is_err()
+            {
+                continue;
+            }
+            let Ok(mut stream) = Connection::new(stream, tls) else {
                 continue;
             };
 
             let mut buf = Vec::new();
 
             scope.spawn(move || loop {
-                let request = if let Ok(request) = read_request(&mut stream, &mut buf) {
-                    request
-                } else {
-                    return;
+                let request = match read_request(&mut stream, &mut buf) {
+                    Ok(request) => request,
+                    Err(e)
+                        if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+                            && !changed.load(Ordering::SeqCst) =>
+                    {
+                        continue;
+                    }
+                    Err(_) => return,
                 };
 
-                let response = if request.method() == Method::GET {
-                    let mut path = request.uri().path().to_owned();
-                    if path.ends_with('/') {
-                        path.push_str("index.html")
-                    }
-                    if let Some(query) = request.uri().query() {
-                        if query.contains("page=") {
-                            use std::fmt::Write;
-                            write!(path, "?{query}").unwrap();
+                // A panic (like a photo that can't be decoded) would otherwise
+                // take down the server when the scope ends, on reload.
+                let response = catch_unwind(AssertUnwindSafe(|| {
+                    if request.method() == Method::GET
+                        && request.uri().path() == "/_chillphoto/reload"
+                    {
+                        // Long-poll until the page needs reloading.
+                        let current = format!("generation={generation}");
+                        if request.uri().query() == Some(current.as_str()) {
+                            while !changed.load(Ordering::SeqCst) {
+                                std::thread::sleep(Duration::from_millis(100));
+                            }
                         }
-                    }
-
-                    if let Some((file, hasher)) = output.get(&path) {
-                        while http_threads.load(Ordering::SeqCst) >= available_parallelism {
-                            std::thread::sleep(Duration::from_secs(50));
+                        http::Response::builder()
+                            .version(request.version())
+                            .status(http::StatusCode::OK)
+                            .header("Cache-Control", "no-store")
+                            .body(b"reload".to_vec())
+                            .unwrap()
+                    } else if request.method() == Method::GET || request.method() == Method::HEAD {
+                        let mut path = request.uri().path().to_owned();
+                        if path.ends_with('/') {
+                            path.push_str("index.html")
                         }
+                        if let Some(query) = request.uri().query() {
+                            if query.contains("page=") {
+                                use std::fmt::Write;
+                                write!(path, "?{query}").unwrap();
+                            }
+                        }
+                        let html = path.split('?').next().unwrap().ends_with(".html");
+                        let version = request
+                            .uri()
+                            .query()
+                            .and_then(|query| query.split('&').find_map(|q| q.strip_prefix("v=")));
+
+                        if let Some((_, hasher)) = output.get(&path) {
+                            // Versioned URLs of hashed assets change with them.
+                            let cache_control = |hash: Option<&str>| {
+                                if !html && hash.is_some() && version == hash {
+                                    "public, max-age=31536000, immutable"
+                                } else {
+                                    // Cache, but check for changes every time.
+                                    "no-cache"
+                                }
+                            };
+
+                            // The reload script differs between generations.
+                            let generation_suffix = if html {
+                                format!("-{generation}")
+                            } else {
+                                String::new()
+                            };
 
-                        let _guard = Guard::new(http_threads);
+                            // Images needn't even be generated to tell that they're unchanged.
+                            let unchanged_image = hasher
+                                .as_ref()
+                                .filter(|_| !is_compressible(&path))
+                                .map(|hasher| format!("\"{}\"", &***hasher))
+                                .filter(|etag| etag_matches(&request, etag) == Some(true));
+                            if let Some(etag) = unchanged_image {
+                                let cache_control = cache_control(Some(etag.trim_matches('"')));
+                                not_modified_response(&request, &etag, None, cache_control)
+                            } else {
+                                while http_threads.load(Ordering::SeqCst) >= available_parallelism {
+                                    std::thread::sleep(Duration::from_millis(50));
+                                }
 
-                        let mut builder = http::Response::builder()
-                            .version(request.version())
-                            .status(http::StatusCode::OK);
+                                let _guard = Guard::new(http_threads);
 
-                        if let Some(hasher) = hasher {
-                            builder = builder.header("Etag", format!("\"{}\"", (&***hasher)));
+                                let mut builder = http::Response::builder()
+                                    .version(request.version())
+                                    .header("Content-Type", content_type(&path));
+
+                                let Some((contents, modified)) = contents(&path) else {
+                                    // Reported after the request.
+                                    return http::Response::builder()
+                                        .version(request.version())
+                                        .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                                        .body(b"couldn't generate".to_vec())
+                                        .unwrap();
+                                };
+                                let modified = if html { generated } else { modified };
+                                let hash = hasher.as_ref().map_or_else(
+                                    || checksum(&contents),
+                                    |hasher| String::clone(hasher),
+                                );
+                                let cache_control =
+                                    cache_control(hasher.is_some().then_some(hash.as_str()));
+                                builder = builder.header("Cache-Control", cache_control);
+                                let mut body = contents.to_vec();
+                                if html {
+                                    body = version_asset_urls(&body, |path| {
+                                        output
+                                            .get(path)?
+                                            .1
+                                            .as_ref()
+                                            .filter(|_| !path.ends_with(".html"))
+                                            .map(|hasher| String::clone(hasher))
+                                    });
+                                    inject_reload_script(&mut body, generation);
+                                }
+                                // Like `build`, to show real transfer sizes.
+                                let mut encoding = None;
+                                if gallery.config.precompress
+                                    && is_compressible(&path)
+                                    && body.len() as u64 >= gallery.config.precompress_min_size
+                                {
+                                    builder = builder.header("Vary", "Accept-Encoding");
+                                    encoding = request
+                                        .headers()
+                                        .get(http::header::ACCEPT_ENCODING)
+                                        .and_then(|accept_encoding| accept_encoding.to_str().ok())
+                                        .and_then(Encoding::negotiate);
+                                    if let Some(encoding) = encoding {
+                                        body = compressed(&path, encoding, &body).to_vec();
+                                        builder =
+                                            builder.header("Content-Encoding", encoding.name());
+                                    }
+                                }
+                                let encoding_suffix = encoding.map_or(String::new(), |encoding| {
+                                    format!("-{}", encoding.extension())
+                                });
+                                let etag =
+                                    format!("\"{hash}{generation_suffix}{encoding_suffix}\"");
+                                let last_modified = http_date(modified);
+
+                                if not_modified(&request, &etag, modified) {
+                                    not_modified_response(
+                                        &request,
+                                        &etag,
+                                        Some(last_modified),
+                                        cache_control,
+                                    )
+                                } else {
+                                    builder = builder
+                                        .header("Etag", &etag)
+                                        .header("Last-Modified", last_modified);
+                                    if encoding.is_none() {
+                                        builder = builder.header("Accept-Ranges", "bytes");
+                                        match byte_range(&request, &etag, body.len()) {
+                                            Ok(None) => builder
+                                                .status(http::StatusCode::OK)
+                                                .body(body)
+                                                .unwrap(),
+                                            Ok(Some(range)) => builder
+                                                .status(http::StatusCode::PARTIAL_CONTENT)
+                                                .header(
+                                                    "Content-Range",
+                                                    format!(
+                                                        "bytes {}-{}/{}",
+                                                        range.start,
+                                                        range.end - 1,
+                                                        body.len()
+                                                    ),
+                                                )
+                                                .body(body[range].to_vec())
+                                                .unwrap(),
+                                            Err(()) => builder
+                                                .status(http::StatusCode::RANGE_NOT_SATISFIABLE)
+                                                .header(
+                                                    "Content-Range",
+                                                    format!("bytes */{}", body.len()),
+                                                )
+                                                .body(Vec::new())
+                                                .unwrap(),
+                                        }
+                                    } else {
+                                        builder.status(http::StatusCode::OK).body(body).unwrap()
+                                    }
+                                }
+                            }
+                        } else if let Some((page, _)) =
+                            contents(&gallery.config.not_found_html::<false>())
+                        {
+                            let mut body = page.to_vec();
+                            inject_reload_script(&mut body, generation);
+                            http::Response::builder()
+                                .version(request.version())
+                                .status(http::StatusCode::NOT_FOUND)
+                                .header("Content-Type", "text/html; charset=utf-8")
+                                .header("Cache-Control", "no-store")
+                                .body(body)
+                                .unwrap()
+                        } else {
+                            http::Response::builder()
+                                .version(request.version())
+                                .status(http::StatusCode::NOT_FOUND)
+                                .body(b"not found".to_vec())
+                                .unwrap()
                         }
-                        if path.ends_with(".svg") {
-                            // Help Chrome
-                            builder = builder.header("Content-Type", "image/svg+xml");
+                    } else if request.method() == Method::PUT && request.uri().path() == "/" {
+                        use std::process::Command;
+
+                        #[derive(Deserialize)]
+                        enum Put {
+                            EditConfig {
+                                path: CategoryPath,
+                            },
+                            EditCaption {
+                                path: CategoryPath,
+                                format: Option<RichTextFormat>,
+                            },
                         }
-                        builder.body((&***file).to_vec()).unwrap()
-                    } else {
-                        http::Response::builder()
-                            .version(request.version())
-                            .status(http::StatusCode::NOT_FOUND)
-                            .body(b"not found".to_vec())
-                            .unwrap()
-                    }
-                } else if request.method() == Method::PUT && request.uri().path() == "/" {
-                    use std::process::Command;
-
-                    #[derive(Deserialize)]
-                    enum Put {
-                        EditConfig {
-                            path: CategoryPath,
-                        },
-                        EditCaption {
-                            path: CategoryPath,
-                            format: Option<RichTextFormat>,
-                        },
-                    }
 
-                    match serde_json::from_slice::<Put>(request.body()) {
-                        Err(e) => http::Response::builder()
-                            .version(request.version())
-                            .status(http::StatusCode::BAD_REQUEST)
-                            .body(e.to_string().into_bytes())
-                            .unwrap(),
-                        Ok(Put::EditConfig { path }) => {
-                            if let Some(path) = PhotoConfig::path(gallery, &path) {
+                        match serde_json::from_slice::<Put>(request.body()) {
+                            Err(e) => http::Response::builder()
+                                .version(request.version())
+                                .status(http::StatusCode::BAD_REQUEST)
+                                .body(e.to_string().into_bytes())
+                                .unwrap(),
+                            Ok(Put::EditConfig { path }) => {
+                                if let Some(path) = PhotoConfig::path(gallery, &path) {
+                                    Command::new(gallery.config.text_editor.as_ref().unwrap())
+                                        .arg(path)
+                                        .spawn()
+                                        .unwrap();
+
+                                    http::Response::builder()
+                                        .version(request.version())
+                                        .status(http::StatusCode::OK)
+                                        .body(b"ok".to_vec())
+                                        .unwrap()
+                                } else {
+                                    http::Response::builder()
+                                        .version(request.version())
+                                        .status(http::StatusCode::NOT_FOUND)
+                                        .body(b"not found".to_vec())
+                                        .unwrap()
+                                }
+                            }
+                            Ok(Put::EditCaption { path, format }) => {
+                                let photo = gallery.photo(&path).unwrap();
+                                // Don't allow format changes.
+                                let format = photo
+                                    .text
+                                    .as_ref()
+                                    .map(|t| t.format)
+                                    .or(format)
+                                    .unwrap_or_default();
+                                let mut page_path = gallery.root.clone();
+                                for path in path.pop().unwrap().iter_paths().skip(1) {
+                                    page_path.push(&gallery.category(&path).unwrap().name);
+                                }
+                                page_path.push(format!("{}.{}", photo.name, format.extension()));
                                 Command::new(gallery.config.text_editor.as_ref().unwrap())
-                                    .arg(path)
+                                    .arg(page_path)
                                     .spawn()
                                     .unwrap();
 
@@ -161,56 +569,172 @@ pub fn serve(
                                     .status(http::StatusCode::OK)
                                     .body(b"ok".to_vec())
                                     .unwrap()
-                            } else {
-                                http::Response::builder()
-                                    .version(request.version())
-                                    .status(http::StatusCode::NOT_FOUND)
-                                    .body(b"not found".to_vec())
-                                    .unwrap()
                             }
                         }
-                        Ok(Put::EditCaption { path, format }) => {
-                            let photo = gallery.photo(&path).unwrap();
-                            // Don't allow format changes.
-                            let format = photo
-                                .text
-                                .as_ref()
-                                .map(|t| t.format)
-                                .or(format)
-                                .unwrap_or_default();
-                            let mut page_path = gallery.root.clone();
-                            for path in path.pop().unwrap().iter_paths().skip(1) {
-                                page_path.push(&gallery.category(&path).unwrap().name);
-                            }
-                            page_path.push(format!("{}.{}", photo.name, format.extension()));
-                            Command::new(gallery.config.text_editor.as_ref().unwrap())
-                                .arg(page_path)
-                                .spawn()
-                                .unwrap();
-
-                            http::Response::builder()
-                                .version(request.version())
-                                .status(http::StatusCode::OK)
-                                .body(b"ok".to_vec())
-                                .unwrap()
-                        }
+                    } else {
+                        http::Response::builder()
+                            .version(request.version())
+                            .status(http::StatusCode::METHOD_NOT_ALLOWED)
+                            .body(b"method not allowed".to_vec())
+                            .unwrap()
                     }
-                } else {
+                }))
+                .unwrap_or_else(|_| {
                     http::Response::builder()
                         .version(request.version())
-                        .status(http::StatusCode::METHOD_NOT_ALLOWED)
-                        .body(b"method not allowed".to_vec())
+                        .status(http::StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(b"internal server error".to_vec())
                         .unwrap()
-                };
+                });
 
                 println!("[{}] {}", response.status(), request.uri());
 
-                if write_response(&mut stream, response).is_err() {
+                for diagnostics in [gallery.theme.take_render_diagnostics(), diagnostics.take()] {
+                    if !diagnostics.is_empty() {
+                        diagnostics.report();
+                    }
+                }
+
+                let head = request.method() == Method::HEAD;
+                if write_response(&mut stream, response, head).is_err() {
                     return;
                 }
             });
         }
-    });
+        watch.join().unwrap()
+    })
+}
+
+/// Whether any entity tag in `If-None-Match` is `etag`, if there is one.
+fn etag_matches(request: &http::Request<Vec<u8>>, etag: &str) -> Option<bool> {
+    let if_none_match = request.headers().get(http::header::IF_NONE_MATCH)?;
+    Some(if_none_match.to_str().is_ok_and(|if_none_match| {
+        if_none_match
+            .split(',')
+            .map(str::trim)
+            .any(|tag| tag == "*" || tag.strip_prefix("W/").unwrap_or(tag) == etag)
+    }))
+}
+
+/// Whether the client's copy is up to date, preferring `If-None-Match`
+/// over `If-Modified-Since`.
+fn not_modified(request: &http::Request<Vec<u8>>, etag: &str, modified: SystemTime) -> bool {
+    if let Some(matches) = etag_matches(request, etag) {
+        return matches;
+    }
+    request
+        .headers()
+        .get(http::header::IF_MODIFIED_SINCE)
+        .and_then(|since| since.to_str().ok())
+        .and_then(|since| chrono::DateTime::parse_from_rfc2822(since).ok())
+        .is_some_and(|since| {
+            // HTTP dates are in whole seconds.
+            chrono::DateTime::<chrono::Utc>::from(modified).timestamp() <= since.timestamp()
+        })
+}
+
+fn not_modified_response(
+    request: &http::Request<Vec<u8>>,
+    etag: &str,
+    last_modified: Option<String>,
+    cache_control: &str,
+) -> http::Response<Vec<u8>> {
+    let mut builder = http::Response::builder()
+        .version(request.version())
+        .status(http::StatusCode::NOT_MODIFIED)
+        .header("Etag", etag)
+        .header("Cache-Control", cache_control);
+    if let Some(last_modified) = last_modified {
+        builder = builder.header("Last-Modified", last_modified);
+    }
+    builder.body(Vec::new()).unwrap()
+}
+
+fn http_date(time: SystemTime) -> String {
+    chrono::DateTime::<chrono::Utc>::from(time)
+        .format("%a, %d %b %Y %H:%M:%S GMT")
+        .to_string()
+}
+
+/// The single byte range requested of a body of length `len`, if any and
+/// if `If-Range` allows it, or `Err` if it's unsatisfiable. Multiple
+/// ranges aren't supported, so the whole body is sent instead.
+fn byte_range(
+    request: &http::Request<Vec<u8>>,
+    etag: &str,
+    len: usize,
+) -> Result<Option<Range<usize>>, ()> {
+    let Some(range) = request
+        .headers()
+        .get(http::header::RANGE)
+        .and_then(|range| range.to_str().ok())
+    else {
+        return Ok(None);
+    };
+    if let Some(if_range) = request.headers().get(http::header::IF_RANGE) {
+        if if_range.to_str().ok() != Some(etag) {
+            return Ok(None);
+        }
+    }
+    let Some((start, end)) = range
+        .strip_prefix("bytes=")
+        .filter(|ranges| !ranges.contains(','))
+        .and_then(|range| range.trim().split_once('-'))
+    else {
+        return Ok(None);
+    };
+    let range = match (start.parse::<usize>(), end.parse::<usize>()) {
+        (Ok(start), Ok(end)) if start <= end => start..end.saturating_add(1).min(len),
+        (Ok(start), Err(_)) if end.is_empty() => start..len,
+        (Err(_), Ok(suffix)) if start.is_empty() => len.saturating_sub(suffix)..len,
+        _ => return Ok(None),
+    };
+    if range.start < range.end {
+        Ok(Some(range))
+    } else {
+        Err(())
+    }
+}
+
+/// Appends `?v={hash}` to the URLs of hashed assets in `html`'s attributes
+/// (including each of a `srcset`), so they can be cached until they change.
+fn version_asset_urls(html: &[u8], hash: impl Fn(&str) -> Option<String>) -> Vec<u8> {
+    use std::fmt::Write;
+
+    let Ok(html) = std::str::from_utf8(html) else {
+        return html.to_vec();
+    };
+    let mut ret = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find("=\"") {
+        let (before, after) = rest.split_at(start + 2);
+        ret.push_str(before);
+        let (value, after) = after.split_at(after.find('"').unwrap_or(after.len()));
+        let separator = |c: char| c.is_whitespace() || c == ',';
+        for part in value.split_inclusive(separator) {
+            let url = part.trim_end_matches(separator);
+            ret.push_str(url);
+            if let Some(hash) = url.strip_prefix('/').and_then(|_| hash(url)) {
+                write!(ret, "?v={hash}").unwrap();
+            }
+            ret.push_str(&part[url.len()..]);
+        }
+        rest = after;
+    }
+    ret.push_str(rest);
+    ret.into_bytes()
+}
+
+fn inject_reload_script(html: &mut Vec<u8>, generation: usize) {
+    let script = format!(
+        "<script>\nconst GENERATION = {generation};\n{}</script>\n",
+        include_str!("reload.js")
+    );
+    let position = html
+        .windows(b"</body>".len())
+        .rposition(|w| w == b"</body>")
+        .unwrap_or(html.len());
+    html.splice(position..position, script.into_bytes());
 }
 
 struct Guard<'a>(&'a AtomicUsize);
@@ -228,7 +752,7 @@ impl<'a> Drop for Guard<'a> {
     }
 }
 
-fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<http::Request<Vec<u8>>> {
+fn read_request(stream: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<http::Request<Vec<u8>>> {
     loop {
         let mut headers = [httparse::EMPTY_HEADER; 128];
         let mut parse_req = httparse::Request::new(&mut headers);
@@ -311,7 +835,12 @@ fn read_request(stream: &mut TcpStream, buf: &mut Vec<u8>) -> io::Result<http::R
     }
 }
 
-fn write_response(stream: &mut TcpStream, mut response: http::Response<Vec<u8>>) -> io::Result<()> {
+/// Without the body if `head`, but with its length.
+fn write_response(
+    stream: &mut impl Write,
+    mut response: http::Response<Vec<u8>>,
+    head: bool,
+) -> io::Result<()> {
     let mut header = format!(
         "{:?} {} {}\r\n",
         response.version(),
@@ -319,11 +848,13 @@ fn write_response(stream: &mut TcpStream, mut response: http::Response<Vec<u8>>)
         response.status().canonical_reason().unwrap()
     );
 
-    let content_length = response.body().len();
-    response.headers_mut().insert(
-        "Content-Length",
-        HeaderValue::from_str(&content_length.to_string()).unwrap(),
-    );
+    if response.status() != http::StatusCode::NOT_MODIFIED {
+        let content_length = response.body().len();
+        response.headers_mut().insert(
+            "Content-Length",
+            HeaderValue::from_str(&content_length.to_string()).unwrap(),
+        );
+    }
 
     for (name, value) in response.headers() {
         use std::fmt::Write;
@@ -332,7 +863,108 @@ fn write_response(stream: &mut TcpStream, mut response: http::Response<Vec<u8>>)
 
     header.push_str("\r\n");
     stream.write_all(header.as_bytes())?;
-    stream.write_all(&response.body())?;
+    if !head {
+        stream.write_all(response.body())?;
+    }
     stream.flush()?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn request(headers: &[(&str, &str)]) -> http::Request<Vec<u8>> {
+        let mut builder = http::Request::builder();
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Vec::new()).unwrap()
+    }
+
+    fn range(range: &str) -> Result<Option<Range<usize>>, ()> {
+        byte_range(&request(&[("Range", range)]), "\"a\"", 10)
+    }
+
+    #[test]
+    fn parses_byte_ranges() {
+        assert_eq!(byte_range(&request(&[]), "\"a\"", 10), Ok(None));
+        assert_eq!(range("bytes=0-4"), Ok(Some(0..5)));
+        assert_eq!(range("bytes=5-100"), Ok(Some(5..10)));
+        assert_eq!(range("bytes=0-18446744073709551615"), Ok(Some(0..10)));
+        // Open-ended and suffix ranges.
+        assert_eq!(range("bytes=5-"), Ok(Some(5..10)));
+        assert_eq!(range("bytes=-3"), Ok(Some(7..10)));
+        assert_eq!(range("bytes=-20"), Ok(Some(0..10)));
+    }
+
+    #[test]
+    fn rejects_unsatisfiable_ranges() {
+        assert_eq!(range("bytes=10-"), Err(()));
+        assert_eq!(range("bytes=10-20"), Err(()));
+        assert_eq!(range("bytes=-0"), Err(()));
+    }
+
+    #[test]
+    fn ignores_unsupported_ranges() {
+        // Multiple ranges get the whole body.
+        assert_eq!(range("bytes=0-1,3-4"), Ok(None));
+        assert_eq!(range("bytes=4-2"), Ok(None));
+        assert_eq!(range("lines=0-4"), Ok(None));
+        // As do ranges of a different version.
+        let request = request(&[("Range", "bytes=0-4"), ("If-Range", "\"b\"")]);
+        assert_eq!(byte_range(&request, "\"a\"", 10), Ok(None));
+    }
+
+    #[test]
+    fn matches_etags() {
+        let matches = |if_none_match: &str| {
+            etag_matches(&request(&[("If-None-Match", if_none_match)]), "\"a\"")
+        };
+        assert_eq!(etag_matches(&request(&[]), "\"a\""), None);
+        assert_eq!(matches("\"a\""), Some(true));
+        assert_eq!(matches("W/\"a\""), Some(true));
+        assert_eq!(matches("\"b\", W/\"a\""), Some(true));
+        assert_eq!(matches("*"), Some(true));
+        assert_eq!(matches("\"b\""), Some(false));
+        assert_eq!(matches("a"), Some(false));
+    }
+
+    #[test]
+    fn prefers_etags_to_dates() {
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let date = http_date(modified);
+        let earlier = http_date(modified - Duration::from_secs(1));
+        assert!(not_modified(
+            &request(&[("If-Modified-Since", &date)]),
+            "\"a\"",
+            modified
+        ));
+        assert!(!not_modified(
+            &request(&[("If-Modified-Since", &earlier)]),
+            "\"a\"",
+            modified
+        ));
+        assert!(!not_modified(
+            &request(&[("If-None-Match", "\"b\""), ("If-Modified-Since", &date)]),
+            "\"a\"",
+            modified
+        ));
+        assert!(!not_modified(&request(&[]), "\"a\"", modified));
+    }
+
+    #[test]
+    fn versions_asset_urls() {
+        let html = r#"<img src="/a.jpg" srcset="/a.jpg 1x, /b.jpg 2x"><a href="/b/">"#;
+        let versioned = version_asset_urls(html.as_bytes(), |path| match path {
+            "/a.jpg" => Some("1".to_owned()),
+            "/b.jpg" => Some("2".to_owned()),
+            _ => None,
+        });
+        assert_eq!(
+            String::from_utf8(versioned).unwrap(),
+            r#"<img src="/a.jpg?v=1" srcset="/a.jpg?v=1 1x, /b.jpg?v=2 2x"><a href="/b/">"#
+        );
+    }
+}
@@ -106,6 +106,8 @@ pub struct PhotoStructuredData {
     name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     description: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    keywords: Vec<String>,
     #[serde(rename = "dateCreated", skip_serializing_if = "Option::is_none")]
     date_created: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -193,6 +195,7 @@ pub fn photo_structured_data(
         content_url,
         name: photo.output_name().to_owned(),
         description: photo.config.description.clone(),
+        keywords: photo.tags.clone(),
         date_created: photo.date_time().map(|d| d.date().to_string()),
         creator: author_person.clone(),
         copyright_holder: author_person.clone(),
@@ -0,0 +1,231 @@
+use crate::{
+    gallery::{tag_slug, CategoryPath, Gallery, Page, Photo},
+    output::{
+        paginate, render_html, render_photo_items, render_picture, AppProps, DynLazy,
+        ItemThemeContext, RelativeNavigation, ThemeGallery, ThemeItem,
+    },
+};
+use minijinja::Value;
+use std::{collections::BTreeMap, fmt::Write, sync::LazyLock};
+use yew::{html, Html};
+
+pub struct Tag<'a> {
+    /// As written in the first config that uses it.
+    pub name: &'a str,
+    /// Newest first.
+    pub photos: Vec<(CategoryPath, &'a Photo)>,
+}
+
+/// Tags by slug.
+pub type Tags<'a> = BTreeMap<String, Tag<'a>>;
+
+impl Gallery {
+    pub fn tags(&self) -> Tags<'_> {
+        let mut ret = Tags::new();
+        self.visit_items(|path, item| {
+            let Some(photo) = item.photo() else {
+                return;
+            };
+            for tag in &photo.tags {
+                ret.entry(tag_slug(tag))
+                    .or_insert_with(|| Tag {
+                        name: tag,
+                        photos: Vec::new(),
+                    })
+                    .photos
+                    .push((path.clone(), photo));
+            }
+        });
+        for tag in ret.values_mut() {
+            tag.photos.sort_by(|(_, a), (_, b)| {
+                b.date_time()
+                    .cmp(&a.date_time())
+                    .then_with(|| a.name.cmp(&b.name))
+            });
+        }
+        ret
+    }
+
+    /// Tag pages, by output path.
+    pub fn tags_output<'a>(
+        &'a self,
+        pages: &[(String, &'a Page)],
+        og_image: Option<(String, (u32, u32))>,
+    ) -> Vec<(String, DynLazy<'a, Vec<u8>>)> {
+        let config = &self.config;
+        let tags = self.tags();
+        let mut ret = Vec::<(String, DynLazy<'a, Vec<u8>>)>::new();
+        if tags.is_empty() {
+            return ret;
+        }
+
+        {
+            let tags = tags
+                .iter()
+                .map(|(slug, tag)| {
+                    (
+                        slug.clone(),
+                        tag.name,
+                        tag.photos[0].clone(),
+                        tag.photos.len(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            ret.push(self.tag_page(
+                pages,
+                og_image.clone(),
+                None,
+                0,
+                Box::new(move || {
+                    html! {
+                        <section id="page_main_body_items" data-nosnippet="nosnippet">
+                            {tags.iter().map(|(slug, name, (path, photo), count)| {
+                                render_tag_item(self, slug, name, path, photo, *count)
+                            }).collect::<Html>()}
+                        </section>
+                    }
+                }),
+                None,
+            ));
+        }
+
+        for (slug, tag) in &tags {
+            for chunk in paginate(&tag.photos, config.items_per_page) {
+                let photos = chunk.items.to_vec();
+                let page_html = |page: usize| config.tags_html::<true>(Some(slug), page);
+                ret.push(self.tag_page(
+                    pages,
+                    og_image.clone(),
+                    Some((slug.clone(), tag.name)),
+                    chunk.index,
+                    Box::new(move || render_photo_items(self, &photos)),
+                    (chunk.count != 1).then(|| RelativeNavigation {
+                        index: chunk.index,
+                        count: chunk.count,
+                        previous: chunk.index.checked_sub(1).map(page_html),
+                        next: (chunk.index + 1 < chunk.count).then(|| page_html(chunk.index + 1)),
+                    }),
+                ));
+            }
+        }
+
+        ret
+    }
+
+    fn tag_page<'a>(
+        &'a self,
+        pages: &[(String, &'a Page)],
+        og_image: Option<(String, (u32, u32))>,
+        tag: Option<(String, &str)>,
+        page: usize,
+        body: Box<dyn FnOnce() -> Html + Send + Sync + 'a>,
+        relative: Option<RelativeNavigation>,
+    ) -> (String, DynLazy<'a, Vec<u8>>) {
+        let config = &self.config;
+        let pages = pages.to_vec();
+        let slug = tag.as_ref().map(|(slug, _)| slug.clone());
+        let mut title = match &tag {
+            Some((_, name)) => format!("{name} | Tags"),
+            None => "Tags".to_owned(),
+        };
+        if page != 0 {
+            write!(title, " (page {})", page + 1).unwrap();
+        }
+        let description = match &tag {
+            Some((_, name)) => format!("Photos tagged {name:?}"),
+            None => format!("Photos from {}, by tag", config.title),
+        };
+        (
+            config.tags_html::<false>(slug.as_deref(), page),
+            LazyLock::new(Box::new(move || {
+                render_html(AppProps {
+                    canonical: config.tags_html::<true>(slug.as_deref(), page),
+                    gallery: self,
+                    title: title.into(),
+                    description: Some(description.into()),
+                    head: Default::default(),
+                    body: body(),
+                    sidebar: Html::default(),
+                    pages,
+                    path: tags_path(slug.as_deref()),
+                    relative,
+                    og_image,
+                    index: true,
+                })
+            })),
+        )
+    }
+}
+
+/// Breadcrumb path of a tag page.
+fn tags_path(tag: Option<&str>) -> CategoryPath {
+    let ret = CategoryPath::ROOT.push("tags".to_owned());
+    match tag {
+        Some(tag) => ret.push(tag.to_owned()),
+        None => ret,
+    }
+}
+
+/// Parses the tag slug from the path of a tag page.
+pub fn parse_tags_path(path: &CategoryPath) -> Option<Option<&str>> {
+    let mut segments = path.iter_segments();
+    if segments.next() != Some("tags") {
+        return None;
+    }
+    Some(segments.next())
+}
+
+/// Links to a tag, using its newest photo as the thumbnail.
+fn render_tag_item(
+    gallery: &Gallery,
+    slug: &str,
+    name: &str,
+    photo_path: &CategoryPath,
+    photo: &Photo,
+    count: usize,
+) -> Html {
+    let href = gallery.config.tags_html::<true>(Some(slug), 0);
+    let thumbnail_url = gallery.config.thumbnail::<true>(photo_path, &photo.slug());
+    let count_text = format!("{count} photo{}", if count == 1 { "" } else { "s" });
+
+    if gallery.theme.overrides("item.html") {
+        if let Some(html) = gallery.theme.render(
+            "item.html",
+            ItemThemeContext {
+                gallery: ThemeGallery::new(&gallery.config),
+                item: ThemeItem {
+                    kind: "tag",
+                    name,
+                    href: href.clone(),
+                    thumbnail: thumbnail_url.clone(),
+                    alt: photo.output_name().to_owned(),
+                    description: None,
+                    first_date: None,
+                    last_date: None,
+                    count: Some(count),
+                    draft: false,
+                    structured_data: Value::from_safe_string(String::new()),
+                },
+            },
+        ) {
+            return html;
+        }
+    }
+
+    html! {
+        <a
+            class="thumbnail_container category_item"
+            {href}
+        >
+            {render_picture(gallery, photo_path, photo, true, photo.output_name().to_owned(), None)}
+            <div class="category_item_info">
+                <h2 class="category_item_name">
+                    {name.to_owned()}
+                </h2>
+                <div class="category_item_dates">
+                    {count_text}
+                </div>
+            </div>
+        </a>
+    }
+}
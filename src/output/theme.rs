@@ -0,0 +1,229 @@
+use crate::{
+    diagnostics::Diagnostics,
+    gallery::{BuiltInTheme, GalleryConfig},
+    output::RelativeNavigation,
+    util::checksum,
+};
+use minijinja::{value::Serde, Environment, Value};
+use serde::Serialize;
+use std::{
+    collections::HashSet,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+use yew::Html;
+
+/// The directory of a gallery's theme, relative to its root. It's reserved
+/// for the theme, so it can't be a category.
+pub const THEME_DIR: &str = "theme";
+
+/// Templates a theme may provide in place of the built-in markup.
+pub const THEME_TEMPLATES: [&str; 4] = ["header.html", "sidebar.html", "footer.html", "item.html"];
+
+/// Stylesheet and template overrides, from a built-in theme and/or
+/// the gallery's `theme` directory.
+#[derive(Debug)]
+pub struct Theme {
+    style: String,
+    templates: Environment<'static>,
+    dir: PathBuf,
+    /// Of rendering, which happens after loading.
+    render_diagnostics: Diagnostics,
+    render_failures: Mutex<HashSet<String>>,
+}
+
+impl Theme {
+    /// Changes whenever the stylesheet or templates do.
+    pub fn fingerprint(&self) -> String {
+        let mut templates = self
+            .templates
+            .templates()
+            .map(|(name, template)| (name, template.source().to_owned()))
+            .collect::<Vec<_>>();
+        templates.sort_unstable();
+        checksum(format!("{:?}{templates:?}", self.style).as_bytes())
+    }
+
+    /// Reports problems to `diagnostics`.
+    pub fn load(dir: &Path, built_in: BuiltInTheme, diagnostics: &Diagnostics) -> Self {
+        let mut ret = Self {
+            style: match built_in {
+                BuiltInTheme::ZenPage => include_str!("zenpage.css").to_owned(),
+                BuiltInTheme::Dark => {
+                    [include_str!("zenpage.css"), include_str!("dark.css")].join("\n")
+                }
+            },
+            templates: Environment::new(),
+            dir: dir.to_owned(),
+            render_diagnostics: Diagnostics::default(),
+            render_failures: Mutex::default(),
+        };
+
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return ret,
+            Err(e) => {
+                diagnostics.error(dir, format!("couldn't read theme: {e}"));
+                return ret;
+            }
+        };
+
+        for entry in entries {
+            let path = match entry {
+                Ok(entry) => entry.path(),
+                Err(e) => {
+                    diagnostics.error(dir, format!("couldn't read directory entry: {e}"));
+                    continue;
+                }
+            };
+            let name = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .unwrap_or_default()
+                .to_owned();
+            if path.is_dir() {
+                diagnostics.warning(&path, "ignoring directory in theme");
+                continue;
+            }
+            if name != "style.css" && !THEME_TEMPLATES.contains(&name.as_str()) {
+                diagnostics.warning(
+                    &path,
+                    format!(
+                        "ignoring unknown theme file (expected style.css, {})",
+                        THEME_TEMPLATES.join(", ")
+                    ),
+                );
+                continue;
+            }
+            let text = match fs::read_to_string(&path) {
+                Ok(text) => text,
+                Err(e) => {
+                    diagnostics.error(&path, format!("couldn't read theme file: {e}"));
+                    continue;
+                }
+            };
+            if name == "style.css" {
+                ret.style = text;
+            } else if let Err(e) = ret.templates.add_template_owned(name, text.clone()) {
+                diagnostics.error_at(
+                    &path,
+                    &text,
+                    e.range(),
+                    e.detail()
+                        .map_or_else(|| e.kind().to_string(), str::to_owned),
+                );
+            }
+        }
+
+        ret
+    }
+
+    pub fn style(&self) -> &str {
+        &self.style
+    }
+
+    pub fn overrides(&self, template: &str) -> bool {
+        self.templates.get_template(template).is_ok()
+    }
+
+    /// `None` if the theme doesn't override `template` (or failed to render it).
+    pub fn render(&self, template: &str, context: impl Serialize) -> Option<Html> {
+        let template = self.templates.get_template(template).ok()?;
+        match template.render(Serde(context)) {
+            Ok(html) => Some(Html::from_html_unchecked(html.into())),
+            Err(e) => {
+                let message = format!("couldn't render template: {e}");
+                // Once, rather than for every page.
+                if self.render_failures.lock().unwrap().insert(message.clone()) {
+                    self.render_diagnostics
+                        .warning(self.dir.join(template.name()), message);
+                }
+                None
+            }
+        }
+    }
+
+    /// Problems rendering templates since this was last called.
+    pub fn take_render_diagnostics(&self) -> Diagnostics {
+        self.render_diagnostics.take()
+    }
+}
+
+/// Data available to the header, sidebar, and footer templates.
+#[derive(Serialize)]
+pub struct ThemeContext<'a> {
+    pub gallery: ThemeGallery<'a>,
+    pub title: &'a str,
+    pub description: Option<&'a str>,
+    pub canonical: &'a str,
+    pub path: String,
+    pub pages: Vec<ThemeLink>,
+    pub breadcrumbs: Vec<ThemeLink>,
+    /// Link to the archive, if there are any photos.
+    pub archive: Option<String>,
+    /// Link to the tag index, if any photos have tags.
+    pub tags: Option<String>,
+    pub relative: Option<&'a RelativeNavigation>,
+    /// Page-specific sidebar panels, as HTML.
+    pub sidebar: Value,
+}
+
+/// Data available to the item template.
+#[derive(Serialize)]
+pub struct ItemThemeContext<'a> {
+    pub gallery: ThemeGallery<'a>,
+    pub item: ThemeItem<'a>,
+}
+
+#[derive(Serialize)]
+pub struct ThemeGallery<'a> {
+    pub title: &'a str,
+    pub description: Option<&'a str>,
+    pub author: Option<&'a str>,
+    pub author_url: Option<&'a str>,
+    pub root_url: Option<&'a str>,
+    pub license_url: Option<&'a str>,
+}
+
+impl<'a> ThemeGallery<'a> {
+    pub fn new(config: &'a GalleryConfig) -> Self {
+        Self {
+            title: &config.title,
+            description: config.description.as_deref(),
+            author: config.author.as_deref(),
+            author_url: config.author_url.as_deref(),
+            root_url: config.root_url.as_deref(),
+            license_url: config.license_url.as_deref(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct ThemeLink {
+    pub name: String,
+    /// `None` for the current page.
+    pub href: Option<String>,
+}
+
+/// A photo, category, or archive period, as shown in a list of items.
+#[derive(Serialize)]
+pub struct ThemeItem<'a> {
+    /// `"photo"`, `"category"`, `"period"` (in the archive), or `"tag"`.
+    pub kind: &'static str,
+    pub name: &'a str,
+    pub href: String,
+    pub thumbnail: String,
+    pub alt: String,
+    pub description: Option<&'a str>,
+    /// Range of dates of a category's photos, or the date of a photo.
+    pub first_date: Option<String>,
+    pub last_date: Option<String>,
+    /// Number of photos in an archive period or with a tag.
+    pub count: Option<usize>,
+    /// An unpublished photo, only shown by `serve`.
+    pub draft: bool,
+    /// JSON-LD to include, as HTML.
+    pub structured_data: Value,
+}
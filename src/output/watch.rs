@@ -0,0 +1,168 @@
+use crate::{
+    gallery::{sidecar_paths, GalleryConfig},
+    output::THEME_DIR,
+};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{absolute, Path, PathBuf},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, SystemTime},
+};
+use wax::{Glob, Pattern};
+
+/// Modification times and sizes, by path.
+type Sources = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Reports which of the gallery's source files change, using file system
+/// notifications where available and polling otherwise.
+pub struct SourceWatcher {
+    /// Absolute.
+    root: PathBuf,
+    glob: Glob<'static>,
+    /// Absolute output and cache directories.
+    ignored: [PathBuf; 2],
+    config_path: PathBuf,
+    notifications: Option<(RecommendedWatcher, Receiver<notify::Result<notify::Event>>)>,
+    /// Only kept when polling.
+    sources: Sources,
+}
+
+impl SourceWatcher {
+    /// Time to wait for further changes, like when saving several files.
+    const DEBOUNCE: Duration = Duration::from_millis(100);
+    const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// `None` if the input glob is invalid.
+    pub fn new(config: &GalleryConfig) -> Option<Self> {
+        let (root, glob) = Glob::new(&config.input_glob())
+            .ok()?
+            .into_owned()
+            .partition();
+        let absolute = |path: &Path| absolute(path).unwrap_or_else(|_| path.to_owned());
+        let mut ret = Self {
+            root: absolute(&root),
+            glob,
+            ignored: [
+                absolute(Path::new(&config.output)),
+                absolute(Path::new(&config.cache)),
+            ],
+            config_path: absolute(Path::new(GalleryConfig::PATH)),
+            notifications: None,
+            sources: Sources::new(),
+        };
+        ret.notifications = ret.notify().ok();
+        if ret.notifications.is_none() {
+            ret.sources = ret.sources();
+        }
+        Some(ret)
+    }
+
+    fn notify(
+        &self,
+    ) -> notify::Result<(RecommendedWatcher, Receiver<notify::Result<notify::Event>>)> {
+        let (sender, receiver) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&self.root, RecursiveMode::Recursive)?;
+        if let Some(parent) = self.config_path.parent() {
+            if !parent.starts_with(&self.root) {
+                watcher.watch(parent, RecursiveMode::NonRecursive)?;
+            }
+        }
+        Ok((watcher, receiver))
+    }
+
+    /// Whether the gallery may be loaded differently if `path` changes.
+    fn is_source(&self, path: &Path) -> bool {
+        if *path == self.config_path {
+            return true;
+        }
+        if self.ignored.iter().any(|ignored| path.starts_with(ignored)) {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.root) else {
+            return false;
+        };
+        relative.starts_with(THEME_DIR)
+            || self.glob.is_match(relative)
+            || path.extension().is_some_and(|extension| extension == "xmp")
+    }
+
+    /// Blocks until some source files change, and returns their absolute
+    /// paths.
+    pub fn wait(&mut self) -> HashSet<PathBuf> {
+        let mut changed = HashSet::new();
+        while let Some((_, receiver)) = &self.notifications {
+            let timeout = if changed.is_empty() {
+                Duration::MAX
+            } else {
+                Self::DEBOUNCE
+            };
+            match receiver.recv_timeout(timeout) {
+                Ok(Ok(event)) => {
+                    if !matches!(event.kind, EventKind::Access(_)) {
+                        changed.extend(event.paths.into_iter().filter(|path| self.is_source(path)));
+                    }
+                }
+                // Events may have been missed, so anything may have changed.
+                Ok(Err(_)) | Err(RecvTimeoutError::Disconnected) => {
+                    self.notifications = None;
+                    self.sources = self.sources();
+                    changed.insert(self.config_path.clone());
+                }
+                Err(RecvTimeoutError::Timeout) => return changed,
+            }
+        }
+        if !changed.is_empty() {
+            return changed;
+        }
+        loop {
+            std::thread::sleep(Self::POLL_INTERVAL);
+            let sources = self.sources();
+            let changed = sources
+                .iter()
+                .filter(|(path, metadata)| self.sources.get(*path) != Some(metadata))
+                .chain(
+                    self.sources
+                        .iter()
+                        .filter(|(path, _)| !sources.contains_key(*path)),
+                )
+                .map(|(path, _)| path.clone())
+                .collect::<HashSet<_>>();
+            self.sources = sources;
+            if !changed.is_empty() {
+                return changed;
+            }
+        }
+    }
+
+    /// Of the files that make up the gallery.
+    fn sources(&self) -> Sources {
+        let mut ret = Sources::new();
+        let mut record = |path: PathBuf| {
+            if let Ok(metadata) = std::fs::metadata(&path) {
+                ret.insert(path, (metadata.modified().ok(), metadata.len()));
+            }
+        };
+        record(self.config_path.clone());
+        if let Ok(entries) = self.glob.walk(&self.root).not(
+            self.ignored
+                .iter()
+                .filter_map(|path| path.strip_prefix(&self.root).ok()?.to_str()),
+        ) {
+            for entry in entries.flatten() {
+                record(entry.path().to_owned());
+                for sidecar in sidecar_paths(entry.path()) {
+                    record(sidecar);
+                }
+            }
+        }
+        // Not necessarily matched by the input glob.
+        if let Ok(entries) = std::fs::read_dir(self.root.join(THEME_DIR)) {
+            for entry in entries.flatten() {
+                record(entry.path());
+            }
+        }
+        ret
+    }
+}
@@ -36,7 +36,7 @@ pub fn recursively_remove_empty_dirs_of_contents<P: AsRef<Path>>(path: P) -> io:
             }
         }
         if !keep {
-            fs::remove_dir(&path).unwrap();
+            fs::remove_dir(&path)?;
         }
         Ok(keep)
     }
@@ -52,6 +52,36 @@ pub fn recursively_remove_empty_dirs_of_contents<P: AsRef<Path>>(path: P) -> io:
     Ok(())
 }
 
+/// Removes the directories containing `path` while they're empty, up to but
+/// not including `root`.
+pub fn remove_empty_ancestors<P: AsRef<Path>, R: AsRef<Path>>(path: P, root: R) -> io::Result<()> {
+    let root = root.as_ref();
+    for dir in path.as_ref().ancestors().skip(1) {
+        if dir == root || !dir.starts_with(root) {
+            break;
+        }
+        match fs::remove_dir(dir) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) if e.kind() == io::ErrorKind::DirectoryNotEmpty => break,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// Like [`fs::rename`], but also works across file systems, by copying and
+/// then removing the original.
+pub fn move_file<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    match fs::rename(&from, &to) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            fs::copy(&from, to)?;
+            fs::remove_file(from)
+        }
+        result => result,
+    }
+}
+
 // TODO: wait for `slice_concat_ext` stabilization.
 pub fn join<T: Clone>(slice: &[T], sep: &T) -> Vec<T> {
     let mut iter = slice.iter();
@@ -86,6 +116,21 @@ pub fn is_camera_file_name(name: &str) -> bool {
     name.starts_with("IMG") || name.starts_with("DSC")
 }
 
+/// In bytes, if known (only on Linux).
+pub fn available_memory() -> Option<usize> {
+    let meminfo = fs::read_to_string("/proc/meminfo").ok()?;
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kilobytes = line
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse::<usize>()
+        .ok()?;
+    Some(kilobytes << 10)
+}
+
 pub fn progress_bar(name: &str, count: usize, start: Instant) -> ProgressBar {
     ProgressBar::new(count as u64)
         .with_message(name.to_owned())
@@ -95,4 +140,4 @@ pub fn progress_bar(name: &str, count: usize, start: Instant) -> ProgressBar {
                 .unwrap(),
         )
         .with_elapsed(start.elapsed())
-}
\ No newline at end of file
+}